@@ -0,0 +1,73 @@
+use std::{hint::black_box, iter, time::Instant};
+
+use dispatch_graphql::{utf16, NextPayload};
+use serde_json::{json, Value};
+
+/// Compare the UTF-16 serializer with the previous path through a UTF-8 `String`, on the kind of
+/// `ItemsReloaded` payload which a large folder produces. Run with `cargo bench`.
+fn main() {
+    for count in [100, 1_000, 10_000] {
+        let payload = NextPayload {
+            next: serde_json::value::to_raw_value(&items_reloaded(count)).unwrap_or_default(),
+            subscription: 1,
+            sequence: 1,
+            extensions: None,
+        };
+        let bytes = serde_json::to_string(&payload).unwrap_or_default().len();
+        let iterations = (2_000_000 / count).max(10);
+
+        let via_string = measure(iterations, || {
+            let payload = serde_json::to_string(&payload).unwrap_or_default();
+            payload
+                .encode_utf16()
+                .chain(iter::once(0_u16))
+                .collect::<Vec<_>>()
+        });
+        let direct = measure(iterations, || utf16::to_utf16(&payload).unwrap_or_default());
+
+        println!(
+            "{count:>6} items ({:>9} bytes): via String {:>10.1} µs, direct UTF-16 {:>10.1} µs ({:.2}x)",
+            bytes,
+            via_string,
+            direct,
+            via_string / direct
+        );
+    }
+}
+
+/// The average time for each call in microseconds.
+fn measure<T>(iterations: usize, mut serialize: impl FnMut() -> T) -> f64 {
+    black_box(serialize());
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(serialize());
+    }
+    start.elapsed().as_secs_f64() * 1_000_000.0 / iterations as f64
+}
+
+fn items_reloaded(count: usize) -> Value {
+    let items: Vec<_> = (0..count)
+        .map(|index| {
+            json!({
+                "id": format!("AAMkAGI2TG93AAA{index:08}"),
+                "subject": format!("Re: Quarterly planning — item {index} ✉️"),
+                "sender": "Sam Example <sam@example.com>",
+                "to": "Team <team@example.com>",
+                "cc": null,
+                "preview": "Thanks for the update, I've attached the latest numbers and notes from the review.",
+                "read": index % 3 == 0,
+                "received": "2023-09-14T17:32:05.000Z",
+                "modified": "2023-09-14T17:32:05.000Z",
+            })
+        })
+        .collect();
+
+    json!({
+        "data": {
+            "items": [{
+                "__typename": "ItemsReloaded",
+                "reloaded": items,
+            }]
+        }
+    })
+}
@@ -1,7 +1,8 @@
 use std::{
     env,
-    fs::File,
+    fs::{self, File},
     io::{self, Write},
+    mem,
     path::*,
     process::Command,
 };
@@ -9,6 +10,7 @@ use std::{
 fn main() -> io::Result<()> {
     println!("cargo:rerun-if-changed=src/lib.rs");
     println!("cargo:rerun-if-changed=src/GraphQLService.idl");
+    println!("cargo:rerun-if-changed=src/dispatch-graphql.js");
 
     let mut idl_path = PathBuf::from("src");
     idl_path.push("GraphQLService.idl");
@@ -17,7 +19,18 @@ fn main() -> io::Result<()> {
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
     let out_dir = out_dir.strip_prefix(manifest_dir).unwrap();
-    let mut tlb_path = PathBuf::from(&out_dir);
+
+    // The type library is only needed for the COM interface, which is only built on Windows.
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
+        link_type_lib(idl_path, out_dir)?;
+    }
+
+    write_typescript_interface(idl_path, out_dir)?;
+    write_client_interface("src/dispatch-graphql.js", out_dir)
+}
+
+fn link_type_lib(idl_path: &str, out_dir: &Path) -> io::Result<()> {
+    let mut tlb_path = PathBuf::from(out_dir);
 
     tlb_path.push("GraphQLService.tlb");
     let tlb_path = tlb_path.as_path().to_str().unwrap();
@@ -25,7 +38,7 @@ fn main() -> io::Result<()> {
         .args(["/nologo", "/tlb", tlb_path, idl_path])
         .output()?;
 
-    let mut rc_path = PathBuf::from(&out_dir);
+    let mut rc_path = PathBuf::from(out_dir);
     rc_path.push("GraphQLService.rc");
     let rc_path = rc_path.as_path().to_str().unwrap();
     let mut rc_file = File::create(rc_path)?;
@@ -36,7 +49,7 @@ fn main() -> io::Result<()> {
 
     let _ = Command::new("rc").args(["/nologo", rc_path]).output()?;
 
-    let mut res_path = PathBuf::from(&out_dir);
+    let mut res_path = PathBuf::from(out_dir);
     res_path.push("GraphQLService.res");
     let res_path = res_path.as_path().to_str().unwrap();
 
@@ -44,3 +57,182 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+/// Generate the TypeScript interface for the host object from the methods declared in the IDL, so
+/// the typings in `src/typings.rs` can't drift from `IGraphQLService`.
+fn write_typescript_interface(idl_path: &str, out_dir: &Path) -> io::Result<()> {
+    let idl = fs::read_to_string(idl_path)?;
+    let mut methods = Vec::new();
+    let mut overrides = Vec::new();
+    for line in idl.lines().map(str::trim) {
+        if let Some(annotation) = line.strip_prefix("// @ts ") {
+            let (name, ts_type) = annotation
+                .split_once(':')
+                .ok_or_else(|| invalid_data(format!("expected `// @ts <name>: <type>`: {line}")))?;
+            overrides.push((name.trim(), ts_type.trim()));
+        } else if line.starts_with("[id(") {
+            let method = typescript_method(line, &overrides)
+                .ok_or_else(|| invalid_data(format!("unsupported IDL method: {line}")))?;
+            methods.push(method);
+            overrides.clear();
+        }
+    }
+
+    let mut ts_path = PathBuf::from(out_dir);
+    ts_path.push("GraphQLService.d.ts");
+    let mut ts_file = File::create(ts_path)?;
+    writeln!(ts_file, "export interface GraphQLHostObject {{")?;
+    for method in methods {
+        writeln!(ts_file, "  {method};")?;
+    }
+    writeln!(ts_file, "}}")
+}
+
+/// Translate an IDL method to TypeScript. Each of the `overrides` replaces the type of the
+/// parameter with that name, or the result if it is named `return`.
+fn typescript_method(line: &str, overrides: &[(&str, &str)]) -> Option<String> {
+    let (_, signature) = line.split_once("HRESULT ")?;
+    let (name, params) = signature.split_once('(')?;
+    let params = &params[..params.rfind(')')?];
+    let override_for = |param_name: &str| {
+        overrides
+            .iter()
+            .find(|(name, _)| *name == param_name)
+            .map(|(_, ts_type)| *ts_type)
+    };
+
+    let mut args = Vec::new();
+    let mut result = override_for("return").unwrap_or("void");
+    let mut names = vec!["return"];
+    for param in split_params(params)
+        .into_iter()
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+    {
+        let (attributes, declaration) = param.strip_prefix('[')?.split_once(']')?;
+        let (idl_type, param_name) = declaration.trim().rsplit_once(' ')?;
+        names.push(param_name);
+        let ts_type = match idl_type.trim() {
+            "BSTR" | "BSTR*" => "string",
+            "INT" | "INT*" | "LONG" | "LONG*" => "number",
+            "VARIANT_BOOL" | "VARIANT_BOOL*" => "boolean",
+            "IDispatch*" => "(payload: string) => void",
+            "IDispatch**" => "GraphQLHostObject",
+            _ => "unknown",
+        };
+
+        if attributes.contains("retval") {
+            result = override_for("return").unwrap_or(ts_type);
+        } else {
+            let ts_type = override_for(param_name).unwrap_or(ts_type);
+            args.push(format!("{param_name}: {ts_type}"));
+        }
+    }
+
+    // An override for a parameter which doesn't exist is most likely a typo.
+    if overrides.iter().any(|(name, _)| !names.contains(name)) {
+        return None;
+    }
+
+    Some(format!("{name}({}): Promise<{result}>", args.join(", ")))
+}
+
+/// Split a parameter list on the commas between parameters, but not the ones between attributes
+/// such as `[out, retval]`.
+fn split_params(params: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in params.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(&params[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    split.push(&params[start..]);
+    split
+}
+
+/// Generate the `GraphQLClient` interface from the `@signature` of each method which
+/// `createClient` returns in the client script, so its typings can't drift from the script either.
+/// A method without a signature fails the build.
+fn write_client_interface(script_path: &str, out_dir: &Path) -> io::Result<()> {
+    let script = fs::read_to_string(script_path)?;
+    let mut methods = Vec::new();
+    let mut comment = Vec::new();
+    let mut in_comment = false;
+    for line in script.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("/**") {
+            in_comment = true;
+            comment.clear();
+        }
+        if in_comment {
+            let text = trimmed
+                .trim_start_matches("/**")
+                .trim_end_matches("*/")
+                .trim_start_matches('*')
+                .trim();
+            if !text.is_empty() {
+                comment.push(text);
+            }
+            in_comment = !trimmed.ends_with("*/");
+            continue;
+        }
+
+        // The methods of the returned object are the only declarations indented by 4 spaces.
+        let declaration = line.strip_prefix("    ").unwrap_or_default();
+        let declaration = declaration.strip_prefix("async ").unwrap_or(declaration);
+        let name = declaration
+            .split_once('(')
+            .map(|(name, _)| name)
+            .unwrap_or_default();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            if !trimmed.is_empty() {
+                comment.clear();
+            }
+            continue;
+        }
+
+        let comment = mem::take(&mut comment);
+        let signature = comment
+            .iter()
+            .find_map(|line| line.strip_prefix("@signature "))
+            .ok_or_else(|| invalid_data(format!("client method {name} has no @signature")))?;
+        if !signature.starts_with(name)
+            || !matches!(signature[name.len()..].chars().next(), Some('(' | '<'))
+        {
+            return Err(invalid_data(format!(
+                "the @signature of client method {name} is for another method: {signature}"
+            )));
+        }
+        let description = comment
+            .iter()
+            .take_while(|line| !line.starts_with('@'))
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        methods.push((description, signature.to_string()));
+    }
+
+    let mut ts_path = PathBuf::from(out_dir);
+    ts_path.push("GraphQLClient.d.ts");
+    let mut ts_file = File::create(ts_path)?;
+    writeln!(ts_file, "export interface GraphQLClient {{")?;
+    for (description, signature) in methods {
+        if !description.is_empty() {
+            writeln!(ts_file, "  /** {description} */")?;
+        }
+        writeln!(ts_file, "  {signature};")?;
+    }
+    writeln!(ts_file, "}}")
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -35,7 +35,7 @@ window.chrome.webview.hostObjects.graphql.fetchQuery(`
     };
   })
   .then((variables) => {
-    window.chrome.webview.hostObjects.graphql.fetchQuery(`
+    window.chrome.webview.hostObjects.graphql.fetchQueryWithVariables(`
       subscription InboxItemsSubscription($storeId: ID!, $objectId: ID!) {
         items(folderId: {storeId: $storeId, objectId: $objectId}) @take(count: 10) {
           ... on ItemAdded {
@@ -73,7 +73,7 @@ window.chrome.webview.hostObjects.graphql.fetchQuery(`
         cc
         preview
       }
-    `, "", JSON.stringify(variables), (payload) => {
+    `, "", variables, (payload) => {
       let next = JSON.parse(payload);
       subscriptionPayloads.push(next);
       console.log(next);
@@ -3,7 +3,7 @@
 extern crate webview2_com;
 extern crate windows;
 
-use std::{ffi::c_void, fmt, mem, ptr, rc::Rc, sync::mpsc};
+use std::{env, ffi::c_void, fmt, mem, ptr, rc::Rc, sync::mpsc};
 
 use windows::{
     core::*,
@@ -51,11 +51,18 @@ fn main() -> Result<()> {
         }
     }
 
-    // Configure the target URL and add an init script to output the default store and inbox IDs.
-    webview
-        .set_title("webview2-com example (crates/webview2-com/examples)")?
-        .init(include_str!("sample.js"))?
-        .navigate("https://github.com/wravery/dispatch-graphql")?;
+    if env::args().any(|arg| arg == "--explorer") {
+        // Show the embedded explorer page instead of running the sample script.
+        webview
+            .set_title("GraphQL Explorer")?
+            .navigate_to_string(include_str!("../src/explorer.html"))?;
+    } else {
+        // Configure the target URL and add an init script to output the default store and inbox IDs.
+        webview
+            .set_title("webview2-com example (crates/webview2-com/examples)")?
+            .init(include_str!("sample.js"))?
+            .navigate("https://github.com/wravery/dispatch-graphql")?;
+    }
 
     // Off we go....
     webview.run()
@@ -173,6 +180,7 @@ pub struct WebView {
     frame: Option<FrameWindow>,
     parent: HWND,
     url: String,
+    html: String,
 }
 
 impl Drop for WebViewController {
@@ -272,6 +280,7 @@ impl WebView {
             frame,
             parent,
             url: String::new(),
+            html: String::new(),
         };
 
         if webview.frame.is_some() {
@@ -284,9 +293,10 @@ impl WebView {
     pub fn run(self) -> Result<()> {
         let webview = self.webview.as_ref();
         let url = self.url.clone();
+        let html = self.html.clone();
         let (tx, rx) = mpsc::channel();
 
-        if !url.is_empty() {
+        if !url.is_empty() || !html.is_empty() {
             let handler =
                 NavigationCompletedEventHandler::create(Box::new(move |_sender, _args| {
                     tx.send(()).expect("send over mpsc channel");
@@ -295,8 +305,13 @@ impl WebView {
             let mut token = EventRegistrationToken::default();
             unsafe {
                 webview.add_NavigationCompleted(&handler, &mut token)?;
-                let url = CoTaskMemPWSTR::from(url.as_str());
-                webview.Navigate(*url.as_ref().as_pcwstr())?;
+                if html.is_empty() {
+                    let url = CoTaskMemPWSTR::from(url.as_str());
+                    webview.Navigate(*url.as_ref().as_pcwstr())?;
+                } else {
+                    let html = CoTaskMemPWSTR::from(html.as_str());
+                    webview.NavigateToString(*html.as_ref().as_pcwstr())?;
+                }
                 let result = webview2_com::wait_with_pump(rx);
                 webview.remove_NavigationCompleted(token)?;
                 result?;
@@ -397,6 +412,13 @@ impl WebView {
 
     pub fn navigate(&mut self, url: &str) -> Result<&mut Self> {
         self.url = url.into();
+        self.html.clear();
+        Ok(self)
+    }
+
+    pub fn navigate_to_string(&mut self, html: &str) -> Result<&mut Self> {
+        self.html = html.into();
+        self.url.clear();
         Ok(self)
     }
 
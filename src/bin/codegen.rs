@@ -0,0 +1,115 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use dispatch_graphql::{codegen::Codegen, executor, schema::Schema, SchemaFormat, Service};
+
+const USAGE: &str = "usage: codegen <documents directory> [--schema <introspection.json> | --fake <responses.json>] [--output <file.ts>]";
+
+/// Generate TypeScript types for every operation in the `.graphql` documents under a directory.
+/// The schema is loaded from the executor through introspection unless `--schema` is specified.
+fn main() -> ExitCode {
+    let mut documents = None;
+    let mut schema_path = None;
+    let mut output_path = None;
+    let mut fake_responses = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--schema" => schema_path = args.next().map(PathBuf::from),
+            "--output" => output_path = args.next().map(PathBuf::from),
+            "--fake" => fake_responses = args.next().map(PathBuf::from),
+            _ if documents.is_none() => documents = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(documents) = documents else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let introspection = match schema_path {
+        Some(schema_path) => fs::read_to_string(schema_path).map_err(|error| error.to_string()),
+        None => executor::from_args(fake_responses.as_deref(), None)
+            .map_err(|error| error.to_string())
+            .and_then(|executor| {
+                Service::new(executor)
+                    .get_schema(SchemaFormat::Introspection)
+                    .map_err(|error| error.to_string())
+            }),
+    };
+    let schema = introspection.and_then(|introspection| {
+        serde_json::from_str::<serde_json::Value>(&introspection)
+            .ok()
+            .as_ref()
+            .and_then(Schema::from_introspection)
+            .ok_or_else(|| String::from("invalid introspection results"))
+    });
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(error) => {
+            eprintln!("error loading schema: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut paths = Vec::new();
+    if let Err(error) = find_documents(&documents, &mut paths) {
+        eprintln!("error reading {}: {error}", documents.display());
+        return ExitCode::FAILURE;
+    }
+    paths.sort();
+
+    let mut codegen = Codegen::new(&schema);
+    for path in paths {
+        match fs::read_to_string(&path) {
+            Ok(document) => codegen.add_document(&path.display().to_string(), &document),
+            Err(error) => {
+                eprintln!("error reading {}: {error}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let output = match codegen.finish() {
+        Ok(output) => output,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{error}");
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match output_path {
+        Some(output_path) => {
+            if let Err(error) = fs::write(&output_path, output) {
+                eprintln!("error writing {}: {error}", output_path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{output}"),
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn find_documents(directory: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_documents(&path, paths)?;
+        } else if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("graphql" | "gql")
+        ) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+use dispatch_graphql::{
+    encoding, executor, is_subscription, Encoding, NextPayload, NextReceiver, QueryOptions,
+    QueryResult, Recorder, ResultPayload, Service,
+};
+use serde_json::value::RawValue;
+
+const USAGE: &str = "usage: dispatch-graphql-cli [<query file> | -] [--operation-name <name>] [--variables <json> | --variables @<file>] [--pretty] [--encoding json|cbor|msgpack] [--timeout <ms>] [--tracing] [--fake <responses.json> | --replay <recording>] [--record <recording>]";
+
+/// How often to check for Ctrl-C while waiting for the next result.
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+
+/// Run a query or subscription against the same executor as the `graphql` host object. The query
+/// is read from a file, or from stdin if the file name is `-` or missing. Query results are
+/// printed as JSON, and subscription results are streamed as one `next` payload per line (NDJSON)
+/// until the subscription completes or the process is interrupted with Ctrl-C, which cancels it. With a binary
+/// `--encoding`, each payload is written to stdout as a length-prefixed frame instead. With
+/// `--tracing`, query results are printed as the whole payload, so every payload has the timing of
+/// the operation in its `extensions`. `--record` writes the requests and results to a recording,
+/// and `--replay` serves the results from one instead of running against MAPI.
+fn main() -> ExitCode {
+    let mut query_path = None;
+    let mut operation_name = String::new();
+    let mut variables = String::new();
+    let mut pretty = false;
+    let mut encoding = Encoding::Json;
+    let mut timeout = None;
+    let mut tracing = false;
+    let mut fake_responses = None;
+    let mut replay = None;
+    let mut record = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--operation-name" => operation_name = args.next().unwrap_or_default(),
+            "--variables" => variables = args.next().unwrap_or_default(),
+            "--pretty" => pretty = true,
+            "--encoding" => match args.next().unwrap_or_default().parse() {
+                Ok(value) => encoding = value,
+                Err(()) => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--timeout" => match args.next().unwrap_or_default().parse() {
+                Ok(milliseconds) => timeout = Some(Duration::from_millis(milliseconds)),
+                Err(_) => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            "--tracing" => tracing = true,
+            "--fake" => fake_responses = args.next().map(PathBuf::from),
+            "--replay" => replay = args.next().map(PathBuf::from),
+            "--record" => record = args.next().map(PathBuf::from),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ if query_path.is_none() => query_path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let query = match query_path.as_deref() {
+        None | Some("-") => {
+            let mut query = String::new();
+            io::stdin().read_to_string(&mut query).map(|_| query)
+        }
+        Some(path) => fs::read_to_string(path),
+    };
+    let query = match query {
+        Ok(query) => query,
+        Err(error) => {
+            eprintln!("error reading query: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(path) = variables.strip_prefix('@') {
+        variables = match fs::read_to_string(path) {
+            Ok(variables) => variables,
+            Err(error) => {
+                eprintln!("error reading variables: {error}");
+                return ExitCode::FAILURE;
+            }
+        };
+    }
+    if !variables.trim().is_empty()
+        && !serde_json::from_str::<serde_json::Value>(&variables)
+            .is_ok_and(|variables| variables.is_object())
+    {
+        eprintln!("error parsing variables: expected a JSON object");
+        return ExitCode::FAILURE;
+    }
+
+    #[cfg(feature = "trace-log")]
+    if let Err(error) = dispatch_graphql::trace::init_from_env() {
+        eprintln!("error opening log: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut service = match executor::from_args(fake_responses.as_deref(), replay.as_deref()) {
+        Ok(executor) => Service::new(executor),
+        Err(error) => {
+            eprintln!("error creating executor: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = record {
+        match Recorder::create(&path) {
+            Ok(recorder) => service = service.with_recorder(Arc::new(recorder)),
+            Err(error) => {
+                eprintln!("error creating recording: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_interrupted = interrupted.clone();
+    if let Err(error) =
+        ctrlc::set_handler(move || handler_interrupted.store(true, Ordering::SeqCst))
+    {
+        eprintln!("error installing the Ctrl-C handler: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    let options = QueryOptions { timeout, tracing };
+    match service.start_query_with_options(&query, &operation_name, &variables, options) {
+        Ok(QueryResult::Results(payload)) => print_results(payload, tracing, pretty, encoding),
+        Ok(QueryResult::Pending {
+            key,
+            next: next_receiver,
+        }) if !is_subscription(&query, &operation_name) => {
+            // A query which did not complete right away, e.g. with `--timeout`, still has exactly
+            // one result.
+            let exit_code = match recv(&next_receiver, &interrupted) {
+                Some(next) => match RawValue::from_string(next) {
+                    Ok(results) => {
+                        let payload = ResultPayload {
+                            results,
+                            extensions: next_receiver.extensions(),
+                        };
+                        print_results(payload, tracing, pretty, encoding)
+                    }
+                    Err(error) => {
+                        eprintln!("error parsing results: {error}");
+                        ExitCode::FAILURE
+                    }
+                },
+                None => ExitCode::FAILURE,
+            };
+            let _ = service.cancel_subscription(key);
+            exit_code
+        }
+        Ok(QueryResult::Pending {
+            key,
+            next: next_receiver,
+        }) => {
+            let mut stdout = io::stdout().lock();
+            let mut sequence = 0;
+            while let Some(next) = recv(&next_receiver, &interrupted) {
+                let Ok(next) = RawValue::from_string(next) else {
+                    eprintln!("error parsing subscription result");
+                    continue;
+                };
+                sequence += 1;
+                let next = NextPayload {
+                    next,
+                    subscription: key,
+                    sequence,
+                    extensions: next_receiver.extensions(),
+                };
+                let written = if encoding == Encoding::Json {
+                    let line = serde_json::to_string(&next).unwrap_or_default();
+                    writeln!(stdout, "{line}")
+                } else {
+                    write_encoded(&mut stdout, encoding, &next)
+                };
+                if written.and_then(|()| stdout.flush()).is_err() {
+                    break;
+                }
+            }
+            let _ = service.cancel_subscription(key);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("error running query: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Wait for the next result, or return `None` once the operation has ended or Ctrl-C was pressed.
+fn recv(next_receiver: &NextReceiver, interrupted: &AtomicBool) -> Option<String> {
+    while !interrupted.load(Ordering::SeqCst) {
+        match next_receiver.recv_timeout(INTERRUPT_POLL) {
+            Ok(next) => return Some(next),
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+    None
+}
+
+/// Print the results of a query or mutation. With `--tracing`, print the whole payload so the
+/// extensions are included.
+fn print_results(
+    payload: ResultPayload,
+    tracing: bool,
+    pretty: bool,
+    encoding: Encoding,
+) -> ExitCode {
+    let results = if tracing {
+        serde_json::value::to_raw_value(&payload).unwrap_or(payload.results)
+    } else {
+        payload.results
+    };
+    if encoding != Encoding::Json {
+        return match write_encoded(&mut io::stdout().lock(), encoding, &results) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("error writing results: {error}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if pretty {
+        let results = serde_json::from_str::<serde_json::Value>(results.get())
+            .and_then(|results| serde_json::to_string_pretty(&results));
+        println!("{}", results.unwrap_or_default());
+    } else {
+        println!("{}", results.get());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Write a payload as one frame in a binary encoding.
+fn write_encoded<T: serde::Serialize + ?Sized>(
+    writer: &mut impl Write,
+    encoding: Encoding,
+    payload: &T,
+) -> io::Result<()> {
+    let frame = encoding
+        .encode(payload)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    encoding::write_frame(writer, &frame)?;
+    writer.flush()
+}
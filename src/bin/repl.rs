@@ -0,0 +1,714 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Context, Editor, ExternalPrinter, Helper,
+};
+
+use dispatch_graphql::{
+    executor, metrics,
+    schema::{Schema, TypeKind},
+    slow_log, Error, QueryResult, Recorder, ResultPayload, SchemaFormat, Service,
+};
+
+const USAGE: &str =
+    "usage: repl [--fake <responses.json> | --replay <recording>] [--record <recording>]";
+
+const HELP: &str = r#"Enter a GraphQL document to run it. Multi-line input continues until the braces are balanced.
+
+Commands:
+  :vars <json>       Set the variables for the following operations (no argument clears them)
+  :op <name>         Set the operation name for the following operations (no argument clears it)
+  :schema            Print the schema as SDL
+  :subs              List the active subscriptions
+  :cancel <key|all>  Cancel a subscription, or all of them
+  :metrics           Print the metrics in the Prometheus text format
+  :slow              List the operations which were slow, or whose results piled up
+  :help              Show this help
+  :quit              Exit
+"#;
+
+const COMMANDS: [&str; 9] = [
+    ":vars", ":op", ":schema", ":subs", ":cancel", ":metrics", ":slow", ":help", ":quit",
+];
+
+/// An interactive shell on top of the same service core as the `graphql` host object, with
+/// schema-aware completion. Subscription results are printed as they arrive until cancelled.
+fn main() -> ExitCode {
+    let mut fake_responses = None;
+    let mut replay = None;
+    let mut record = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fake" => fake_responses = args.next().map(PathBuf::from),
+            "--replay" => replay = args.next().map(PathBuf::from),
+            "--record" => record = args.next().map(PathBuf::from),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    #[cfg(feature = "trace-log")]
+    if let Err(error) = dispatch_graphql::trace::init_from_env() {
+        eprintln!("error opening log: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut service = match executor::from_args(fake_responses.as_deref(), replay.as_deref()) {
+        Ok(executor) => Service::new(executor),
+        Err(error) => {
+            eprintln!("error creating executor: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = record {
+        match Recorder::create(&path) {
+            Ok(recorder) => service = service.with_recorder(Arc::new(recorder)),
+            Err(error) => {
+                eprintln!("error creating recording: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let schema = service
+        .get_schema(SchemaFormat::Introspection)
+        .ok()
+        .and_then(|introspection| serde_json::from_str(&introspection).ok())
+        .and_then(|introspection| Schema::from_introspection(&introspection));
+    if schema.is_none() {
+        eprintln!("warning: could not load the schema, completion is limited to commands");
+    }
+
+    match run(&service, schema) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(service: &Service, schema: Option<Schema>) -> rustyline::Result<()> {
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper { schema }));
+
+    let history_path = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".dispatch_graphql_history"));
+    if let Some(history_path) = history_path.as_ref() {
+        let _ = editor.load_history(history_path);
+    }
+
+    // Print subscription results above the prompt, unless the input is not a terminal.
+    let printer: Option<Arc<Mutex<dyn ExternalPrinter + Send>>> = editor
+        .create_external_printer()
+        .ok()
+        .map(|printer| Arc::new(Mutex::new(printer)) as _);
+    let mut operation_name = String::new();
+    let mut variables = String::new();
+    let mut subscriptions: BTreeMap<i32, String> = BTreeMap::new();
+
+    println!("Type :help for commands.");
+    loop {
+        let input = match editor.readline("graphql> ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input);
+
+        // Forget about subscriptions which completed on their own.
+        let active = service.active_subscriptions();
+        subscriptions.retain(|key, _| active.contains(key));
+
+        let (command, argument) = input
+            .split_once(char::is_whitespace)
+            .map_or((input, ""), |(command, argument)| {
+                (command, argument.trim())
+            });
+        match command {
+            ":quit" | ":q" => break,
+            ":help" => print!("{HELP}"),
+            ":vars" => {
+                if argument.is_empty()
+                    || serde_json::from_str::<serde_json::Value>(argument).is_ok()
+                {
+                    variables = argument.to_string();
+                } else {
+                    eprintln!("error: variables must be JSON");
+                }
+            }
+            ":op" => operation_name = argument.to_string(),
+            ":schema" => match service.get_schema(SchemaFormat::Sdl) {
+                Ok(sdl) => print!("{sdl}"),
+                Err(error) => eprintln!("error: {error}"),
+            },
+            ":subs" => match service.list_subscriptions() {
+                Ok(active) if active.is_empty() => println!("No active subscriptions."),
+                Ok(active) => {
+                    for subscription in active {
+                        println!(
+                            "{}\t{}\t{} results\t{}",
+                            subscription.key,
+                            subscription.operation,
+                            subscription.deliveries,
+                            subscription.variables
+                        );
+                    }
+                }
+                Err(error) => eprintln!("error: {error}"),
+            },
+            ":cancel" => {
+                let keys: Vec<i32> = if argument == "all" {
+                    subscriptions.keys().copied().collect()
+                } else {
+                    match argument.parse() {
+                        Ok(key) => vec![key],
+                        Err(_) => {
+                            eprintln!("usage: :cancel <key|all>");
+                            continue;
+                        }
+                    }
+                };
+                for key in keys {
+                    match service.cancel_subscription(key) {
+                        Ok(()) => {
+                            subscriptions.remove(&key);
+                            println!("Cancelled {key}");
+                        }
+                        Err(Error::UnknownSubscription) => {
+                            subscriptions.remove(&key);
+                            eprintln!("No active subscription {key}");
+                        }
+                        Err(error) => eprintln!("error: {error}"),
+                    }
+                }
+            }
+            ":metrics" => print!("{}", metrics::global().to_prometheus()),
+            ":slow" => {
+                let entries = slow_log::global().entries();
+                if entries.is_empty() {
+                    println!("No slow operations.");
+                }
+                for entry in entries {
+                    println!(
+                        "{}\t{}\t{}\t{} ms{}\t{}",
+                        entry.key,
+                        entry.operation,
+                        entry.reason,
+                        entry.duration,
+                        entry
+                            .backlog
+                            .map(|backlog| format!(", {backlog} waiting"))
+                            .unwrap_or_default(),
+                        entry.variables
+                    );
+                }
+            }
+            _ if command.starts_with(':') => eprintln!("Unknown command: {command}"),
+            _ => match service.start_query(input, &operation_name, &variables) {
+                Ok(QueryResult::Results(ResultPayload { results, .. })) => {
+                    println!(
+                        "{}",
+                        serde_json::from_str::<serde_json::Value>(results.get())
+                            .and_then(|results| serde_json::to_string_pretty(&results))
+                            .unwrap_or_else(|_| results.get().to_string())
+                    );
+                }
+                Ok(QueryResult::Pending { key, next }) => {
+                    let name = if operation_name.is_empty() {
+                        executor::first_operation_name(input).unwrap_or_default()
+                    } else {
+                        operation_name.clone()
+                    };
+                    println!("Subscribed {key} {name}");
+                    subscriptions.insert(key, name);
+
+                    let printer = printer.clone();
+                    thread::spawn(move || {
+                        while let Ok(next) = next.recv() {
+                            let next = serde_json::from_str::<serde_json::Value>(&next)
+                                .and_then(|next| serde_json::to_string_pretty(&next))
+                                .unwrap_or(next);
+                            let next = format!("[{key}] {next}");
+                            match printer.as_ref().map(|printer| printer.lock()) {
+                                Some(Ok(mut printer)) => {
+                                    let _ = printer.print(next);
+                                }
+                                _ => println!("{next}"),
+                            }
+                        }
+                    });
+                }
+                Err(error) => eprintln!("error: {error}"),
+            },
+        }
+    }
+
+    for key in subscriptions.keys() {
+        let _ = service.cancel_subscription(*key);
+    }
+    if let Some(history_path) = history_path.as_ref() {
+        let _ = editor.save_history(history_path);
+    }
+
+    Ok(())
+}
+
+struct ReplHelper {
+    schema: Option<Schema>,
+}
+
+impl Helper for ReplHelper {}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.trim_start().starts_with(':') {
+            return Ok(ValidationResult::Valid(None));
+        }
+
+        let scanner = Scanner::new(input);
+        if scanner.depth > 0 || scanner.in_string {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let text = &line[..pos];
+        let start = text
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+            .map_or(0, |index| index + 1);
+        let prefix = &text[start..];
+
+        let candidates: Vec<String> = if text.trim_start().starts_with(':') {
+            COMMANDS.iter().map(|command| command.to_string()).collect()
+        } else if let Some(schema) = self.schema.as_ref() {
+            completions(schema, &text[..start])
+        } else {
+            Vec::new()
+        };
+
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .filter(|candidate| candidate.starts_with(prefix))
+                .map(|candidate| Pair {
+                    display: candidate.clone(),
+                    replacement: candidate,
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Find the field or argument names which could be typed next, based on the document so far.
+fn completions(schema: &Schema, text: &str) -> Vec<String> {
+    let scanner = Scanner::new(text);
+    let mut stack: Vec<Option<String>> = Vec::new();
+    let mut root_type = Some(schema.query_type.clone());
+    let mut type_condition: Option<String> = None;
+    let mut expect_type = false;
+    let mut last_field: Option<(String, String)> = None;
+    let mut arguments: Option<Option<(String, String)>> = None;
+    let mut value_depth = 0;
+    let mut expect_argument = false;
+    let mut previous = "";
+
+    for token in scanner.tokens.iter().map(String::as_str) {
+        if arguments.is_some() {
+            match token {
+                ")" if value_depth == 0 => arguments = None,
+                "{" | "[" => value_depth += 1,
+                "}" | "]" => value_depth -= 1,
+                "," if value_depth == 0 => expect_argument = true,
+                ":" if value_depth == 0 => expect_argument = false,
+                _ => {}
+            }
+            previous = token;
+            continue;
+        }
+
+        match token {
+            "{" => {
+                let selection_type = if let Some(type_condition) = type_condition.take() {
+                    Some(type_condition)
+                } else if stack.is_empty() {
+                    root_type.clone()
+                } else {
+                    last_field.as_ref().and_then(|(parent, field)| {
+                        schema
+                            .field(parent, field)
+                            .map(|field| field.type_ref.named_type().to_string())
+                    })
+                };
+                stack.push(selection_type);
+                last_field = None;
+            }
+            "}" => {
+                stack.pop();
+                last_field = None;
+            }
+            "(" => {
+                // Variable definitions and directive arguments are not completed.
+                let field = if stack.is_empty() || previous.starts_with('@') {
+                    None
+                } else {
+                    last_field.clone()
+                };
+                arguments = Some(field);
+                expect_argument = true;
+                value_depth = 0;
+            }
+            "on" if previous == "..." || stack.is_empty() => expect_type = true,
+            _ if expect_type && is_name(token) => {
+                type_condition = Some(token.to_string());
+                expect_type = false;
+            }
+            "query" | "mutation" | "subscription" if stack.is_empty() => {
+                root_type = match token {
+                    "query" => Some(schema.query_type.clone()),
+                    "mutation" => schema.mutation_type.clone(),
+                    _ => schema.subscription_type.clone(),
+                };
+            }
+            _ if !stack.is_empty() && is_name(token) && previous != "..." => {
+                if let Some(Some(parent)) = stack.last() {
+                    last_field = Some((parent.clone(), token.to_string()));
+                }
+            }
+            _ => {}
+        }
+        previous = token;
+    }
+
+    if let Some(field) = arguments {
+        if value_depth > 0 || !expect_argument {
+            return Vec::new();
+        }
+        return field
+            .and_then(|(parent, field)| schema.field(&parent, &field))
+            .map(|field| {
+                field
+                    .args
+                    .iter()
+                    .map(|arg| format!("{}:", arg.name))
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    if expect_type {
+        return schema
+            .types
+            .values()
+            .filter(|named_type| {
+                matches!(
+                    named_type.kind,
+                    TypeKind::Object | TypeKind::Interface | TypeKind::Union
+                ) && !named_type.name.starts_with("__")
+            })
+            .map(|named_type| named_type.name.clone())
+            .collect();
+    }
+
+    match stack.last() {
+        Some(Some(parent)) if previous != "..." && !previous.starts_with('@') => schema
+            .get_type(parent)
+            .map(|named_type| {
+                named_type
+                    .fields
+                    .iter()
+                    .map(|field| field.name.clone())
+                    .chain(std::iter::once(String::from("__typename")))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// A minimal GraphQL tokenizer which is tolerant of incomplete input, used for completion and to
+/// decide whether multi-line input is finished.
+struct Scanner {
+    tokens: Vec<String>,
+    depth: i32,
+    in_string: bool,
+}
+
+/// The kind of string the [`Scanner`] is in, if any.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StringKind {
+    None,
+    /// A `"..."` string, which ends at the first unescaped quote.
+    Quoted,
+    /// A `"""..."""` block string, which may contain quotes, braces and newlines, and only ends
+    /// at three quotes which are not escaped as `\"""`.
+    Block,
+}
+
+impl Scanner {
+    fn new(text: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        let mut string = StringKind::None;
+        let chars: Vec<char> = text.chars().collect();
+        let triple_quote = |index: usize| chars.get(index..index + 3) == Some(&['"'; 3][..]);
+        let mut index = 0;
+
+        while let Some(&c) = chars.get(index) {
+            index += 1;
+            match string {
+                StringKind::Quoted => {
+                    match c {
+                        '\\' => index += 1,
+                        '"' => string = StringKind::None,
+                        _ => {}
+                    }
+                    continue;
+                }
+                StringKind::Block => {
+                    if c == '\\' && triple_quote(index) {
+                        index += 3;
+                    } else if triple_quote(index - 1) {
+                        index += 2;
+                        string = StringKind::None;
+                    }
+                    continue;
+                }
+                StringKind::None => {}
+            }
+
+            match c {
+                '#' => {
+                    while chars.get(index).is_some_and(|&c| c != '\n') {
+                        index += 1;
+                    }
+                }
+                '"' if triple_quote(index - 1) => {
+                    index += 2;
+                    string = StringKind::Block;
+                }
+                '"' => string = StringKind::Quoted,
+                '{' | '(' | '[' => {
+                    depth += 1;
+                    tokens.push(c.to_string());
+                }
+                '}' | ')' | ']' => {
+                    depth -= 1;
+                    tokens.push(c.to_string());
+                }
+                ':' | ',' | '=' | '!' | '|' | '&' => tokens.push(c.to_string()),
+                '.' if chars.get(index) == Some(&'.') => {
+                    index += 2;
+                    tokens.push(String::from("..."));
+                }
+                '@' | '$' | '_' | 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' => {
+                    let mut token = c.to_string();
+                    let numeric = c.is_ascii_digit() || c == '-';
+                    while let Some(&next) = chars.get(index) {
+                        if next.is_ascii_alphanumeric() || next == '_' || (numeric && next == '.') {
+                            token.push(next);
+                            index += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    tokens.push(token);
+                }
+                _ => {}
+            }
+        }
+
+        let in_string = string != StringKind::None;
+        Self {
+            tokens,
+            depth,
+            in_string,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn named(kind: &str, name: &str) -> Value {
+        json!({ "kind": kind, "name": name })
+    }
+
+    fn field(name: &str, args: &[&str], type_ref: Value) -> Value {
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| json!({ "name": arg, "type": named("SCALAR", "String") }))
+            .collect();
+        json!({ "name": name, "args": args, "type": type_ref })
+    }
+
+    fn schema() -> Schema {
+        let list = |of_type| json!({ "kind": "LIST", "ofType": of_type });
+        Schema::from_introspection(&json!({
+            "queryType": { "name": "Query" },
+            "types": [
+                {
+                    "kind": "OBJECT",
+                    "name": "Query",
+                    "fields": [
+                        field("folder", &["id"], named("OBJECT", "Folder")),
+                        field("items", &["first", "search"], list(named("OBJECT", "Item"))),
+                    ]
+                },
+                {
+                    "kind": "OBJECT",
+                    "name": "Folder",
+                    "fields": [
+                        field("name", &[], named("SCALAR", "String")),
+                        field("items", &["first"], list(named("OBJECT", "Item"))),
+                    ]
+                },
+                {
+                    "kind": "OBJECT",
+                    "name": "Item",
+                    "fields": [field("subject", &[], named("SCALAR", "String"))]
+                },
+                named("SCALAR", "String"),
+            ]
+        }))
+        .unwrap()
+    }
+
+    fn is_complete(text: &str) -> bool {
+        let scanner = Scanner::new(text);
+        scanner.depth == 0 && !scanner.in_string
+    }
+
+    #[test]
+    fn continues_until_the_braces_are_balanced() {
+        let mut text = String::from("query Inbox {\n");
+        assert!(!is_complete(&text));
+        text.push_str("  folder(id: \"inbox\") {\n    items { subject }\n");
+        assert!(!is_complete(&text));
+        text.push_str("  }\n}");
+        assert!(is_complete(&text));
+    }
+
+    #[test]
+    fn ignores_braces_and_quotes_in_strings_and_comments() {
+        assert!(is_complete(r#"{ items(search: "}{\"") { subject } }"#));
+        assert!(is_complete("{ items { subject } # }\n}"));
+        assert!(!is_complete("{ items(search: \"unterminated) }"));
+    }
+
+    #[test]
+    fn scans_block_strings() {
+        assert!(is_complete(
+            "{ items(search: \"\"\"\n  a \"quote\" and { brace\n\"\"\") { subject } }"
+        ));
+        assert!(is_complete(
+            r#"{ items(search: """ends with "quotes" """) { subject } }"#
+        ));
+        assert!(is_complete(
+            r#"{ items(search: """escaped \""" }""") { subject } }"#
+        ));
+        assert!(is_complete(
+            r#"{ items(search: """5" { tall""") { subject } }"#
+        ));
+        assert!(!is_complete(r#"{ items(search: """C:\dir\""") }"#));
+        assert!(!is_complete(
+            "{ items(search: \"\"\"\n{ \"not done\" }\n\"\") }"
+        ));
+        assert_eq!(
+            Scanner::new(r#"{ items(search: """)}""") }"#).tokens,
+            ["{", "items", "(", "search", ":", ")", "}"]
+        );
+    }
+
+    #[test]
+    fn completes_fields_of_the_enclosing_selection() {
+        let schema = schema();
+        assert_eq!(
+            completions(&schema, "{ "),
+            ["folder", "items", "__typename"]
+        );
+        assert_eq!(
+            completions(&schema, "query {\n  folder(id: \"}\") {\n    "),
+            ["name", "items", "__typename"]
+        );
+        assert_eq!(
+            completions(&schema, "{ folder { items { subject } "),
+            ["name", "items", "__typename"]
+        );
+        assert_eq!(
+            completions(&schema, r#"{ items(search: """{ "a" """) { "#),
+            ["subject", "__typename"]
+        );
+    }
+
+    #[test]
+    fn completes_arguments_of_the_field() {
+        let schema = schema();
+        assert_eq!(completions(&schema, "{ items("), ["first:", "search:"]);
+        assert_eq!(
+            completions(&schema, "{ items(first: 5, "),
+            ["first:", "search:"]
+        );
+        assert!(completions(&schema, "{ items(first: ").is_empty());
+        assert!(completions(&schema, "{ items(search: \"\"\"(").is_empty());
+    }
+
+    #[test]
+    fn completes_type_conditions() {
+        assert_eq!(
+            completions(&schema(), "{ folder { ... on "),
+            ["Folder", "Item", "Query"]
+        );
+    }
+}
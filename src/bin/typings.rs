@@ -0,0 +1,16 @@
+use std::{env, fs, io, path::PathBuf};
+
+/// Write `dispatch-graphql.d.ts` and `dispatch-graphql.js` to the directory passed on the command
+/// line, or to the current directory by default.
+fn main() -> io::Result<()> {
+    let out_dir = PathBuf::from(env::args().nth(1).unwrap_or_else(|| String::from(".")));
+    fs::create_dir_all(&out_dir)?;
+
+    let mut ts_path = out_dir.clone();
+    ts_path.push("dispatch-graphql.d.ts");
+    fs::write(ts_path, dispatch_graphql::typescript_definitions())?;
+
+    let mut js_path = out_dir;
+    js_path.push("dispatch-graphql.js");
+    fs::write(js_path, dispatch_graphql::CLIENT_SCRIPT)
+}
@@ -0,0 +1,769 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    mem,
+};
+
+use graphql_parser::{
+    query::{
+        Definition, Document, Field, FragmentDefinition, OperationDefinition, Selection,
+        SelectionSet, Type, TypeCondition, VariableDefinition,
+    },
+    Pos,
+};
+
+use crate::{
+    schema::{OperationKind, Schema, TypeKind, TypeRef},
+    validation,
+};
+
+type Fragments<'d> = BTreeMap<&'d str, &'d FragmentDefinition<'d, String>>;
+
+/// Generate TypeScript result and variable types for each named operation in a set of documents,
+/// using a [`Schema`] loaded from introspection.
+///
+/// The documents are validated together, so a fragment can be defined in one document and spread
+/// in another, and each operation or fragment name must be unique across all of them.
+pub struct Codegen<'s> {
+    schema: &'s Schema,
+    /// The source name and text of each document which parsed.
+    documents: Vec<(String, String)>,
+    /// The line in the combined document where each document starts, and its source name.
+    sources: Vec<(usize, String)>,
+    operations: String,
+    enums: BTreeSet<String>,
+    scalars: BTreeSet<String>,
+    input_objects: BTreeSet<String>,
+    errors: Vec<String>,
+}
+
+/// Field selections collected for one concrete object type, grouped by response key along with
+/// the type they were selected on.
+struct Shape<'d> {
+    fields: Vec<(String, String, Vec<&'d Field<'d, String>>)>,
+}
+
+impl<'s> Codegen<'s> {
+    pub fn new(schema: &'s Schema) -> Self {
+        Self {
+            schema,
+            documents: Vec::new(),
+            sources: Vec::new(),
+            operations: String::new(),
+            enums: BTreeSet::new(),
+            scalars: BTreeSet::new(),
+            input_objects: BTreeSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Parse a document and add it to the set which [`Codegen::finish`] generates types for. The
+    /// `source_name` (usually the file name) is used to report errors.
+    pub fn add_document(&mut self, source_name: &str, document: &str) {
+        // Syntax errors are reported for each document, so one which does not parse doesn't hide
+        // the errors in the others.
+        if let Err(error) = graphql_parser::parse_query::<String>(document) {
+            self.errors.push(format!("{source_name}: {error}"));
+            return;
+        }
+        self.documents
+            .push((source_name.to_string(), document.to_string()));
+    }
+
+    /// Validate every document which was added as one set, and generate types for each operation.
+    fn generate(&mut self) {
+        // Each document starts on a new line of the combined document, so an error's position can
+        // be traced back to the document and line it came from.
+        let mut combined = String::new();
+        let mut line = 1;
+        for (source_name, document) in mem::take(&mut self.documents) {
+            self.sources.push((line, source_name));
+            combined.push_str(&document);
+            combined.push('\n');
+            line += document.matches('\n').count() + 1;
+        }
+        let document: Document<String> = match graphql_parser::parse_query(&combined) {
+            Ok(document) => document,
+            Err(error) => {
+                self.errors.push(error.to_string());
+                return;
+            }
+        };
+
+        let violations = validation::validate_definitions(self.schema, &document);
+        if !violations.is_empty() {
+            for violation in violations {
+                match violation.locations.first() {
+                    Some(location) => {
+                        let position = Pos {
+                            line: location.line,
+                            column: location.column,
+                        };
+                        self.error(position, &violation.message);
+                    }
+                    None => self.errors.push(violation.message),
+                }
+            }
+            return;
+        }
+
+        let fragments: Fragments = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                Definition::Operation(_) => None,
+            })
+            .collect();
+
+        for definition in document.definitions.iter() {
+            let Definition::Operation(operation) = definition else {
+                continue;
+            };
+            let (kind, name, variables, selection_set, position) = match operation {
+                OperationDefinition::SelectionSet(selection_set) => (
+                    OperationKind::Query,
+                    None,
+                    &[][..],
+                    selection_set,
+                    selection_set.span.0,
+                ),
+                OperationDefinition::Query(query) => (
+                    OperationKind::Query,
+                    query.name.as_deref(),
+                    &query.variable_definitions[..],
+                    &query.selection_set,
+                    query.position,
+                ),
+                OperationDefinition::Mutation(mutation) => (
+                    OperationKind::Mutation,
+                    mutation.name.as_deref(),
+                    &mutation.variable_definitions[..],
+                    &mutation.selection_set,
+                    mutation.position,
+                ),
+                OperationDefinition::Subscription(subscription) => (
+                    OperationKind::Subscription,
+                    subscription.name.as_deref(),
+                    &subscription.variable_definitions[..],
+                    &subscription.selection_set,
+                    subscription.position,
+                ),
+            };
+
+            let Some(name) = name else {
+                self.error(position, "Operations must be named to generate types");
+                continue;
+            };
+            // Validation has already reported operations which the schema does not support.
+            let Some(root_type) = self.schema.root_type(kind) else {
+                continue;
+            };
+
+            let type_name = if name.ends_with(&kind.to_string()) {
+                name.to_string()
+            } else {
+                format!("{name}{kind}")
+            };
+            let root_type = root_type.name.clone();
+
+            let variables = self.variables(variables);
+            let result = self.selection_set(&root_type, &[selection_set], &fragments, 0);
+            let _ = writeln!(
+                self.operations,
+                "export interface {type_name}Variables {variables}\n"
+            );
+            let _ = writeln!(self.operations, "export type {type_name} = {result};\n");
+        }
+    }
+
+    /// Finish generating the TypeScript module, or return all of the errors found in the documents.
+    pub fn finish(mut self) -> Result<String, Vec<String>> {
+        self.generate();
+
+        // Input objects may reference other input objects, so keep going until no new ones are
+        // added. They may also reference enums and custom scalars, so emit them first.
+        let mut input_objects = String::new();
+        let mut emitted = BTreeSet::new();
+        let mut pending: Vec<_> = self.input_objects.iter().rev().cloned().collect();
+        while let Some(name) = pending.pop() {
+            if !emitted.insert(name.clone()) {
+                continue;
+            }
+            let Some(input_type) = self.schema.get_type(&name) else {
+                continue;
+            };
+            let _ = writeln!(input_objects, "export interface {name} {{");
+            for input_field in input_type.input_fields.iter() {
+                let optional =
+                    !input_field.type_ref.is_non_null() || input_field.default_value.is_some();
+                let ts_type = self.input_type(&input_field.type_ref);
+                let _ = writeln!(
+                    input_objects,
+                    "  {}{}: {ts_type};",
+                    input_field.name,
+                    if optional { "?" } else { "" }
+                );
+            }
+            input_objects.push_str("}\n\n");
+            pending.extend(self.input_objects.difference(&emitted).cloned());
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let mut output = String::from("// Generated by dispatch-graphql, do not edit.\n\n");
+
+        for scalar in self.scalars.iter() {
+            let _ = writeln!(output, "export type {scalar} = unknown;\n");
+        }
+
+        for name in self.enums.iter() {
+            let Some(enum_type) = self.schema.get_type(name) else {
+                continue;
+            };
+            let values: Vec<_> = enum_type
+                .enum_values
+                .iter()
+                .map(|value| format!("\"{value}\""))
+                .collect();
+            let _ = writeln!(output, "export type {name} = {};\n", values.join(" | "));
+        }
+
+        output.push_str(&input_objects);
+        output.push_str(&self.operations);
+        Ok(output.trim_end().to_string() + "\n")
+    }
+
+    /// Report an error at a position in the combined document, with the source name and line of
+    /// the document it came from.
+    fn error(&mut self, position: Pos, message: &str) {
+        let (first_line, source_name) = self
+            .sources
+            .iter()
+            .rev()
+            .find(|(first_line, _)| *first_line <= position.line)
+            .map_or((1, ""), |(first_line, source_name)| {
+                (*first_line, source_name.as_str())
+            });
+        let error = format!(
+            "{source_name}:{}:{}: {message}",
+            position.line - first_line + 1,
+            position.column
+        );
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    fn variables(&mut self, variables: &[VariableDefinition<String>]) -> String {
+        let mut definition = String::from("{\n");
+        for variable in variables {
+            let type_ref = type_ref(&variable.var_type);
+            let optional = !type_ref.is_non_null() || variable.default_value.is_some();
+            let ts_type = self.input_type(&type_ref);
+            let _ = writeln!(
+                definition,
+                "  {}{}: {ts_type};",
+                variable.name,
+                if optional { "?" } else { "" }
+            );
+        }
+        definition.push('}');
+        definition
+    }
+
+    fn input_type(&mut self, type_ref: &TypeRef) -> String {
+        let name = type_ref.named_type();
+        let named = match self.schema.get_type(name).map(|named_type| named_type.kind) {
+            Some(TypeKind::InputObject) => {
+                self.input_objects.insert(name.to_string());
+                name.to_string()
+            }
+            _ => self.leaf_type(name),
+        };
+        wrap_type(type_ref, &named)
+    }
+
+    fn leaf_type(&mut self, name: &str) -> String {
+        match name {
+            "String" | "ID" => String::from("string"),
+            "Int" | "Float" => String::from("number"),
+            "Boolean" => String::from("boolean"),
+            _ => {
+                match self.schema.get_type(name).map(|named_type| named_type.kind) {
+                    Some(TypeKind::Enum) => {
+                        self.enums.insert(name.to_string());
+                    }
+                    _ => {
+                        self.scalars.insert(name.to_string());
+                    }
+                }
+                name.to_string()
+            }
+        }
+    }
+
+    /// Generate the type of the selection sets of every field with the same response key, which
+    /// are merged, e.g. `user { id }` and `user { name }` become `{ id; name }`.
+    fn selection_set<'d>(
+        &mut self,
+        parent_type: &str,
+        selection_sets: &[&'d SelectionSet<'d, String>],
+        fragments: &Fragments<'d>,
+        indent: usize,
+    ) -> String {
+        let mut shapes: Vec<String> = Vec::new();
+        let possible_types: Vec<String> = self
+            .schema
+            .possible_types(parent_type)
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        for object_type in possible_types.iter() {
+            let mut shape = Shape { fields: Vec::new() };
+            let mut visited = BTreeSet::new();
+            for selection_set in selection_sets {
+                self.collect_fields(
+                    parent_type,
+                    object_type,
+                    selection_set,
+                    fragments,
+                    &mut shape,
+                    &mut visited,
+                );
+            }
+
+            let mut rendered = String::from("{\n");
+            for (key, scope_type, fields) in shape.fields {
+                let Some(ts_type) =
+                    self.field_type(&scope_type, object_type, &fields, fragments, indent)
+                else {
+                    continue;
+                };
+                // A field is only missing from the response if every selection of it is
+                // conditional.
+                let optional = fields.iter().all(|field| {
+                    field
+                        .directives
+                        .iter()
+                        .any(|directive| directive.name == "skip" || directive.name == "include")
+                });
+                let _ = writeln!(
+                    rendered,
+                    "{:width$}{key}{}: {ts_type};",
+                    "",
+                    if optional { "?" } else { "" },
+                    width = indent + 2
+                );
+            }
+            let _ = write!(rendered, "{:width$}}}", "", width = indent);
+
+            if !shapes.contains(&rendered) {
+                shapes.push(rendered);
+            }
+        }
+
+        if shapes.is_empty() {
+            String::from("{}")
+        } else {
+            shapes.join(" | ")
+        }
+    }
+
+    /// Generate the type of every field selected with the same response key on an object type.
+    fn field_type<'d>(
+        &mut self,
+        scope_type: &str,
+        object_type: &str,
+        fields: &[&'d Field<'d, String>],
+        fragments: &Fragments<'d>,
+        indent: usize,
+    ) -> Option<String> {
+        let field = fields.first()?;
+        if let Some(conflict) = fields.iter().find(|other| other.name != field.name) {
+            let key = field.alias.as_ref().unwrap_or(&field.name);
+            self.error(
+                conflict.position,
+                &format!(
+                    "Fields \"{key}\" conflict because \"{}\" and \"{}\" are different fields",
+                    field.name, conflict.name
+                ),
+            );
+            return None;
+        }
+
+        if field.name == "__typename" {
+            return Some(format!("\"{object_type}\""));
+        }
+        let Some(schema_field) = self.schema.field(scope_type, &field.name) else {
+            self.error(
+                field.position,
+                &format!(
+                    "Cannot generate a type for field \"{}\" on type \"{scope_type}\"",
+                    field.name
+                ),
+            );
+            return None;
+        };
+
+        let type_ref = schema_field.type_ref.clone();
+        let named_type = type_ref.named_type();
+        let named = if self.schema.is_composite(named_type) {
+            let selection_sets: Vec<_> = fields.iter().map(|field| &field.selection_set).collect();
+            self.selection_set(named_type, &selection_sets, fragments, indent + 2)
+        } else {
+            self.leaf_type(named_type)
+        };
+        Some(wrap_type(&type_ref, &named))
+    }
+
+    /// Group the fields selected on an object type by response key, following fragments whose
+    /// type condition applies to it.
+    fn collect_fields<'d>(
+        &mut self,
+        scope_type: &str,
+        object_type: &str,
+        selection_set: &'d SelectionSet<'d, String>,
+        fragments: &Fragments<'d>,
+        shape: &mut Shape<'d>,
+        visited: &mut BTreeSet<&'d str>,
+    ) {
+        for selection in selection_set.items.iter() {
+            match selection {
+                Selection::Field(field) => {
+                    let key = field.alias.as_ref().unwrap_or(&field.name);
+                    match shape
+                        .fields
+                        .iter_mut()
+                        .find(|(existing, _, _)| existing == key)
+                    {
+                        Some((_, _, fields)) => fields.push(field),
+                        None => {
+                            shape
+                                .fields
+                                .push((key.clone(), scope_type.to_string(), vec![field]))
+                        }
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    let Some(fragment) = fragments.get(spread.fragment_name.as_str()) else {
+                        continue;
+                    };
+                    if !visited.insert(fragment.name.as_str()) {
+                        continue;
+                    }
+                    let TypeCondition::On(type_condition) = &fragment.type_condition;
+                    if self.schema.applies_to(type_condition, object_type) {
+                        self.collect_fields(
+                            type_condition,
+                            object_type,
+                            &fragment.selection_set,
+                            fragments,
+                            shape,
+                            visited,
+                        );
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => {
+                    let type_condition = match &inline_fragment.type_condition {
+                        Some(TypeCondition::On(type_condition)) => type_condition.as_str(),
+                        None => scope_type,
+                    };
+                    if self.schema.applies_to(type_condition, object_type) {
+                        self.collect_fields(
+                            type_condition,
+                            object_type,
+                            &inline_fragment.selection_set,
+                            fragments,
+                            shape,
+                            visited,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Convert a parsed variable type to the [`TypeRef`] used by the schema model.
+pub fn type_ref(var_type: &Type<String>) -> TypeRef {
+    match var_type {
+        Type::NamedType(name) => TypeRef::Named(name.clone()),
+        Type::ListType(of_type) => TypeRef::List(Box::new(type_ref(of_type))),
+        Type::NonNullType(of_type) => TypeRef::NonNull(Box::new(type_ref(of_type))),
+    }
+}
+
+fn wrap_type(type_ref: &TypeRef, named: &str) -> String {
+    match type_ref {
+        TypeRef::NonNull(of_type) => match of_type.as_ref() {
+            TypeRef::List(item) => format!("Array<{}>", wrap_type(item, named)),
+            _ => named.to_string(),
+        },
+        TypeRef::List(item) => format!("Array<{}> | null", wrap_type(item, named)),
+        TypeRef::Named(_) => format!("{named} | null"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn named(kind: &str, name: &str) -> Value {
+        json!({ "kind": kind, "name": name, "ofType": null })
+    }
+
+    fn non_null(of_type: Value) -> Value {
+        json!({ "kind": "NON_NULL", "name": null, "ofType": of_type })
+    }
+
+    fn list(of_type: Value) -> Value {
+        json!({ "kind": "LIST", "name": null, "ofType": of_type })
+    }
+
+    fn field(name: &str, type_ref: Value) -> Value {
+        json!({ "name": name, "args": [], "type": type_ref })
+    }
+
+    fn id_arg() -> Value {
+        json!([{ "name": "id", "type": non_null(named("SCALAR", "ID")), "defaultValue": null }])
+    }
+
+    fn schema() -> Schema {
+        let node = named("INTERFACE", "Node");
+        let user = named("OBJECT", "User");
+        let introspection = json!({
+            "queryType": { "name": "Query" },
+            "types": [
+                {
+                    "kind": "OBJECT",
+                    "name": "Query",
+                    "fields": [
+                        { "name": "user", "args": id_arg(), "type": user },
+                        { "name": "node", "args": id_arg(), "type": node },
+                        field("users", non_null(list(non_null(user.clone())))),
+                    ]
+                },
+                {
+                    "kind": "INTERFACE",
+                    "name": "Node",
+                    "fields": [field("id", non_null(named("SCALAR", "ID")))],
+                    "possibleTypes": [user, named("OBJECT", "Post")]
+                },
+                {
+                    "kind": "OBJECT",
+                    "name": "User",
+                    "interfaces": [node],
+                    "fields": [
+                        field("id", non_null(named("SCALAR", "ID"))),
+                        field("name", named("SCALAR", "String")),
+                        field("friends", non_null(list(non_null(user.clone())))),
+                    ]
+                },
+                {
+                    "kind": "OBJECT",
+                    "name": "Post",
+                    "interfaces": [node],
+                    "fields": [
+                        field("id", non_null(named("SCALAR", "ID"))),
+                        field("title", non_null(named("SCALAR", "String"))),
+                    ]
+                },
+                { "kind": "SCALAR", "name": "ID" },
+                { "kind": "SCALAR", "name": "String" },
+            ]
+        });
+        Schema::from_introspection(&introspection).expect("valid schema")
+    }
+
+    fn generate(document: &str) -> Result<String, Vec<String>> {
+        generate_files(&[("test.graphql", document)])
+    }
+
+    fn generate_files(documents: &[(&str, &str)]) -> Result<String, Vec<String>> {
+        let schema = schema();
+        let mut codegen = Codegen::new(&schema);
+        for (source_name, document) in documents {
+            codegen.add_document(source_name, document);
+        }
+        codegen.finish()
+    }
+
+    fn operation_type(output: &str) -> &str {
+        let (_, operation) = output
+            .split_once("\nexport type ")
+            .expect("an operation type");
+        operation.trim_end()
+    }
+
+    #[test]
+    fn merges_fields_with_the_same_response_key() {
+        let output = generate(
+            r#"query Merged {
+                user(id: "1") { id }
+                user(id: "1") { name friends { id } friends { name } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operation_type(&output),
+            r#"MergedQuery = {
+  user: {
+    id: string;
+    name: string | null;
+    friends: Array<{
+      id: string;
+      name: string | null;
+    }>;
+  } | null;
+};"#
+        );
+    }
+
+    #[test]
+    fn merges_fragments_for_each_possible_type() {
+        let output = generate(
+            r#"query Nodes {
+                node(id: "1") { __typename ...NodeId ... on User { name } }
+            }
+            fragment NodeId on Node { id }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operation_type(&output),
+            r#"NodesQuery = {
+  node: {
+    __typename: "User";
+    id: string;
+    name: string | null;
+  } | {
+    __typename: "Post";
+    id: string;
+  } | null;
+};"#
+        );
+    }
+
+    #[test]
+    fn keys_fields_by_alias() {
+        let output = generate(
+            r#"query Aliases { me: user(id: "1") { id } friend: user(id: "2") { id: name } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operation_type(&output),
+            r#"AliasesQuery = {
+  me: {
+    id: string;
+  } | null;
+  friend: {
+    id: string | null;
+  } | null;
+};"#
+        );
+    }
+
+    #[test]
+    fn merges_nested_lists_from_inline_fragments() {
+        let output = generate(
+            r#"query Lists {
+                users { friends { id } ... on User { friends { name @include(if: true) } } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operation_type(&output),
+            r#"ListsQuery = {
+  users: Array<{
+    friends: Array<{
+      id: string;
+      name?: string | null;
+    }>;
+  }>;
+};"#
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_aliases() {
+        assert_eq!(
+            generate(r#"query Conflict { user(id: "1") { x: id x: name } }"#),
+            Err(vec![String::from(
+                r#"test.graphql:1:40: Fields "x" conflict because "id" and "name" are different fields"#
+            )])
+        );
+    }
+
+    #[test]
+    fn reports_validation_errors() {
+        assert_eq!(
+            generate(r#"query Unknown($id: ID!) { user(id: "1") { email } }"#),
+            Err(vec![
+                String::from(r#"test.graphql:1:43: Cannot query field "email" on type "User"."#),
+                String::from(
+                    r#"test.graphql:1:15: Variable "$id" is never used in operation "Unknown"."#
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn spreads_fragments_from_other_documents() {
+        let output = generate_files(&[
+            ("fragments.graphql", "fragment UserName on User { name }\n"),
+            (
+                "query.graphql",
+                r#"query Named { user(id: "1") { ...UserName } }"#,
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            operation_type(&output),
+            r#"NamedQuery = {
+  user: {
+    name: string | null;
+  } | null;
+};"#
+        );
+    }
+
+    #[test]
+    fn reports_names_defined_in_more_than_one_document() {
+        assert_eq!(
+            generate_files(&[
+                (
+                    "a.graphql",
+                    "fragment UserId on User { id }\nquery Twice { users { ...UserId } }"
+                ),
+                (
+                    "b.graphql",
+                    "\n\nquery Twice { users { id } }\n\nfragment UserId on User { id }\n"
+                ),
+            ]),
+            Err(vec![
+                String::from(r#"b.graphql:5:1: There can be only one fragment named "UserId"."#),
+                String::from(r#"b.graphql:3:1: There can be only one operation named "Twice"."#),
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors_for_each_document() {
+        let errors = generate_files(&[
+            ("broken.graphql", "query {"),
+            ("unknown.graphql", "query Unknown { users { email } }"),
+        ])
+        .unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:#?}");
+        assert!(errors[0].starts_with("broken.graphql: "), "{}", errors[0]);
+        assert_eq!(
+            errors[1],
+            r#"unknown.graphql:1:25: Cannot query field "email" on type "User"."#
+        );
+    }
+}
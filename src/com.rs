@@ -0,0 +1,1113 @@
+use std::{
+    cell::UnsafeCell,
+    env,
+    ffi::c_void,
+    iter, mem, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Once, OnceLock, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::*,
+        System::{
+            Com::*, Diagnostics::Debug::OutputDebugStringW, LibraryLoader::*, Ole::*, Variant::*,
+        },
+        UI::WindowsAndMessaging::{
+            self, PeekMessageW, CREATESTRUCTW, GWLP_USERDATA, MSG, PM_NOREMOVE, WINDOW_EX_STYLE,
+            WINDOW_STYLE, WM_USER, WNDCLASSEXW,
+        },
+    },
+};
+use windows_implement::implement;
+use windows_interface::interface;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    dispatcher::{CallbackDispatcher, CallbackError, CallbackQueue, DeliverySender},
+    encoding::Encoding,
+    json_dispatch, metrics,
+    recording::{self, Recorder},
+    service::{self, QueryOptions, QueryResult, Service},
+    slow_log::{self, SlowLogConfig},
+    utf16, variant, PendingPayload, ResultPayload, ShutdownReport,
+};
+
+macro_rules! impl_dispatch {
+    ($type:ident, $interface:ident) => {
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        impl IDispatch_Impl for $type {
+            fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
+                Ok(1)
+            }
+
+            fn GetTypeInfo(&self, itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
+                if itinfo != 0 {
+                    TYPE_E_ELEMENTNOTFOUND.ok()?;
+                }
+
+                unsafe {
+                    let type_lib = match &mut *self.type_lib.get() {
+                        Some(type_lib) => type_lib.clone(),
+                        None => {
+                            let type_lib = load_type_lib()?;
+                            *self.type_lib.get() = Some(type_lib.clone());
+                            type_lib
+                        }
+                    };
+
+                    type_lib.GetTypeInfoOfGuid(&<$interface as ComInterface>::IID)
+                }
+            }
+
+            fn GetIDsOfNames(
+                &self,
+                _riid: *const windows::core::GUID,
+                rgsznames: *const windows::core::PCWSTR,
+                cnames: u32,
+                lcid: u32,
+                rgdispid: *mut i32,
+            ) -> windows::core::Result<()> {
+                let type_info = self.GetTypeInfo(0, lcid)?;
+                unsafe { type_info.GetIDsOfNames(rgsznames, cnames, rgdispid) }
+            }
+
+            fn Invoke(
+                &self,
+                dispidmember: i32,
+                _riid: *const windows::core::GUID,
+                lcid: u32,
+                wflags: DISPATCH_FLAGS,
+                pdispparams: *const DISPPARAMS,
+                pvarresult: *mut VARIANT,
+                pexcepinfo: *mut EXCEPINFO,
+                puargerr: *mut u32,
+            ) -> windows::core::Result<()> {
+                let type_info = self.GetTypeInfo(0, lcid)?;
+                unsafe {
+                    let this: $interface = self.cast()?;
+                    type_info.Invoke(
+                        this.as_raw(),
+                        dispidmember,
+                        wflags,
+                        pdispparams as *mut _,
+                        pvarresult,
+                        pexcepinfo,
+                        puargerr,
+                    )
+                }
+            }
+        }
+    };
+}
+
+/// External entry point to create an IDispatch object for the service. If this function succeeds,
+/// the caller is responsible for calling `Release` on the `IDispatch` interface pointer returned
+/// through the `result` out-param. The initial value that `result` points to must be `null`.
+///
+/// # Safety
+///
+/// The `result` parameter must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn CreateService(result: *mut *mut c_void) -> HRESULT {
+    // Parameter validation
+    let Some(result) = result.as_mut() else {
+        return E_POINTER;
+    };
+    if !result.is_null() {
+        return E_INVALIDARG;
+    }
+
+    #[cfg(feature = "trace-log")]
+    {
+        static INIT_LOG: Once = Once::new();
+        INIT_LOG.call_once(|| {
+            // A log file which can't be opened shouldn't stop the service from starting.
+            let _ = crate::trace::init_from_env();
+        });
+    }
+
+    static SERVE_METRICS: Once = Once::new();
+    SERVE_METRICS.call_once(|| {
+        let allow_remote =
+            env::var(metrics::METRICS_ALLOW_REMOTE_VAR).is_ok_and(|allow| allow == "1");
+        if let Some(listener) = env::var(metrics::METRICS_ADDRESS_VAR)
+            .ok()
+            .and_then(|address| metrics::bind(&address, allow_remote).ok())
+        {
+            metrics::serve(listener);
+        }
+    });
+
+    static RECORDER: OnceLock<Option<Arc<Recorder>>> = OnceLock::new();
+    let recorder = RECORDER.get_or_init(|| {
+        // A recording which can't be created shouldn't stop the service from starting either.
+        env::var_os(recording::RECORD_PATH_VAR)
+            .and_then(|path| Recorder::create(path).ok())
+            .map(Arc::new)
+    });
+    let mut service = Service::default();
+    if let Some(recorder) = recorder {
+        service = service.with_recorder(recorder.clone());
+    }
+
+    let service: IGraphQLService = GraphQLService::with_service(service).into();
+    let Ok(service) = service.cast::<IDispatch>() else {
+        return E_NOINTERFACE;
+    };
+    *result = service.into_raw();
+    S_OK
+}
+
+/// How payloads are delivered to script, both as the result of the call which starts an operation
+/// and to its `nextCallback`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PayloadFormat {
+    /// A JSON string, which script passes to `JSON.parse`.
+    Json,
+    /// A read-only script object, which reads from the JSON value on demand.
+    Object,
+    /// A `SAFEARRAY` of bytes in a binary encoding such as CBOR or MessagePack.
+    Binary(Encoding),
+}
+
+impl From<Encoding> for PayloadFormat {
+    fn from(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Json => Self::Json,
+            encoding => Self::Binary(encoding),
+        }
+    }
+}
+
+impl PayloadFormat {
+    fn to_variant<T: Serialize>(self, payload: &T) -> VARIANT {
+        match self {
+            Self::Json => {
+                let mut variant = VARIANT::default();
+                unsafe {
+                    let data = &mut *variant.Anonymous.Anonymous;
+                    data.vt = VT_BSTR;
+                    data.Anonymous.bstrVal = mem::ManuallyDrop::new(serialize_results(payload));
+                }
+                variant
+            }
+            Self::Object => {
+                json_dispatch::to_variant(serde_json::to_value(payload).unwrap_or_default())
+            }
+            Self::Binary(encoding) => encoding
+                .encode(payload)
+                .map(|payload| byte_array(&payload))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Copy bytes into a `VT_ARRAY | VT_UI1` variant, which is how COM passes binary data.
+fn byte_array(bytes: &[u8]) -> VARIANT {
+    let mut variant = VARIANT::default();
+    let Ok(length) = u32::try_from(bytes.len()) else {
+        return variant;
+    };
+
+    unsafe {
+        let array = SafeArrayCreateVector(VT_UI1, 0, length);
+        if array.is_null() {
+            return variant;
+        }
+        if !bytes.is_empty() {
+            // The array was just allocated, so nothing else can have it locked yet.
+            ptr::copy_nonoverlapping(bytes.as_ptr(), (*array).pvData as *mut u8, bytes.len());
+        }
+
+        let data = &mut *variant.Anonymous.Anonymous;
+        data.vt = VARENUM(VT_ARRAY.0 | VT_UI1.0);
+        data.Anonymous.parray = array;
+    }
+
+    variant
+}
+
+/// The immediate result of starting an operation.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum StartPayload {
+    Results(ResultPayload),
+    Pending(PendingPayload),
+}
+
+/// Serialize a payload to a UTF-16 buffer, then copy it into the `BSTR` allocation, which adds its
+/// own null terminator. The length prefix of a `BSTR` has to be known when it is allocated, so the
+/// payload can't be written into the `BSTR` directly, but this is the only copy.
+fn serialize_results<T: Serialize>(payload: T) -> BSTR {
+    utf16::to_utf16(&payload)
+        .ok()
+        .and_then(|payload| BSTR::from_wide(&payload).ok())
+        .unwrap_or_default()
+}
+
+impl From<service::Error> for HRESULT {
+    fn from(error: service::Error) -> Self {
+        match error {
+            service::Error::InvalidArgument | service::Error::UnknownSubscription => E_INVALIDARG,
+            service::Error::Unexpected => E_UNEXPECTED,
+        }
+    }
+}
+
+fn to_hresult(result: service::Result<()>) -> HRESULT {
+    result.map_or_else(HRESULT::from, |()| S_OK)
+}
+
+/// Convert a `BSTR` argument to a `String`. The caller (WebView2) retains ownership of it, so this
+/// suppresses the drop destructor on the `windows::core::BSTR` constructed by the generated
+/// `IGraphQLService` impl, whether or not the conversion succeeds.
+fn string_argument(value: BSTR) -> std::result::Result<String, HRESULT> {
+    let value = mem::ManuallyDrop::new(value);
+    String::from_utf16(value.as_wide()).map_err(|_| E_INVALIDARG)
+}
+
+/// The operation which one of the `fetchQuery` methods was asked to start.
+struct Request {
+    query: String,
+    operation_name: String,
+    variables: String,
+}
+
+impl Request {
+    /// Convert the arguments which every `fetchQuery` method takes. Both `BSTR`s are taken before
+    /// anything can fail, so neither of them is dropped on an early return.
+    fn new(
+        query: BSTR,
+        operation_name: BSTR,
+        variables: std::result::Result<String, HRESULT>,
+    ) -> std::result::Result<Self, HRESULT> {
+        let (query, operation_name) = (string_argument(query), string_argument(operation_name));
+        Ok(Self {
+            query: query?,
+            operation_name: operation_name?,
+            variables: variables?,
+        })
+    }
+}
+
+/// The `options` of `fetchQueryWithOptions`, which combine what the other `fetchQuery` methods
+/// each add, e.g. `{ "timeout": 1000, "tracing": true, "asObject": true }`.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+struct FetchOptions {
+    /// Milliseconds before a query or mutation is cancelled, like `fetchQueryWithTimeout`.
+    timeout: Option<f64>,
+    /// Add the `tracing` extension to each payload, like `fetchQueryWithExtensions`.
+    tracing: bool,
+    /// Deliver script objects, like `fetchQueryAsObject`.
+    as_object: bool,
+    /// Deliver payloads in a binary encoding, like `fetchQueryEncoded`.
+    encoding: Option<String>,
+}
+
+impl FetchOptions {
+    /// Parse the options from the JSON which `variables_from_variant` produces. An empty string
+    /// uses the defaults, which are the same as `fetchQuery`.
+    fn parse(options: &str) -> std::result::Result<Self, HRESULT> {
+        if options.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(options).map_err(|_| E_INVALIDARG)
+    }
+
+    fn into_parts(self) -> std::result::Result<(PayloadFormat, QueryOptions), HRESULT> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding.parse::<Encoding>().map_err(|()| E_INVALIDARG)?,
+            None => Encoding::Json,
+        };
+        let format = match (self.as_object, encoding) {
+            (false, encoding) => PayloadFormat::from(encoding),
+            (true, Encoding::Json) => PayloadFormat::Object,
+            // Script objects can't also be encoded.
+            (true, _) => return Err(E_INVALIDARG),
+        };
+        let options = QueryOptions {
+            timeout: timeout_from_millis(self.timeout.unwrap_or_default()),
+            tracing: self.tracing,
+        };
+        Ok((format, options))
+    }
+}
+
+/// A timeout of 0 (or less) waits for as long as the query takes.
+fn timeout_from_millis(timeout: f64) -> Option<Duration> {
+    Some(timeout)
+        .filter(|&timeout| timeout > 0.0)
+        .and_then(|timeout| Duration::try_from_secs_f64(timeout / 1000.0).ok())
+}
+
+/// Where a `fetchQuery` method writes its immediate result.
+trait FetchResult {
+    fn from_payload(format: PayloadFormat, payload: &StartPayload) -> Self;
+}
+
+/// A JSON string, for the methods which always deliver JSON.
+impl FetchResult for BSTR {
+    fn from_payload(_format: PayloadFormat, payload: &StartPayload) -> Self {
+        serialize_results(payload)
+    }
+}
+
+/// A `VARIANT` in the same format as the `next` payloads.
+impl FetchResult for VARIANT {
+    fn from_payload(format: PayloadFormat, payload: &StartPayload) -> Self {
+        format.to_variant(payload)
+    }
+}
+
+#[interface("FA294686-DB83-4268-A84F-157012D56033")]
+pub unsafe trait IGraphQLService: IDispatch {
+    fn fetchQuery(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: BSTR,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT;
+    fn unsubscribe(&self, key: i32) -> HRESULT;
+    fn getSchema(&self, format: BSTR, result: *mut BSTR) -> HRESULT;
+    fn fetchQueryWithVariables(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT;
+    fn fetchQueryAsObject(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT;
+    fn fetchQueryEncoded(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        encoding: BSTR,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT;
+    fn fetchQueryWithTimeout(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        timeout: i32,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT;
+    fn listSubscriptions(&self, result: *mut BSTR) -> HRESULT;
+    fn openSession(&self, result: *mut *mut c_void) -> HRESULT;
+    fn unsubscribeAll(&self) -> HRESULT;
+    fn shutdown(&self, timeout: i32, result: *mut BSTR) -> HRESULT;
+    fn getMetrics(&self, format: BSTR, result: *mut BSTR) -> HRESULT;
+    fn fetchQueryWithExtensions(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        extensions: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT;
+    fn getSlowOperations(&self, result: *mut BSTR) -> HRESULT;
+    fn setSlowOperationThresholds(&self, duration: i32, backlog: i32) -> HRESULT;
+    fn fetchQueryWithOptions(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        options: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT;
+}
+
+#[implement(IGraphQLService, IDispatch)]
+pub struct GraphQLService {
+    type_lib: UnsafeCell<Option<ITypeLib>>,
+    service: Service,
+    dispatch_queue: DeferCallbackQueue,
+    workers: Mutex<Vec<(i32, JoinHandle<service::Result<()>>)>>,
+    shut_down: AtomicBool,
+}
+
+impl GraphQLService {
+    pub fn new() -> Self {
+        Self::with_service(Service::default())
+    }
+
+    /// Wrap a [`Service`] using any executor in the COM interface.
+    pub fn with_service(service: Service) -> Self {
+        Self {
+            type_lib: UnsafeCell::new(None),
+            service,
+            dispatch_queue: DeferCallbackQueue::new(),
+            workers: Mutex::new(Vec::new()),
+            shut_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Cancel every subscription in this session, and wait until `timeout` for the worker threads
+    /// which deliver their results to finish. Any `next` payloads which are still queued are
+    /// discarded, and the callbacks are released. After this, new operations fail with
+    /// `E_ILLEGAL_METHOD_CALL`, and calling it again only reports the workers which are still
+    /// running.
+    pub fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        self.shut_down.store(true, Ordering::Release);
+        let cancelled = self.service.unsubscribe_all().unwrap_or_default();
+
+        // Cancelling the subscriptions closes their channels, so the workers should finish almost
+        // immediately, unless the executor is stuck.
+        let deadline = Instant::now() + timeout;
+        let mut workers = self
+            .workers
+            .lock()
+            .map(|mut workers| mem::take(&mut *workers))
+            .unwrap_or_default();
+        loop {
+            workers.retain(|(_, worker)| !worker.is_finished());
+            if workers.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let abandoned = workers.iter().map(|&(key, _)| key).collect();
+        if let Ok(mut remaining) = self.workers.lock() {
+            // Keep the handles for a later call, the threads are detached when they are dropped.
+            remaining.extend(workers);
+        }
+
+        ShutdownReport {
+            cancelled,
+            abandoned,
+            discarded: unsafe { self.dispatch_queue.discard_callbacks() },
+        }
+    }
+
+    pub fn service(&self) -> &Service {
+        &self.service
+    }
+
+    /// Start the query for any of the `fetchQuery` methods, once the arguments have been converted.
+    /// Each `next` payload is delivered to `next_callback` in the requested `format`, and the
+    /// `options` can set a timeout for a query or mutation, or ask for extensions.
+    unsafe fn start_query(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        next_callback: *mut c_void,
+        format: PayloadFormat,
+        options: QueryOptions,
+    ) -> std::result::Result<StartPayload, HRESULT> {
+        let _entered = tracing::info_span!("fetchQuery", ?format).entered();
+        if next_callback.is_null() {
+            return Err(E_INVALIDARG);
+        }
+        if self.shut_down.load(Ordering::Acquire) {
+            return Err(E_ILLEGAL_METHOD_CALL);
+        }
+        let raw = IDispatch::from_raw(next_callback);
+        let next_callback = raw.clone();
+        mem::forget(raw);
+
+        match self
+            .service
+            .start_query_with_options(query, operation_name, variables, options)
+        {
+            Ok(QueryResult::Results(payload)) => Ok(StartPayload::Results(payload)),
+            Ok(QueryResult::Pending { key, next }) => {
+                let Some(sender) = self.dispatch_queue.get_sender() else {
+                    return Err(to_hresult(self.service.cancel_subscription(key)));
+                };
+                self.dispatch_queue
+                    .add_subscription(next_callback, format, key);
+                let worker = self.service.deliver_results(key, next, sender);
+                if let Ok(mut workers) = self.workers.lock() {
+                    workers.retain(|(_, worker)| !worker.is_finished());
+                    workers.push((key, worker));
+                }
+
+                Ok(StartPayload::Pending(PendingPayload {
+                    pending: key,
+                    subscription: service::is_subscription(query, operation_name),
+                }))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Start the query for any of the `fetchQuery` methods once their arguments have been converted,
+    /// and write the immediate result in the requested `format`.
+    unsafe fn fetch_query<R: FetchResult>(
+        &self,
+        request: std::result::Result<Request, HRESULT>,
+        next_callback: *mut c_void,
+        options: std::result::Result<(PayloadFormat, QueryOptions), HRESULT>,
+        result: *mut R,
+    ) -> HRESULT {
+        let (request, (format, options)) = match (request, options) {
+            (Ok(request), Ok(options)) => (request, options),
+            (Err(hr), _) | (_, Err(hr)) => return hr,
+        };
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        match self.start_query(
+            &request.query,
+            &request.operation_name,
+            &request.variables,
+            next_callback,
+            format,
+            options,
+        ) {
+            Ok(payload) => {
+                result.write(R::from_payload(format, &payload));
+                S_OK
+            }
+            Err(hr) => hr,
+        }
+    }
+
+    /// Convert the `variables` argument of the `fetchQuery` methods which take a `VARIANT` to a
+    /// JSON string. A string is still passed through as JSON, but any other value is walked into
+    /// JSON.
+    unsafe fn variables_from_variant(variables: &VARIANT) -> std::result::Result<String, HRESULT> {
+        match variant::from_variant(variables) {
+            Ok(variant::Variant::Empty | variant::Variant::Null) => Ok(String::new()),
+            Ok(variant::Variant::String(variables)) => Ok(variables),
+            Ok(variables) => Ok(Value::from(&variables).to_string()),
+            Err(error) => Err(error.code()),
+        }
+    }
+}
+
+impl Default for GraphQLService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for GraphQLService {
+    fn drop(&mut self) {
+        // The last reference may be released on the UI thread, so this only cancels the
+        // subscriptions, and the worker threads are detached. Hosts which need to wait for them, or
+        // to know what was abandoned, should call `shutdown` before releasing it.
+        self.shut_down.store(true, Ordering::Release);
+        let _ = self.service.unsubscribe_all();
+    }
+}
+
+impl_dispatch!(GraphQLService, IGraphQLService);
+
+impl IGraphQLService_Impl for GraphQLService {
+    unsafe fn fetchQuery(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: BSTR,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT {
+        let request = Request::new(query, operation_name, string_argument(variables));
+        let options = Ok((PayloadFormat::Json, QueryOptions::default()));
+        self.fetch_query(request, next_callback, options, result)
+    }
+
+    unsafe fn unsubscribe(&self, key: i32) -> HRESULT {
+        to_hresult(self.service.cancel_subscription(key))
+    }
+
+    unsafe fn getSchema(&self, format: BSTR, result: *mut BSTR) -> HRESULT {
+        let Ok(format) = string_argument(format) else {
+            return E_INVALIDARG;
+        };
+        let Ok(format) = format.parse() else {
+            return E_INVALIDARG;
+        };
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        match self.service.get_schema(format) {
+            Ok(schema) => {
+                *result = BSTR::from(schema.as_str());
+                S_OK
+            }
+            Err(error) => error.into(),
+        }
+    }
+
+    unsafe fn fetchQueryWithVariables(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT {
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        let options = Ok((PayloadFormat::Json, QueryOptions::default()));
+        self.fetch_query(request, next_callback, options, result)
+    }
+
+    unsafe fn fetchQueryAsObject(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT {
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        let options = Ok((PayloadFormat::Object, QueryOptions::default()));
+        self.fetch_query(request, next_callback, options, result)
+    }
+
+    unsafe fn fetchQueryEncoded(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        encoding: BSTR,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT {
+        let encoding = string_argument(encoding);
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        let options = encoding
+            .and_then(|encoding| encoding.parse::<Encoding>().map_err(|()| E_INVALIDARG))
+            .map(|encoding| (PayloadFormat::from(encoding), QueryOptions::default()));
+        self.fetch_query(request, next_callback, options, result)
+    }
+
+    unsafe fn fetchQueryWithTimeout(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        timeout: i32,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT {
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        let options = QueryOptions {
+            timeout: timeout_from_millis(timeout.into()),
+            ..Default::default()
+        };
+        self.fetch_query(
+            request,
+            next_callback,
+            Ok((PayloadFormat::Json, options)),
+            result,
+        )
+    }
+
+    unsafe fn listSubscriptions(&self, result: *mut BSTR) -> HRESULT {
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        match self.service.list_subscriptions() {
+            Ok(subscriptions) => {
+                *result = serialize_results(&subscriptions);
+                S_OK
+            }
+            Err(error) => error.into(),
+        }
+    }
+
+    unsafe fn openSession(&self, result: *mut *mut c_void) -> HRESULT {
+        let Some(result) = result.as_mut() else {
+            return E_POINTER;
+        };
+
+        let session: IGraphQLService =
+            GraphQLService::with_service(self.service.open_session()).into();
+        let Ok(session) = session.cast::<IDispatch>() else {
+            return E_NOINTERFACE;
+        };
+        *result = session.into_raw();
+        S_OK
+    }
+
+    unsafe fn unsubscribeAll(&self) -> HRESULT {
+        to_hresult(self.service.unsubscribe_all().map(|_| ()))
+    }
+
+    unsafe fn shutdown(&self, timeout: i32, result: *mut BSTR) -> HRESULT {
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        let timeout = Duration::from_millis(u64::try_from(timeout).unwrap_or_default());
+        *result = serialize_results(GraphQLService::shutdown(self, timeout));
+        S_OK
+    }
+
+    unsafe fn getMetrics(&self, format: BSTR, result: *mut BSTR) -> HRESULT {
+        let Ok(format) = string_argument(format) else {
+            return E_INVALIDARG;
+        };
+        let Ok(format) = format.parse() else {
+            return E_INVALIDARG;
+        };
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        *result = BSTR::from(metrics::global().format(format).as_str());
+        S_OK
+    }
+
+    unsafe fn fetchQueryWithExtensions(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        extensions: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut BSTR,
+    ) -> HRESULT {
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        // The extensions are converted the same way as the variables.
+        let options = Self::variables_from_variant(&extensions).and_then(|extensions| {
+            QueryOptions::default()
+                .with_extensions(&extensions)
+                .map(|options| (PayloadFormat::Json, options))
+                .map_err(HRESULT::from)
+        });
+        self.fetch_query(request, next_callback, options, result)
+    }
+
+    unsafe fn getSlowOperations(&self, result: *mut BSTR) -> HRESULT {
+        if result.is_null() {
+            return E_POINTER;
+        }
+
+        *result = serialize_results(slow_log::global().entries());
+        S_OK
+    }
+
+    unsafe fn setSlowOperationThresholds(&self, duration: i32, backlog: i32) -> HRESULT {
+        let (Ok(duration), Ok(backlog)) = (u64::try_from(duration), u64::try_from(backlog)) else {
+            return E_INVALIDARG;
+        };
+
+        slow_log::global().configure(SlowLogConfig {
+            threshold: Duration::from_millis(duration),
+            backlog,
+            ..slow_log::global().config()
+        });
+        S_OK
+    }
+
+    unsafe fn fetchQueryWithOptions(
+        &self,
+        query: BSTR,
+        operation_name: BSTR,
+        variables: VARIANT,
+        options: VARIANT,
+        next_callback: *mut c_void,
+        result: *mut VARIANT,
+    ) -> HRESULT {
+        let request = Request::new(
+            query,
+            operation_name,
+            Self::variables_from_variant(&variables),
+        );
+        // The options are converted the same way as the variables.
+        let options = Self::variables_from_variant(&options)
+            .and_then(|options| FetchOptions::parse(&options))
+            .and_then(FetchOptions::into_parts);
+        self.fetch_query(request, next_callback, options, result)
+    }
+}
+
+unsafe fn load_type_lib() -> windows::core::Result<ITypeLib> {
+    let mut buffer: mem::MaybeUninit<[u16; MAX_PATH as usize]> = mem::MaybeUninit::uninit();
+    let count = GetModuleFileNameW(get_module_handle(), &mut *buffer.as_mut_ptr()) as usize;
+    let buffer = buffer.assume_init();
+    if count >= buffer.len() {
+        return Err(ERROR_INSUFFICIENT_BUFFER.into());
+    }
+    let Ok(file_name) = String::from_utf16(&buffer[0..count]) else {
+        return Err(E_UNEXPECTED.into());
+    };
+    let resource_name: Vec<_> = format!("{file_name}\\1")
+        .as_str()
+        .encode_utf16()
+        .chain(iter::once(0_u16))
+        .collect();
+    LoadTypeLibEx(PCWSTR(resource_name.as_ptr()), REGKIND_NONE)
+}
+
+const MODULE_NAME: PCSTR =
+    PCSTR::from_raw(concat!(env!("CARGO_CRATE_NAME"), ".dll", '\0').as_ptr());
+
+fn get_module_handle() -> HMODULE {
+    unsafe { GetModuleHandleA(MODULE_NAME) }.unwrap_or_default()
+}
+
+#[derive(Default)]
+struct UniqueHwnd(Option<HWND>);
+
+impl Drop for UniqueHwnd {
+    fn drop(&mut self) {
+        if let Some(window) = self.0.take() {
+            unsafe {
+                let _ = WindowsAndMessaging::DestroyWindow(window);
+            }
+        }
+    }
+}
+
+const CALLBACK_WINDOW_CLASS_NAME: PCWSTR = w!("NextCallback");
+const DISPATCH_CALLBACKS: u32 = WindowsAndMessaging::WM_USER;
+
+/// The errors from invoking a callback which mean that its script context is gone, rather than
+/// that the script threw (`DISP_E_EXCEPTION`), so only these count toward `MAX_CALLBACK_FAILURES`.
+const DISCONNECTED: [HRESULT; 4] = [
+    RPC_E_DISCONNECTED,
+    CO_E_OBJNOTCONNECTED,
+    RPC_E_SERVER_DIED,
+    RPC_E_SERVERFAULT,
+];
+
+/// The callbacks for each subscription, which live in the hidden window's user data.
+type NextCallbacks = CallbackQueue<(IDispatch, PayloadFormat)>;
+
+/// Wakes the thread which created the hidden window by posting a message to it.
+struct WindowDispatcher {
+    window: Weak<UniqueHwnd>,
+}
+
+impl CallbackDispatcher for WindowDispatcher {
+    fn wake(&self) -> bool {
+        let Some(window) = self.window.upgrade() else {
+            return false;
+        };
+        let Some(window) = window.0 else {
+            return false;
+        };
+        unsafe {
+            WindowsAndMessaging::PostMessageW(window, DISPATCH_CALLBACKS, WPARAM(0), LPARAM(0))
+                .is_ok()
+        }
+    }
+}
+
+struct DeferCallbackQueue {
+    window: Arc<UniqueHwnd>,
+    sender: Mutex<DeliverySender>,
+}
+
+impl DeferCallbackQueue {
+    fn new() -> Self {
+        Self::ensure_message_queue();
+
+        Self::register_window_class();
+
+        let mut sender = None;
+        let window = Arc::new_cyclic(|window| {
+            let (delivery_sender, callbacks) = NextCallbacks::new(Arc::new(WindowDispatcher {
+                window: window.clone(),
+            }));
+            sender = Some(delivery_sender);
+
+            UniqueHwnd(Some(unsafe {
+                WindowsAndMessaging::CreateWindowExW(
+                    WINDOW_EX_STYLE(0),
+                    CALLBACK_WINDOW_CLASS_NAME,
+                    None,
+                    WINDOW_STYLE(0),
+                    0,
+                    0,
+                    0,
+                    0,
+                    HWND::default(),
+                    None,
+                    get_module_handle(),
+                    Some(Box::into_raw(Box::new(callbacks)) as *const _),
+                )
+            }))
+        });
+
+        Self {
+            window,
+            sender: Mutex::new(sender.expect("the window is created with a sender")),
+        }
+    }
+
+    unsafe fn callbacks(&self) -> Option<&mut NextCallbacks> {
+        let window = self.window.0?;
+        let callbacks: *mut NextCallbacks =
+            WindowsAndMessaging::GetWindowLongPtrW(window, GWLP_USERDATA) as *mut _;
+        callbacks.as_mut()
+    }
+
+    unsafe fn add_subscription(
+        &self,
+        next_callback: IDispatch,
+        format: PayloadFormat,
+        subscription: i32,
+    ) {
+        if let Some(callbacks) = self.callbacks() {
+            callbacks.add(subscription, (next_callback, format));
+        }
+    }
+
+    /// Drop any `next` payloads which have not been dispatched yet, and release every callback.
+    /// Returns the number of payloads which were discarded.
+    unsafe fn discard_callbacks(&self) -> u64 {
+        self.callbacks().map_or(0, |callbacks| callbacks.discard())
+    }
+
+    fn ensure_message_queue() {
+        let mut msg = MSG::default();
+        let hwnd = HWND::default();
+        unsafe { PeekMessageW(&mut msg, hwnd, WM_USER, WM_USER, PM_NOREMOVE) };
+    }
+
+    fn get_sender(&self) -> Option<DeliverySender> {
+        self.sender.lock().ok().map(|sender| sender.clone())
+    }
+
+    fn register_window_class() {
+        static REGISTER: Once = Once::new();
+        REGISTER.call_once(|| {
+            let wnd_class = WNDCLASSEXW {
+                cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(Self::window_proc),
+                hInstance: HINSTANCE(get_module_handle().0),
+                lpszClassName: CALLBACK_WINDOW_CLASS_NAME,
+                ..Default::default()
+            };
+
+            unsafe {
+                WindowsAndMessaging::RegisterClassExW(&wnd_class);
+            }
+        })
+    }
+
+    unsafe extern "system" fn window_proc(
+        window: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        match message {
+            WindowsAndMessaging::WM_CREATE => {
+                let create_struct: *const CREATESTRUCTW = lparam.0 as *const _;
+                if !create_struct.is_null() && !(*create_struct).lpCreateParams.is_null() {
+                    WindowsAndMessaging::SetWindowLongPtrW(
+                        window,
+                        GWLP_USERDATA,
+                        (*create_struct).lpCreateParams as _,
+                    );
+
+                    LRESULT(0)
+                } else {
+                    LRESULT(-1)
+                }
+            }
+
+            DISPATCH_CALLBACKS => {
+                let callbacks: *mut NextCallbacks =
+                    WindowsAndMessaging::GetWindowLongPtrW(window, GWLP_USERDATA) as *mut _;
+                if let Some(callbacks) = callbacks.as_mut() {
+                    let dropped = callbacks.deliver(|(next_callback, format), payload| {
+                        let mut rgvarg = [format.to_variant(&payload)];
+                        let params = DISPPARAMS {
+                            rgvarg: rgvarg.as_mut_ptr(),
+                            cArgs: 1,
+                            ..Default::default()
+                        };
+                        const LOCALE_USER_DEFAULT: u32 = 0x400;
+                        let result = next_callback.Invoke(
+                            DISPID_UNKNOWN,
+                            &GUID::default(),
+                            LOCALE_USER_DEFAULT,
+                            DISPATCH_METHOD,
+                            &params as *const _,
+                            None,
+                            None,
+                            None,
+                        );
+                        ClearVariantArray(&mut rgvarg);
+                        result.map_err(|error| {
+                            if DISCONNECTED.contains(&error.code()) {
+                                CallbackError::Disconnected(error)
+                            } else {
+                                CallbackError::Failed(error)
+                            }
+                        })
+                    });
+
+                    // The page which registered these callbacks is probably gone, so there is no
+                    // script left to tell. Leave a trace for whoever is debugging the host.
+                    for dropped in dropped {
+                        OutputDebugStringW(&HSTRING::from(format!(
+                            "dispatch-graphql: dropped the nextCallback for subscription {} after \
+                             {} failures ({}), it was unsubscribed\n",
+                            dropped.subscription, dropped.failures, dropped.error
+                        )));
+                    }
+                }
+                LRESULT(0)
+            }
+
+            WindowsAndMessaging::WM_DESTROY => {
+                let callbacks: *mut NextCallbacks =
+                    WindowsAndMessaging::SetWindowLongPtrW(window, GWLP_USERDATA, 0) as *mut _;
+                if !callbacks.is_null() {
+                    let _ = Box::from_raw(callbacks);
+                }
+                LRESULT(0)
+            }
+
+            _ => WindowsAndMessaging::DefWindowProcW(window, message, wparam, lparam),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Promise and async-iterator wrapper around the `graphql` host object, which hides the
+// JSON.stringify/JSON.parse handling of variables and payloads.
+export function createClient(hostObject = window.chrome.webview.hostObjects.graphql) {
+  const stringifyVariables = (variables) => (variables ? JSON.stringify(variables) : "");
+
+  // Only calls which ask for extensions need the host object method which accepts them.
+  const fetchQuery = (query, operationName, variables, extensions, nextCallback) =>
+    extensions
+      ? hostObject.fetchQueryWithExtensions(
+          query,
+          operationName,
+          stringifyVariables(variables),
+          JSON.stringify(extensions),
+          nextCallback
+        )
+      : hostObject.fetchQuery(query, operationName, stringifyVariables(variables), nextCallback);
+
+  // Merge the extensions of a payload, such as `tracing`, into the result it carries.
+  const withExtensions = (result, extensions) =>
+    extensions ? { ...result, extensions: { ...result.extensions, ...extensions } } : result;
+
+  return {
+    /**
+     * Run a query or mutation and resolve with its results.
+     * @signature query<TData = any>(query: string, options?: QueryOptions): Promise<ExecutionResult<TData>>
+     */
+    async query(query, { operationName = "", variables, extensions } = {}) {
+      let resolveNext;
+      const nextPayload = new Promise((resolve) => {
+        resolveNext = resolve;
+      });
+      const payload = JSON.parse(
+        await fetchQuery(query, operationName, variables, extensions, (next) =>
+          resolveNext(JSON.parse(next))
+        )
+      );
+
+      if ("pending" in payload) {
+        if (payload.subscription) {
+          // The operation may have ended already, in which case there is nothing to unsubscribe.
+          await hostObject.unsubscribe(payload.pending).catch(() => {});
+          throw new Error("Use subscribe() for subscription operations");
+        }
+
+        // A query or mutation which did not complete immediately delivers its results, or an
+        // error if it timed out or was cancelled, as its only `next` payload.
+        const { next, extensions } = await nextPayload;
+        return withExtensions(next, extensions);
+      }
+
+      return withExtensions(payload.results, payload.extensions);
+    },
+
+    /**
+     * Start a subscription and iterate over each result; `return()` unsubscribes.
+     * @signature subscribe<TData = any>(query: string, options?: QueryOptions): AsyncIterableIterator<ExecutionResult<TData>>
+     */
+    subscribe(query, { operationName = "", variables, extensions } = {}) {
+      const buffered = [];
+      const waiting = [];
+      let done = false;
+      let key = null;
+
+      const push = (result) => {
+        const resolve = waiting.shift();
+        if (resolve) {
+          resolve({ value: result, done: false });
+        } else {
+          buffered.push(result);
+        }
+      };
+
+      const finish = () => {
+        done = true;
+        for (const resolve of waiting.splice(0)) {
+          resolve({ value: undefined, done: true });
+        }
+      };
+
+      const started = fetchQuery(query, operationName, variables, extensions, (payload) => {
+        const { next, extensions } = JSON.parse(payload);
+        push(withExtensions(next, extensions));
+      })
+        .then((payload) => {
+          const { results, pending, extensions } = JSON.parse(payload);
+          if (pending === undefined) {
+            // The operation completed synchronously, so there is exactly one result.
+            push(withExtensions(results, extensions));
+            done = true;
+          } else {
+            key = pending;
+          }
+        })
+        .catch((error) => {
+          finish();
+          throw error;
+        });
+
+      return {
+        [Symbol.asyncIterator]() {
+          return this;
+        },
+
+        async next() {
+          await started;
+          if (buffered.length > 0) {
+            return { value: buffered.shift(), done: false };
+          }
+          if (done) {
+            return { value: undefined, done: true };
+          }
+          return new Promise((resolve) => waiting.push(resolve));
+        },
+
+        async return() {
+          await started.catch(() => {});
+          if (key !== null) {
+            // The subscription may have ended already, in which case there is nothing to unsubscribe.
+            await hostObject.unsubscribe(key).catch(() => {});
+            key = null;
+          }
+          buffered.length = 0;
+          finish();
+          return { value: undefined, done: true };
+        },
+      };
+    },
+
+    /**
+     * Get the schema as introspection results (`"json"`) or SDL (`"sdl"`).
+     * @signature getSchema(format?: "json" | "sdl"): Promise<string>
+     */
+    getSchema(format = "json") {
+      return hostObject.getSchema(format);
+    },
+
+    /**
+     * List the active subscriptions and pending queries, to help track down leaks.
+     * @signature listSubscriptions(): Promise<SubscriptionInfo[]>
+     */
+    async listSubscriptions() {
+      return JSON.parse(await hostObject.listSubscriptions());
+    },
+
+    /**
+     * End every subscription started through this client's session.
+     * @signature unsubscribeAll(): Promise<void>
+     */
+    unsubscribeAll() {
+      return hostObject.unsubscribeAll();
+    },
+
+    /**
+     * Open a new session on the same host object, whose subscriptions can be ended together.
+     * @signature openSession(): Promise<GraphQLClient>
+     */
+    async openSession() {
+      return createClient(await hostObject.openSession());
+    },
+
+    /**
+     * Get the counters and histograms for every service in the process.
+     * @signature getMetrics(): Promise<MetricsSnapshot>
+     */
+    async getMetrics() {
+      return JSON.parse(await hostObject.getMetrics("json"));
+    },
+
+    /**
+     * Get the operations which were slow to complete, or whose results piled up, oldest first.
+     * @signature getSlowOperations(): Promise<SlowOperation[]>
+     */
+    async getSlowOperations() {
+      return JSON.parse(await hostObject.getSlowOperations());
+    },
+
+    /**
+     * Record operations which take longer than `duration` ms, or have more than `backlog` results waiting.
+     * @signature setSlowOperationThresholds(duration: number, backlog: number): Promise<void>
+     */
+    setSlowOperationThresholds(duration, backlog) {
+      return hostObject.setSlowOperationThresholds(duration, backlog);
+    },
+  };
+}
@@ -0,0 +1,677 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    mem,
+    sync::{mpsc, Arc, Mutex, Weak},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use serde_json::value::RawValue;
+
+use crate::{
+    metrics,
+    service::{
+        drop_subscription, record_if_slow, ActiveSubscription, Error, NextReceiver, Result,
+        Subscriptions,
+    },
+    slow_log, NextPayload,
+};
+
+/// Wakes the thread which owns the callbacks, so that it drains its [`CallbackQueue`]. This is
+/// called from worker threads, after each delivery has been queued.
+///
+/// The COM host object wakes its thread by posting a message to a hidden window. A caller-supplied
+/// "post to the UI thread" function, e.g. one which spawns `queue.deliver` on a tokio runtime
+/// handle, can be used directly as a dispatcher, and [`ChannelDispatcher`] suits a thread which
+/// runs its own loop.
+pub trait CallbackDispatcher: Send + Sync {
+    /// Ask the owning thread to call [`CallbackQueue::deliver`] soon. Return `false` if it is gone
+    /// and will never deliver anything again.
+    fn wake(&self) -> bool;
+}
+
+impl<F: Fn() -> bool + Send + Sync> CallbackDispatcher for F {
+    fn wake(&self) -> bool {
+        self()
+    }
+}
+
+/// A dispatcher which signals a channel, for an owning thread which blocks on the receiver and
+/// then calls [`CallbackQueue::deliver`].
+pub struct ChannelDispatcher(mpsc::SyncSender<()>);
+
+impl ChannelDispatcher {
+    /// Create the dispatcher and the receiver which the owning thread waits on. Wake-ups are
+    /// coalesced, since a single call to `deliver` drains everything which has been queued.
+    pub fn new() -> (Self, mpsc::Receiver<()>) {
+        let (tx, rx) = mpsc::sync_channel(1);
+        (Self(tx), rx)
+    }
+}
+
+impl CallbackDispatcher for ChannelDispatcher {
+    fn wake(&self) -> bool {
+        !matches!(
+            self.0.try_send(()),
+            Err(mpsc::TrySendError::Disconnected(()))
+        )
+    }
+}
+
+/// How many times in a row a subscription's callback may be disconnected before
+/// [`CallbackQueue::deliver`] gives up on it, e.g. because the script context which registered it
+/// has gone away.
+pub const MAX_CALLBACK_FAILURES: u32 = 3;
+
+/// Why invoking a callback failed, as returned to [`CallbackQueue::deliver`].
+#[derive(Debug)]
+pub enum CallbackError<E> {
+    /// The callback could not be reached, e.g. because the script context which registered it
+    /// has gone away. These count toward [`MAX_CALLBACK_FAILURES`].
+    Disconnected(E),
+    /// The callback was reached but failed, e.g. because the script threw an exception. The
+    /// callback is still there, so this does not count toward [`MAX_CALLBACK_FAILURES`].
+    Failed(E),
+}
+
+/// A callback which was released because it kept failing, as reported by
+/// [`CallbackQueue::deliver`]. Its subscription has already been cancelled.
+#[derive(Debug)]
+pub struct DroppedCallback<E> {
+    pub subscription: i32,
+    /// The number of consecutive failures, including the last one.
+    pub failures: u32,
+    /// The error from the last failure.
+    pub error: E,
+}
+
+/// Subscriptions whose callbacks were dropped, which their workers should stop forwarding.
+type DroppedSubscriptions = Arc<Mutex<BTreeSet<i32>>>;
+
+/// The subscriptions of the service which started each worker, so the owning thread can cancel a
+/// subscription as soon as its callback is dropped. They are weak, so a worker does not keep its
+/// session alive.
+#[derive(Clone, Default)]
+struct Owners(Arc<Mutex<BTreeMap<i32, Owner>>>);
+
+/// A weak reference to the [`Subscriptions`] of a service.
+type Owner = Weak<Mutex<BTreeMap<i32, ActiveSubscription>>>;
+
+impl Owners {
+    fn insert(&self, subscription: i32, subscriptions: &Subscriptions) {
+        if let Ok(mut owners) = self.0.lock() {
+            owners.insert(subscription, Arc::downgrade(subscriptions));
+        }
+    }
+
+    fn remove(&self, subscription: i32) {
+        if let Ok(mut owners) = self.0.lock() {
+            owners.remove(&subscription);
+        }
+    }
+
+    /// Cancel the subscription in the service which started it. The handle is dropped after the
+    /// lock is released, like [`crate::Service::cancel_subscription`].
+    fn cancel(&self, subscription: i32) {
+        let owner = self
+            .0
+            .lock()
+            .ok()
+            .and_then(|mut owners| owners.remove(&subscription))
+            .and_then(|owner| owner.upgrade());
+        let Some(subscriptions) = owner else {
+            return;
+        };
+        let cancelled = subscriptions
+            .lock()
+            .ok()
+            .and_then(|mut subscriptions| subscriptions.remove(&subscription));
+        drop(cancelled);
+    }
+}
+
+/// The number of payloads queued for each subscription's callback which it has not received yet.
+#[derive(Clone, Default)]
+struct Backlogs(Arc<Mutex<BTreeMap<i32, u64>>>);
+
+impl Backlogs {
+    fn get(&self, subscription: i32) -> u64 {
+        self.0
+            .lock()
+            .ok()
+            .and_then(|backlogs| backlogs.get(&subscription).copied())
+            .unwrap_or_default()
+    }
+
+    fn queued(&self, subscription: i32) {
+        if let Ok(mut backlogs) = self.0.lock() {
+            *backlogs.entry(subscription).or_default() += 1;
+        }
+    }
+
+    fn dequeued(&self, subscription: i32) {
+        if let Ok(mut backlogs) = self.0.lock() {
+            if let Some(backlog) = backlogs.get_mut(&subscription) {
+                *backlog = backlog.saturating_sub(1);
+                if *backlog == 0 {
+                    backlogs.remove(&subscription);
+                }
+            }
+        }
+    }
+
+    fn remove(&self, subscription: i32) {
+        if let Ok(mut backlogs) = self.0.lock() {
+            backlogs.remove(&subscription);
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut backlogs) = self.0.lock() {
+            backlogs.clear();
+        }
+    }
+}
+
+/// Something for the owning thread to do with a subscription's callback.
+enum Delivery {
+    /// A payload, and when it was queued.
+    Next(NextPayload, Instant),
+    /// The subscription ended after the payload with this sequence number, or 0 if it had none.
+    Complete(i32, u64),
+}
+
+/// The worker side of a [`CallbackQueue`], which can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct DeliverySender {
+    tx: mpsc::Sender<Delivery>,
+    dispatcher: Arc<dyn CallbackDispatcher>,
+    dropped: DroppedSubscriptions,
+    owners: Owners,
+    backlogs: Backlogs,
+}
+
+impl DeliverySender {
+    /// Queue a `next` payload for the subscription's callback. Sequence numbers start at 1 for
+    /// each subscription. Returns `false` if the owning thread is gone, or if it dropped the
+    /// callback because it kept failing, in which case the subscription should be cancelled.
+    pub fn next(&self, payload: NextPayload) -> bool {
+        if self.is_dropped(payload.subscription) {
+            return false;
+        }
+        let subscription = payload.subscription;
+        metrics::global().payload_queued();
+        self.backlogs.queued(subscription);
+        if self
+            .tx
+            .send(Delivery::Next(payload, Instant::now()))
+            .is_err()
+        {
+            metrics::global().payloads_dequeued(1);
+            self.backlogs.dequeued(subscription);
+            return false;
+        }
+        self.dispatcher.wake()
+    }
+
+    /// Release the subscription's callback, once every payload up to and including `last_sequence`
+    /// has been delivered.
+    pub fn complete(&self, subscription: i32, last_sequence: u64) -> bool {
+        if let Ok(mut dropped) = self.dropped.lock() {
+            dropped.remove(&subscription);
+        }
+        self.owners.remove(subscription);
+        self.send(Delivery::Complete(subscription, last_sequence))
+    }
+
+    /// The number of payloads which have been queued for the subscription's callback, but not
+    /// delivered to it yet.
+    pub fn backlog(&self, subscription: i32) -> u64 {
+        self.backlogs.get(subscription)
+    }
+
+    fn is_dropped(&self, subscription: i32) -> bool {
+        self.dropped
+            .lock()
+            .is_ok_and(|dropped| dropped.contains(&subscription))
+    }
+
+    fn send(&self, delivery: Delivery) -> bool {
+        self.tx.send(delivery).is_ok() && self.dispatcher.wake()
+    }
+}
+
+/// The callbacks for each subscription, which stay on the thread that registered them. Each
+/// callback receives its payloads in sequence order, even if they were queued out of order from
+/// more than one thread, and it is only released after the last of them.
+pub struct CallbackQueue<C> {
+    rx: mpsc::Receiver<Delivery>,
+    callbacks: BTreeMap<i32, Subscription<C>>,
+    dropped: DroppedSubscriptions,
+    owners: Owners,
+    backlogs: Backlogs,
+}
+
+struct Subscription<C> {
+    callback: C,
+    /// The sequence number of the next payload to deliver.
+    sequence: u64,
+    /// Payloads which arrived ahead of `sequence`, and when they were queued.
+    early: BTreeMap<u64, (NextPayload, Instant)>,
+    /// The last sequence number, once the subscription has ended.
+    last_sequence: Option<u64>,
+    /// The number of times in a row that the callback has been disconnected.
+    failures: u32,
+}
+
+impl<C> CallbackQueue<C> {
+    /// Create an empty queue, and the sender which wakes its owner through `dispatcher`.
+    pub fn new(dispatcher: Arc<dyn CallbackDispatcher>) -> (DeliverySender, Self) {
+        let (tx, rx) = mpsc::channel();
+        let dropped = DroppedSubscriptions::default();
+        let owners = Owners::default();
+        let backlogs = Backlogs::default();
+        (
+            DeliverySender {
+                tx,
+                dispatcher,
+                dropped: dropped.clone(),
+                owners: owners.clone(),
+                backlogs: backlogs.clone(),
+            },
+            Self {
+                rx,
+                callbacks: BTreeMap::new(),
+                dropped,
+                owners,
+                backlogs,
+            },
+        )
+    }
+
+    /// Register the callback for a subscription, before its worker starts sending payloads.
+    pub fn add(&mut self, subscription: i32, callback: C) {
+        self.callbacks.insert(
+            subscription,
+            Subscription {
+                callback,
+                sequence: 1,
+                early: BTreeMap::new(),
+                last_sequence: None,
+                failures: 0,
+            },
+        );
+    }
+
+    /// Invoke the callbacks with every payload which has been queued so far, in sequence order.
+    /// A payload which arrived before one of its predecessors is held until the gap is filled.
+    ///
+    /// A callback which is [`CallbackError::Disconnected`] [`MAX_CALLBACK_FAILURES`] times in a
+    /// row is released, and its subscription is cancelled right away. Returns the callbacks which
+    /// were released that way.
+    pub fn deliver<E>(
+        &mut self,
+        mut invoke: impl FnMut(&C, NextPayload) -> std::result::Result<(), CallbackError<E>>,
+    ) -> Vec<DroppedCallback<E>> {
+        let _entered = tracing::debug_span!("dispatch").entered();
+        let mut released = Vec::new();
+        while let Ok(delivery) = self.rx.try_recv() {
+            let key = match delivery {
+                Delivery::Next(payload, queued) => {
+                    let key = payload.subscription;
+                    let Some(subscription) = self
+                        .callbacks
+                        .get_mut(&key)
+                        .filter(|subscription| payload.sequence >= subscription.sequence)
+                    else {
+                        metrics::global().payloads_dequeued(1);
+                        self.backlogs.dequeued(key);
+                        continue;
+                    };
+                    subscription
+                        .early
+                        .insert(payload.sequence, (payload, queued));
+                    key
+                }
+                Delivery::Complete(key, last_sequence) => {
+                    let Some(subscription) = self.callbacks.get_mut(&key) else {
+                        continue;
+                    };
+                    subscription.last_sequence = Some(last_sequence);
+                    key
+                }
+            };
+
+            let Some(subscription) = self.callbacks.get_mut(&key) else {
+                continue;
+            };
+            let mut error = None;
+            while let Some((payload, queued)) = subscription.early.remove(&subscription.sequence) {
+                subscription.sequence += 1;
+                metrics::global().payloads_dequeued(1);
+                self.backlogs.dequeued(key);
+                let span = tracing::debug_span!("callback", key, sequence = payload.sequence);
+                let result = span.in_scope(|| invoke(&subscription.callback, payload));
+                metrics::global().callback_invoked(queued.elapsed(), result.is_ok());
+                match result {
+                    Ok(()) => subscription.failures = 0,
+                    Err(CallbackError::Failed(_)) => {
+                        // The callback was reached, so it is not disconnected.
+                        subscription.failures = 0;
+                        tracing::debug!(key, "the callback failed");
+                    }
+                    Err(CallbackError::Disconnected(last_error)) => {
+                        subscription.failures += 1;
+                        tracing::debug!(
+                            key,
+                            failures = subscription.failures,
+                            "the callback is disconnected"
+                        );
+                        if subscription.failures >= MAX_CALLBACK_FAILURES {
+                            error = Some(last_error);
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Some(error) = error {
+                let failures = subscription.failures;
+                // A subscription which has already ended has nothing left to cancel.
+                if subscription.last_sequence.is_none() {
+                    if let Ok(mut dropped) = self.dropped.lock() {
+                        dropped.insert(key);
+                    }
+                    self.owners.cancel(key);
+                }
+                if let Some(subscription) = self.callbacks.remove(&key) {
+                    metrics::global().payloads_dequeued(subscription.early.len());
+                }
+                self.backlogs.remove(key);
+                tracing::warn!(key, failures, "released a callback which kept failing");
+                released.push(DroppedCallback {
+                    subscription: key,
+                    failures,
+                    error,
+                });
+            } else if subscription
+                .last_sequence
+                .is_some_and(|last_sequence| subscription.sequence > last_sequence)
+            {
+                self.callbacks.remove(&key);
+            }
+        }
+        released
+    }
+
+    /// Release every callback without delivering anything else, and return the number of payloads
+    /// which were discarded.
+    pub fn discard(&mut self) -> u64 {
+        let early: usize = mem::take(&mut self.callbacks)
+            .into_values()
+            .map(|subscription| subscription.early.len())
+            .sum();
+        let queued = self
+            .rx
+            .try_iter()
+            .filter(|delivery| matches!(delivery, Delivery::Next(..)))
+            .count();
+        metrics::global().payloads_dequeued(early + queued);
+        self.backlogs.clear();
+        (early + queued) as u64
+    }
+}
+
+/// Start a worker thread which forwards the results of a pending operation to its callback, then
+/// releases the callback and drops the subscription once the operation ends.
+pub(crate) fn spawn_worker(
+    key: i32,
+    next: NextReceiver,
+    sender: DeliverySender,
+    subscriptions: Subscriptions,
+) -> JoinHandle<Result<()>> {
+    let operation = subscriptions
+        .lock()
+        .ok()
+        .and_then(|subscriptions| {
+            subscriptions
+                .get(&key)
+                .map(|subscription| subscription.operation_name.clone())
+        })
+        .unwrap_or_default();
+    let span = tracing::info_span!("deliver", operation = %operation, key);
+    sender.owners.insert(key, &subscriptions);
+    thread::spawn(move || {
+        let _entered = span.enter();
+        let mut result = Ok(());
+        let mut sequence = 0;
+        let mut backlogged = false;
+        while let Ok(payload) = next.recv() {
+            // Only check that the payload is well-formed, it is passed through as is.
+            let span =
+                tracing::debug_span!("decode", sequence = sequence + 1, bytes = payload.len());
+            let Ok(payload) = span.in_scope(|| RawValue::from_string(payload)) else {
+                tracing::warn!(
+                    sequence = sequence + 1,
+                    "the executor sent a payload which is not JSON"
+                );
+                result = Err(Error::Unexpected);
+                break;
+            };
+            sequence += 1;
+            let payload = NextPayload {
+                next: payload,
+                subscription: key,
+                sequence,
+                extensions: next.extensions(),
+            };
+            if !sender.next(payload) {
+                break;
+            }
+            // Only record the first time the callback falls behind, not every payload after that.
+            if !backlogged {
+                let backlog = sender.backlog(key);
+                backlogged = slow_log::global().exceeds_backlog(backlog);
+                if backlogged {
+                    record_if_slow(key, &subscriptions, Some(backlog));
+                }
+            }
+        }
+        tracing::info!(payloads = sequence, "the operation ended");
+        sender.complete(key, sequence);
+        drop_subscription(key, &subscriptions).and(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::*;
+    use crate::{
+        executor::fixtures::{self, item_added, ITEMS},
+        FakeResponse, Service,
+    };
+
+    const SUBSCRIPTIONS: i32 = 4;
+    const PAYLOADS: u64 = 50;
+    const THREADS: usize = 4;
+
+    fn payload(subscription: i32, sequence: u64) -> NextPayload {
+        NextPayload {
+            next: RawValue::from_string(format!(r#"{{"data":{{"sequence":{sequence}}}}}"#))
+                .unwrap(),
+            subscription,
+            sequence,
+            extensions: None,
+        }
+    }
+
+    /// A Fisher-Yates shuffle with a fixed xorshift seed, so a failure can be reproduced.
+    fn shuffle<T>(items: &mut [T], mut seed: u64) {
+        for index in (1..items.len()).rev() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            items.swap(index, (seed % (index as u64 + 1)) as usize);
+        }
+    }
+
+    fn queue() -> (DeliverySender, CallbackQueue<i32>, mpsc::Receiver<()>) {
+        let (dispatcher, wake) = ChannelDispatcher::new();
+        let (sender, mut queue) = CallbackQueue::new(Arc::new(dispatcher));
+        for subscription in 1..=SUBSCRIPTIONS {
+            queue.add(subscription, subscription);
+        }
+        (sender, queue, wake)
+    }
+
+    #[test]
+    fn delivers_payloads_from_many_threads_in_sequence() {
+        let (sender, mut queue, wake) = queue();
+
+        let mut payloads: Vec<_> = (1..=SUBSCRIPTIONS)
+            .flat_map(|subscription| (1..=PAYLOADS).map(move |sequence| (subscription, sequence)))
+            .collect();
+        shuffle(&mut payloads, 0x2545_f491_4f6c_dd1d);
+        let workers: Vec<_> = payloads
+            .chunks(payloads.len().div_ceil(THREADS))
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    for (subscription, sequence) in chunk {
+                        assert!(sender.next(payload(subscription, sequence)));
+                        thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+
+        let mut delivered: BTreeMap<i32, Vec<u64>> = BTreeMap::new();
+        let expected = SUBSCRIPTIONS as usize * PAYLOADS as usize;
+        while delivered.values().map(Vec::len).sum::<usize>() < expected {
+            wake.recv_timeout(Duration::from_secs(10))
+                .expect("the workers stopped before every payload was delivered");
+            let released = queue.deliver(|&subscription, payload| {
+                assert_eq!(payload.subscription, subscription);
+                assert_eq!(
+                    payload.next.get(),
+                    format!(r#"{{"data":{{"sequence":{}}}}}"#, payload.sequence)
+                );
+                delivered
+                    .entry(subscription)
+                    .or_default()
+                    .push(payload.sequence);
+                Ok::<_, CallbackError<()>>(())
+            });
+            assert!(released.is_empty());
+        }
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let sequences: Vec<u64> = (1..=PAYLOADS).collect();
+        assert_eq!(delivered.len(), SUBSCRIPTIONS as usize);
+        for (subscription, delivered) in delivered {
+            assert_eq!(delivered, sequences, "subscription {subscription}");
+            assert_eq!(sender.backlog(subscription), 0);
+        }
+    }
+
+    fn deliver(queue: &mut CallbackQueue<i32>, delivered: &mut Vec<(i32, u64)>) {
+        queue.deliver(|&subscription, payload| {
+            delivered.push((subscription, payload.sequence));
+            Ok::<_, CallbackError<()>>(())
+        });
+    }
+
+    #[test]
+    fn holds_early_payloads_until_the_gap_is_filled() {
+        let (sender, mut queue, _wake) = queue();
+        let mut delivered = Vec::new();
+
+        assert!(sender.next(payload(1, 3)));
+        assert!(sender.next(payload(1, 2)));
+        assert!(sender.next(payload(2, 1)));
+        deliver(&mut queue, &mut delivered);
+        assert_eq!(delivered, [(2, 1)]);
+        assert_eq!(sender.backlog(1), 2);
+
+        assert!(sender.next(payload(1, 1)));
+        // A duplicate of a payload which was already delivered is discarded.
+        assert!(sender.next(payload(2, 1)));
+        deliver(&mut queue, &mut delivered);
+        assert_eq!(delivered, [(2, 1), (1, 1), (1, 2), (1, 3)]);
+        assert_eq!(sender.backlog(1), 0);
+        assert_eq!(sender.backlog(2), 0);
+    }
+
+    /// Start a subscription with more payloads than [`MAX_CALLBACK_FAILURES`], whose worker
+    /// forwards them to a queue with a callback for it.
+    fn deliver_subscription() -> (
+        Service,
+        i32,
+        CallbackQueue<()>,
+        mpsc::Receiver<()>,
+        JoinHandle<Result<()>>,
+    ) {
+        let mut executor = fixtures::executor();
+        executor.insert(
+            "Items",
+            FakeResponse::Next(
+                (1..=MAX_CALLBACK_FAILURES as usize + 1)
+                    .map(item_added)
+                    .collect(),
+            ),
+        );
+        let service = Service::new(executor);
+        let (key, next) = fixtures::subscribe(&service, ITEMS);
+        let (dispatcher, wake) = ChannelDispatcher::new();
+        let (sender, mut queue) = CallbackQueue::new(Arc::new(dispatcher));
+        queue.add(key, ());
+        let worker = service.deliver_results(key, next, sender);
+        (service, key, queue, wake, worker)
+    }
+
+    #[test]
+    fn cancels_the_subscription_once_the_callback_is_disconnected() {
+        let (service, key, mut queue, wake, worker) = deliver_subscription();
+
+        let mut invoked = 0;
+        let mut released = Vec::new();
+        while released.is_empty() {
+            wake.recv_timeout(Duration::from_secs(10)).unwrap();
+            released = queue.deliver(|_, _| {
+                invoked += 1;
+                Err(CallbackError::Disconnected("disconnected"))
+            });
+        }
+        assert_eq!(invoked, MAX_CALLBACK_FAILURES);
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].subscription, key);
+        assert_eq!(released[0].failures, MAX_CALLBACK_FAILURES);
+
+        // The subscription is cancelled right away, without waiting for another payload.
+        assert!(service.active_subscriptions().is_empty());
+        assert_eq!(worker.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn keeps_callbacks_which_fail_while_connected() {
+        let (service, key, mut queue, wake, worker) = deliver_subscription();
+
+        let mut invoked = 0;
+        while invoked <= MAX_CALLBACK_FAILURES {
+            wake.recv_timeout(Duration::from_secs(10)).unwrap();
+            let released = queue.deliver(|_, _| {
+                invoked += 1;
+                Err(CallbackError::Failed("the script threw"))
+            });
+            assert!(released.is_empty());
+        }
+        assert_eq!(service.active_subscriptions(), vec![key]);
+
+        assert_eq!(service.cancel_subscription(key), Ok(()));
+        assert_eq!(worker.join().unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use crate::service::{Error, Result};
+
+/// How payloads are encoded for callers which negotiate a binary format instead of JSON text.
+/// Binary payloads have the same shape as the JSON payloads, so they decode to the same objects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// UTF-8 JSON text.
+    #[default]
+    Json,
+    /// Concise Binary Object Representation, as described in RFC 8949.
+    Cbor,
+    /// MessagePack, with structs encoded as maps of field names.
+    MessagePack,
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(encoding: &str) -> std::result::Result<Self, Self::Err> {
+        match encoding.to_ascii_lowercase().as_str() {
+            "" | "json" => Ok(Self::Json),
+            "cbor" => Ok(Self::Cbor),
+            "msgpack" | "messagepack" => Ok(Self::MessagePack),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Encoding {
+    /// Encode a payload in this encoding.
+    pub fn encode<T: Serialize + ?Sized>(self, payload: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => serde_json::to_vec(payload).map_err(|_| Error::Unexpected),
+            Self::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(&to_value(payload)?, &mut buffer)
+                    .map_err(|_| Error::Unexpected)?;
+                Ok(buffer)
+            }
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(&to_value(payload)?).map_err(|_| Error::Unexpected)
+            }
+        }
+    }
+}
+
+/// Results are held as raw JSON, which only `serde_json` knows how to serialize, so payloads are
+/// converted to a `Value` before they are handed to another serializer.
+fn to_value<T: Serialize + ?Sized>(payload: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(payload).map_err(|_| Error::Unexpected)
+}
+
+/// Write one binary payload to a stream, prefixed with its length as a 32-bit little-endian
+/// integer, so a reader can split a stream of payloads back into frames.
+pub fn write_frame<W: Write + ?Sized>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame is too large"))?;
+    writer.write_all(&length.to_le_bytes())?;
+    writer.write_all(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, value::RawValue, Value};
+
+    use super::*;
+    use crate::{NextPayload, ResultPayload};
+
+    fn results() -> Box<RawValue> {
+        RawValue::from_string(String::from(
+            r#"{"data":{"items":[{"subject":"Hello","size":1024,"read":false,"score":0.5}]},"errors":null}"#,
+        ))
+        .unwrap()
+    }
+
+    fn payloads() -> Vec<(Value, Vec<u8>, Vec<u8>)> {
+        let result = ResultPayload {
+            results: results(),
+            extensions: None,
+        };
+        let next = NextPayload {
+            next: results(),
+            subscription: 3,
+            sequence: 7,
+            extensions: None,
+        };
+        vec![
+            (
+                serde_json::to_value(&result).unwrap(),
+                Encoding::Cbor.encode(&result).unwrap(),
+                Encoding::MessagePack.encode(&result).unwrap(),
+            ),
+            (
+                serde_json::to_value(&next).unwrap(),
+                Encoding::Cbor.encode(&next).unwrap(),
+                Encoding::MessagePack.encode(&next).unwrap(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn parses_encodings() {
+        assert_eq!("".parse(), Ok(Encoding::Json));
+        assert_eq!("CBOR".parse(), Ok(Encoding::Cbor));
+        assert_eq!("msgpack".parse(), Ok(Encoding::MessagePack));
+        assert_eq!("messagepack".parse(), Ok(Encoding::MessagePack));
+        assert_eq!("bson".parse::<Encoding>(), Err(()));
+    }
+
+    #[test]
+    fn round_trips_raw_results_through_cbor() {
+        for (expected, cbor, _) in payloads() {
+            let decoded: Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn round_trips_raw_results_through_message_pack() {
+        for (expected, _, message_pack) in payloads() {
+            let decoded: Value = rmp_serde::from_slice(&message_pack).unwrap();
+            assert_eq!(decoded, expected);
+        }
+    }
+
+    #[test]
+    fn encodes_results_as_nested_objects() {
+        let (expected, cbor, _) = payloads().remove(1);
+        let decoded: Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(decoded["next"]["data"]["items"][0]["size"], json!(1024));
+        assert_eq!(decoded["sequence"], expected["sequence"]);
+    }
+
+    #[test]
+    fn prefixes_frames_with_their_length() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, b"abc").unwrap();
+        write_frame(&mut stream, b"").unwrap();
+        write_frame(&mut stream, &[0xff; 300]).unwrap();
+
+        assert_eq!(&stream[..7], &[3, 0, 0, 0, b'a', b'b', b'c']);
+        assert_eq!(&stream[7..11], &[0, 0, 0, 0]);
+        assert_eq!(&stream[11..15], &[44, 1, 0, 0]);
+        assert_eq!(stream.len(), 15 + 300);
+    }
+
+    #[test]
+    fn splits_a_stream_back_into_frames() {
+        let frames: Vec<Vec<u8>> = payloads()
+            .into_iter()
+            .flat_map(|(_, cbor, message_pack)| [cbor, message_pack])
+            .collect();
+        let mut stream = Vec::new();
+        for frame in &frames {
+            write_frame(&mut stream, frame).unwrap();
+        }
+
+        let mut rest = stream.as_slice();
+        let mut split = Vec::new();
+        while !rest.is_empty() {
+            let (length, tail) = rest.split_at(4);
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+            split.push(tail[..length].to_vec());
+            rest = &tail[length..];
+        }
+        assert_eq!(split, frames);
+    }
+}
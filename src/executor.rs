@@ -0,0 +1,570 @@
+use std::{collections::BTreeMap, fs, io, path::Path, sync::mpsc};
+
+use graphql_parser::query::{Definition, OperationDefinition};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    recording::ReplayExecutor,
+    service::{Error, Result},
+};
+
+/// Keeps an operation running until it is dropped.
+pub type SubscriptionHandle = Box<dyn Send>;
+
+/// A backend which can execute GraphQL operations for a [`crate::Service`].
+pub trait Executor {
+    /// Parse the query and start executing the operation. Each result is sent as a JSON string
+    /// through `next`, and `complete` is signalled once there will be no more results. Queries and
+    /// mutations should complete before this returns, while subscriptions keep delivering results
+    /// until the returned handle is dropped. Dropping the handle must also drop `complete` if it
+    /// has not been signalled, which is how the service notices that a query was cancelled.
+    fn subscribe(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        next: mpsc::Sender<String>,
+        complete: mpsc::Sender<()>,
+    ) -> Result<SubscriptionHandle>;
+}
+
+impl<T: Executor + ?Sized> Executor for Box<T> {
+    fn subscribe(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        next: mpsc::Sender<String>,
+        complete: mpsc::Sender<()>,
+    ) -> Result<SubscriptionHandle> {
+        (**self).subscribe(query, operation_name, variables, next, complete)
+    }
+}
+
+/// Choose the executor for the command line tools: a [`ReplayExecutor`] loaded from `replay` or a
+/// [`FakeExecutor`] loaded from `fake_responses` if either is specified, otherwise the
+/// [`MapiExecutor`], which requires Windows.
+pub fn from_args(
+    fake_responses: Option<&Path>,
+    replay: Option<&Path>,
+) -> io::Result<Box<dyn Executor>> {
+    match (fake_responses, replay) {
+        (_, Some(replay)) => Ok(Box::new(ReplayExecutor::from_file(replay)?)),
+        (Some(fake_responses), None) => Ok(Box::new(FakeExecutor::from_file(fake_responses)?)),
+        #[cfg(windows)]
+        (None, None) => Ok(Box::new(MapiExecutor::new(true))),
+        #[cfg(not(windows))]
+        (None, None) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "MAPI requires Windows, use --fake <responses.json> or --replay <recording>",
+        )),
+    }
+}
+
+/// The default executor, which runs operations against MAPI with GqlMAPI.
+#[cfg(windows)]
+pub struct MapiExecutor(gqlmapi_rs::MAPIGraphQL);
+
+#[cfg(windows)]
+impl MapiExecutor {
+    pub fn new(use_default_profile: bool) -> Self {
+        Self(gqlmapi_rs::MAPIGraphQL::new(use_default_profile))
+    }
+}
+
+#[cfg(windows)]
+impl Executor for MapiExecutor {
+    fn subscribe(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        next: mpsc::Sender<String>,
+        complete: mpsc::Sender<()>,
+    ) -> Result<SubscriptionHandle> {
+        let Ok(parsed_query) = self.0.parse_query(query) else {
+            return Err(Error::InvalidArgument);
+        };
+
+        let subscription = self.0.subscribe(parsed_query, operation_name, variables);
+
+        {
+            let Ok(mut locked_subscription) = subscription.lock() else {
+                return Err(Error::Unexpected);
+            };
+            if locked_subscription.listen(next, complete).is_err() {
+                return Err(Error::Unexpected);
+            }
+        }
+
+        Ok(Box::new(subscription))
+    }
+}
+
+/// A canned response served by [`FakeExecutor`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FakeResponse {
+    /// Complete immediately with a single result.
+    Results(Value),
+    /// Deliver each of these results as a subscription, which then stays open until it is
+    /// cancelled.
+    Next(Vec<Value>),
+}
+
+/// An executor which serves canned responses by operation name, so the service can be exercised
+/// without MAPI, e.g. on Linux. Include an `IntrospectionQuery` response to support `getSchema`.
+#[derive(Clone, Debug, Default)]
+pub struct FakeExecutor {
+    responses: BTreeMap<String, FakeResponse>,
+}
+
+impl FakeExecutor {
+    pub fn new(responses: BTreeMap<String, FakeResponse>) -> Self {
+        Self { responses }
+    }
+
+    /// Load responses from a JSON file shaped like
+    /// `{ "OperationName": { "results": {...} }, "SubscriptionName": { "next": [...] } }`.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let responses = serde_json::from_str(&fs::read_to_string(path)?)?;
+        Ok(Self::new(responses))
+    }
+
+    pub fn insert(&mut self, operation_name: &str, response: FakeResponse) {
+        self.responses.insert(operation_name.to_string(), response);
+    }
+}
+
+impl Executor for FakeExecutor {
+    fn subscribe(
+        &self,
+        query: &str,
+        operation_name: &str,
+        _variables: &str,
+        next: mpsc::Sender<String>,
+        complete: mpsc::Sender<()>,
+    ) -> Result<SubscriptionHandle> {
+        let operation_name = if operation_name.is_empty() {
+            first_operation_name(query)?
+        } else {
+            operation_name.to_string()
+        };
+
+        match self.responses.get(&operation_name) {
+            Some(FakeResponse::Next(results)) => {
+                for result in results {
+                    let _ = next.send(result.to_string());
+                }
+
+                // Hold onto the senders so the subscription stays open until it is cancelled.
+                Ok(Box::new((next, complete)))
+            }
+            response => {
+                let results = match response {
+                    Some(FakeResponse::Results(results)) => results.clone(),
+                    _ => json!({
+                        "data": null,
+                        "errors": [{
+                            "message": format!("No fake response for operation: {operation_name}")
+                        }]
+                    }),
+                };
+                let _ = next.send(results.to_string());
+                let _ = complete.send(());
+                Ok(Box::new(()))
+            }
+        }
+    }
+}
+
+/// Get the name of the first operation in a document, or an empty string if it is anonymous.
+pub fn first_operation_name(query: &str) -> Result<String> {
+    let document =
+        graphql_parser::parse_query::<String>(query).map_err(|_| Error::InvalidArgument)?;
+    Ok(document
+        .definitions
+        .into_iter()
+        .find_map(|definition| match definition {
+            Definition::Operation(OperationDefinition::Query(query)) => Some(query.name),
+            Definition::Operation(OperationDefinition::Mutation(mutation)) => Some(mutation.name),
+            Definition::Operation(OperationDefinition::Subscription(subscription)) => {
+                Some(subscription.name)
+            }
+            Definition::Operation(OperationDefinition::SelectionSet(_)) => Some(None),
+            Definition::Fragment(_) => None,
+        })
+        .flatten()
+        .unwrap_or_default())
+}
+
+/// Fixtures shared by the tests of every module which needs an executor: a small mail schema,
+/// which the [`FakeExecutor`] from [`fixtures::executor`] serves for introspection, and canned
+/// responses for a few operations against it.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use graphql_parser::schema::{self, Type, TypeDefinition};
+
+    use super::*;
+    use crate::{
+        schema::{Schema, INTROSPECTION_OPERATION_NAME},
+        service::{NextReceiver, QueryResult, Service},
+    };
+
+    pub(crate) const SCHEMA: &str = r#"
+        schema { query: Query mutation: Mutation subscription: Subscription }
+
+        scalar DateTime
+
+        enum Importance { LOW NORMAL HIGH }
+
+        input ObjectId { storeId: ID! objectId: ID! }
+
+        input ItemFilter { importance: Importance unread: Boolean = false }
+
+        interface Node { id: ID! }
+
+        type Store implements Node {
+            id: ID!
+            name: String!
+            folders(ids: [ObjectId!]): [Folder!]!
+        }
+
+        type Folder implements Node {
+            id: ID!
+            name: String!
+            unread: Int!
+            items(first: Int = 10, filter: ItemFilter): [Item!]!
+        }
+
+        type Item implements Node {
+            id: ID!
+            subject: String!
+            importance: Importance!
+            received: DateTime
+        }
+
+        union SearchResult = Folder | Item
+
+        type Query {
+            stores: [Store!]!
+            folder(folderId: ObjectId!): Folder
+            items(first: Int = 10, filter: ItemFilter): [Item!]!
+            search(text: String!): [SearchResult!]!
+        }
+
+        type Mutation {
+            markAsRead(itemId: ObjectId!, read: Boolean = true): Item
+        }
+
+        type Subscription {
+            itemAdded(folderId: ObjectId): Item!
+        }
+    "#;
+
+    /// A query which completes immediately with an empty list of items.
+    pub(crate) const INBOX: &str = "query Inbox { items { subject } }";
+
+    /// A subscription which delivers [`item_added`] for items 1 and 2, then stays open.
+    pub(crate) const ITEMS: &str = "subscription Items { itemAdded { subject } }";
+
+    /// A query which never completes, so it stays pending until it times out or is cancelled.
+    pub(crate) const PENDING: &str = "query Pending { stores { name } }";
+
+    /// The results of [`INBOX`].
+    pub(crate) fn inbox() -> Value {
+        json!({ "data": { "items": [] } })
+    }
+
+    /// The payload delivered by [`ITEMS`] for the item numbered `item`.
+    pub(crate) fn item_added(item: usize) -> Value {
+        json!({ "data": { "itemAdded": { "subject": format!("Item {item}") } } })
+    }
+
+    /// An executor which serves the introspection results for [`SCHEMA`], along with [`INBOX`],
+    /// [`ITEMS`] and [`PENDING`]. More responses can be added with [`FakeExecutor::insert`].
+    pub(crate) fn executor() -> FakeExecutor {
+        let mut executor = FakeExecutor::default();
+        executor.insert(
+            INTROSPECTION_OPERATION_NAME,
+            FakeResponse::Results(introspection()),
+        );
+        executor.insert("Inbox", FakeResponse::Results(inbox()));
+        executor.insert(
+            "Items",
+            FakeResponse::Next((1..=2).map(item_added).collect()),
+        );
+        executor.insert("Pending", FakeResponse::Next(Vec::new()));
+        executor
+    }
+
+    /// Wraps [`executor`], but fails every operation with [`Error::Unexpected`] while `ready` is
+    /// `false`, like an executor which is still starting up.
+    pub(crate) struct StartingExecutor {
+        pub(crate) ready: Arc<AtomicBool>,
+        executor: FakeExecutor,
+    }
+
+    impl StartingExecutor {
+        pub(crate) fn new() -> Self {
+            Self {
+                ready: Arc::new(AtomicBool::new(false)),
+                executor: executor(),
+            }
+        }
+    }
+
+    impl Executor for StartingExecutor {
+        fn subscribe(
+            &self,
+            query: &str,
+            operation_name: &str,
+            variables: &str,
+            next: mpsc::Sender<String>,
+            complete: mpsc::Sender<()>,
+        ) -> Result<SubscriptionHandle> {
+            if !self.ready.load(Ordering::SeqCst) {
+                return Err(Error::Unexpected);
+            }
+            self.executor
+                .subscribe(query, operation_name, variables, next, complete)
+        }
+    }
+
+    /// A service on top of [`executor`].
+    pub(crate) fn service() -> Service {
+        Service::new(executor())
+    }
+
+    /// Start an operation which is expected to be pending, e.g. [`ITEMS`] or [`PENDING`].
+    pub(crate) fn subscribe(service: &Service, query: &str) -> (i32, NextReceiver) {
+        match service.start_query(query, "", "").unwrap() {
+            QueryResult::Pending { key, next } => (key, next),
+            QueryResult::Results(payload) => {
+                panic!("expected a pending operation: {}", payload.results.get())
+            }
+        }
+    }
+
+    /// The schema model for [`SCHEMA`].
+    pub(crate) fn schema() -> Schema {
+        Schema::from_introspection(&introspection()).unwrap()
+    }
+
+    /// The results of the introspection query for [`SCHEMA`], including the built-in scalars and
+    /// directives.
+    pub(crate) fn introspection() -> Value {
+        let document = schema::parse_schema::<String>(SCHEMA).unwrap();
+        let mut roots = json!({});
+        let mut types: Vec<Value> = ["Int", "Float", "String", "Boolean", "ID"]
+            .into_iter()
+            .map(|name| json!({ "kind": "SCALAR", "name": name }))
+            .collect();
+        let definitions: Vec<_> = document
+            .definitions
+            .iter()
+            .filter_map(|definition| match definition {
+                schema::Definition::SchemaDefinition(definition) => {
+                    for (field, name) in [
+                        ("queryType", &definition.query),
+                        ("mutationType", &definition.mutation),
+                        ("subscriptionType", &definition.subscription),
+                    ] {
+                        roots[field] = json!(name.as_ref().map(|name| json!({ "name": name })));
+                    }
+                    None
+                }
+                schema::Definition::TypeDefinition(definition) => Some(definition),
+                _ => None,
+            })
+            .collect();
+
+        for definition in &definitions {
+            types.push(match definition {
+                TypeDefinition::Scalar(scalar) => json!({ "kind": "SCALAR", "name": scalar.name }),
+                TypeDefinition::Object(object) => json!({
+                    "kind": "OBJECT",
+                    "name": object.name,
+                    "fields": object.fields.iter().map(field).collect::<Vec<_>>(),
+                    "interfaces": named_types("INTERFACE", &object.implements_interfaces),
+                }),
+                TypeDefinition::Interface(interface) => {
+                    let implementations: Vec<_> = definitions
+                        .iter()
+                        .filter_map(|definition| match definition {
+                            TypeDefinition::Object(object)
+                                if object.implements_interfaces.contains(&interface.name) =>
+                            {
+                                Some(object.name.clone())
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    json!({
+                        "kind": "INTERFACE",
+                        "name": interface.name,
+                        "fields": interface.fields.iter().map(field).collect::<Vec<_>>(),
+                        "possibleTypes": named_types("OBJECT", &implementations),
+                    })
+                }
+                TypeDefinition::Union(union) => json!({
+                    "kind": "UNION",
+                    "name": union.name,
+                    "possibleTypes": named_types("OBJECT", &union.types),
+                }),
+                TypeDefinition::Enum(enum_type) => json!({
+                    "kind": "ENUM",
+                    "name": enum_type.name,
+                    "enumValues": enum_type
+                        .values
+                        .iter()
+                        .map(|value| json!({ "name": value.name }))
+                        .collect::<Vec<_>>(),
+                }),
+                TypeDefinition::InputObject(input) => json!({
+                    "kind": "INPUT_OBJECT",
+                    "name": input.name,
+                    "inputFields": input.fields.iter().map(input_value).collect::<Vec<_>>(),
+                }),
+            });
+        }
+
+        let condition = json!([{
+            "name": "if",
+            "type": { "kind": "NON_NULL", "ofType": { "kind": "SCALAR", "name": "Boolean" } },
+            "defaultValue": null,
+        }]);
+        roots["types"] = types.into();
+        roots["directives"] = json!([
+            { "name": "skip", "args": condition },
+            { "name": "include", "args": condition },
+        ]);
+        json!({ "data": { "__schema": roots } })
+    }
+
+    fn field(field: &schema::Field<String>) -> Value {
+        json!({
+            "name": field.name,
+            "args": field.arguments.iter().map(input_value).collect::<Vec<_>>(),
+            "type": type_ref(&field.field_type),
+        })
+    }
+
+    fn input_value(input_value: &schema::InputValue<String>) -> Value {
+        json!({
+            "name": input_value.name,
+            "type": type_ref(&input_value.value_type),
+            "defaultValue": input_value.default_value.as_ref().map(ToString::to_string),
+        })
+    }
+
+    fn named_types(kind: &str, names: &[String]) -> Vec<Value> {
+        names
+            .iter()
+            .map(|name| json!({ "kind": kind, "name": name }))
+            .collect()
+    }
+
+    fn type_ref(type_ref: &Type<String>) -> Value {
+        match type_ref {
+            // The kind of a named type is not needed to build the schema model.
+            Type::NamedType(name) => json!({ "kind": "OBJECT", "name": name }),
+            Type::ListType(of_type) => json!({ "kind": "LIST", "ofType": self::type_ref(of_type) }),
+            Type::NonNullType(of_type) => {
+                json!({ "kind": "NON_NULL", "ofType": self::type_ref(of_type) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fixtures::{self, executor, inbox, item_added, INBOX, ITEMS, PENDING},
+        *,
+    };
+
+    fn subscribe(
+        executor: &FakeExecutor,
+        query: &str,
+        operation_name: &str,
+    ) -> (
+        SubscriptionHandle,
+        mpsc::Receiver<String>,
+        mpsc::Receiver<()>,
+    ) {
+        let (next, next_rx) = mpsc::channel();
+        let (complete, complete_rx) = mpsc::channel();
+        let handle = executor
+            .subscribe(query, operation_name, "", next, complete)
+            .unwrap();
+        (handle, next_rx, complete_rx)
+    }
+
+    #[test]
+    fn completes_queries_with_their_results() {
+        let (_handle, next, complete) = subscribe(&executor(), INBOX, "Inbox");
+        assert_eq!(next.try_recv().unwrap(), inbox().to_string());
+        assert!(complete.try_recv().is_ok());
+    }
+
+    #[test]
+    fn finds_the_first_operation_name() {
+        let query = format!("{INBOX} query Other {{ stores {{ name }} }}");
+        let (_handle, next, _complete) = subscribe(&executor(), &query, "");
+        assert_eq!(next.try_recv().unwrap(), inbox().to_string());
+    }
+
+    #[test]
+    fn keeps_subscriptions_open_until_dropped() {
+        let (handle, next, complete) = subscribe(&executor(), ITEMS, "Items");
+        assert_eq!(next.try_recv().unwrap(), item_added(1).to_string());
+        assert_eq!(next.try_recv().unwrap(), item_added(2).to_string());
+        assert_eq!(complete.try_recv(), Err(mpsc::TryRecvError::Empty));
+
+        drop(handle);
+        assert_eq!(complete.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn reports_missing_responses_as_errors() {
+        let (_handle, next, complete) = subscribe(&executor(), "query Missing { x }", "");
+        let results: Value = serde_json::from_str(&next.try_recv().unwrap()).unwrap();
+        assert_eq!(
+            results,
+            json!({
+                "data": null,
+                "errors": [{ "message": "No fake response for operation: Missing" }]
+            })
+        );
+        assert!(complete.try_recv().is_ok());
+    }
+
+    #[test]
+    fn rejects_documents_which_do_not_parse() {
+        let (next, _) = mpsc::channel();
+        let (complete, _) = mpsc::channel();
+        assert!(matches!(
+            executor().subscribe("query {", "", "", next, complete),
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[test]
+    fn serves_the_fixture_schema() {
+        let schema = fixtures::schema();
+        assert_eq!(schema.subscription_type.as_deref(), Some("Subscription"));
+        assert_eq!(schema.possible_types("Node"), ["Store", "Folder", "Item"]);
+
+        let (handle, next, complete) = subscribe(&executor(), PENDING, "");
+        assert_eq!(next.try_recv(), Err(mpsc::TryRecvError::Empty));
+        drop(handle);
+        assert_eq!(complete.try_recv(), Err(mpsc::TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,333 @@
+use std::{collections::HashMap, ffi::c_void, mem, sync::Arc};
+
+use windows::{
+    core::*,
+    Win32::{
+        Foundation::*,
+        System::{Com::*, Ole::*, Variant::*},
+    },
+};
+use windows_implement::implement;
+
+use serde_json::Value;
+
+/// The members of an object start at this DISPID, in enumeration order.
+const FIRST_MEMBER_DISPID: i32 = 1;
+
+/// A JSON value which has been indexed once for script, so reading a member by name or DISPID
+/// never has to search the value again.
+enum Node {
+    Scalar(Value),
+    Array(Vec<Node>),
+    Object(Arc<Object>),
+}
+
+/// The members of a JSON object in enumeration order, and the DISPID of each name.
+struct Object {
+    members: Vec<(String, Node)>,
+    ids: HashMap<String, i32>,
+}
+
+/// A read-only script object backed by a JSON object. It implements `IDispatchEx`, so script can
+/// enumerate its members, e.g. with `Object.keys` or `for...in`. Nested objects share the same
+/// index, and arrays are copied into a `SAFEARRAY`, which script receives as a real array.
+#[implement(IDispatchEx)]
+struct JsonDispatch {
+    object: Arc<Object>,
+}
+
+/// Convert a JSON value to a `VARIANT`. Objects become `VT_DISPATCH` objects and arrays become
+/// `VT_ARRAY | VT_VARIANT`; numbers become `VT_I4` if they fit, otherwise `VT_R8`.
+pub(crate) fn to_variant(value: Value) -> VARIANT {
+    Node::from(value).to_variant()
+}
+
+impl From<Value> for Node {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Array(items) => Self::Array(items.into_iter().map(Node::from).collect()),
+            Value::Object(properties) => {
+                let members: Vec<_> = properties
+                    .into_iter()
+                    .map(|(name, value)| (name, Node::from(value)))
+                    .collect();
+                let ids = (FIRST_MEMBER_DISPID..)
+                    .zip(members.iter())
+                    .map(|(id, (name, _))| (name.clone(), id))
+                    .collect();
+                Self::Object(Arc::new(Object { members, ids }))
+            }
+            value => Self::Scalar(value),
+        }
+    }
+}
+
+impl Node {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Scalar(value) => value.clone(),
+            Self::Array(items) => Value::Array(items.iter().map(Node::to_json).collect()),
+            Self::Object(object) => object.to_json(),
+        }
+    }
+
+    fn to_variant(&self) -> VARIANT {
+        let mut variant = VARIANT::default();
+        unsafe {
+            let data = &mut *variant.Anonymous.Anonymous;
+            match self {
+                Self::Scalar(Value::Bool(value)) => {
+                    data.vt = VT_BOOL;
+                    data.Anonymous.boolVal = if *value { VARIANT_TRUE } else { VARIANT_FALSE };
+                }
+                Self::Scalar(Value::Number(number)) => {
+                    match number.as_i64().and_then(|n| i32::try_from(n).ok()) {
+                        Some(number) => {
+                            data.vt = VT_I4;
+                            data.Anonymous.lVal = number;
+                        }
+                        None => {
+                            data.vt = VT_R8;
+                            data.Anonymous.dblVal = number.as_f64().unwrap_or(f64::NAN);
+                        }
+                    }
+                }
+                Self::Scalar(Value::String(value)) => {
+                    data.vt = VT_BSTR;
+                    data.Anonymous.bstrVal = mem::ManuallyDrop::new(BSTR::from(value.as_str()));
+                }
+                Self::Scalar(_) => data.vt = VT_NULL,
+                Self::Array(items) => {
+                    if let Some(array) = safe_array(items) {
+                        data.vt = VARENUM(VT_ARRAY.0 | VT_VARIANT.0);
+                        data.Anonymous.parray = array;
+                    }
+                }
+                Self::Object(object) => {
+                    let dispatch: IDispatchEx = JsonDispatch {
+                        object: object.clone(),
+                    }
+                    .into();
+                    if let Ok(dispatch) = dispatch.cast::<IDispatch>() {
+                        data.vt = VT_DISPATCH;
+                        data.Anonymous.pdispVal = mem::ManuallyDrop::new(Some(dispatch));
+                    }
+                }
+            }
+        }
+        variant
+    }
+}
+
+impl Object {
+    fn to_json(&self) -> Value {
+        Value::Object(
+            self.members
+                .iter()
+                .map(|(name, node)| (name.clone(), node.to_json()))
+                .collect(),
+        )
+    }
+}
+
+/// Copy the items of an array into a new `SAFEARRAY` of `VARIANT`s.
+unsafe fn safe_array(items: &[Node]) -> Option<*mut SAFEARRAY> {
+    let array = SafeArrayCreateVector(VT_VARIANT, 0, u32::try_from(items.len()).ok()?);
+    if array.is_null() {
+        return None;
+    }
+    for (index, item) in (0..).zip(items) {
+        // The array makes its own copy of each element.
+        let mut element = item.to_variant();
+        let result = SafeArrayPutElement(array, &index, &element as *const _ as *const c_void);
+        let _ = VariantClear(&mut element);
+        if result.is_err() {
+            let _ = SafeArrayDestroy(array);
+            return None;
+        }
+    }
+    Some(array)
+}
+
+impl JsonDispatch {
+    fn member(&self, id: i32) -> Option<&(String, Node)> {
+        let index = usize::try_from(id.checked_sub(FIRST_MEMBER_DISPID)?).ok()?;
+        self.object.members.get(index)
+    }
+
+    fn get_id(&self, name: &str, case_insensitive: bool) -> Option<i32> {
+        match self.object.ids.get(name) {
+            Some(&id) => Some(id),
+            None if case_insensitive => self
+                .object
+                .members
+                .iter()
+                .position(|(member, _)| member.eq_ignore_ascii_case(name))
+                .and_then(|index| i32::try_from(index).ok())
+                .and_then(|index| index.checked_add(FIRST_MEMBER_DISPID)),
+            None => None,
+        }
+    }
+
+    fn get_property(
+        &self,
+        id: i32,
+        flags: DISPATCH_FLAGS,
+        result: *mut VARIANT,
+    ) -> windows::core::Result<()> {
+        if flags.0 & (DISPATCH_PROPERTYGET.0 | DISPATCH_METHOD.0) == 0 {
+            return Err(DISP_E_MEMBERNOTFOUND.into());
+        }
+        if result.is_null() {
+            return Err(E_POINTER.into());
+        }
+
+        let value = if id == DISPID_VALUE as i32 {
+            // The default value is the JSON text, e.g. for `String(object)` in script.
+            Node::Scalar(Value::String(self.object.to_json().to_string())).to_variant()
+        } else {
+            let (_, node) = self
+                .member(id)
+                .ok_or_else(|| Error::from(DISP_E_MEMBERNOTFOUND))?;
+            node.to_variant()
+        };
+        unsafe {
+            *result = value;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+impl IDispatch_Impl for JsonDispatch {
+    fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
+        Ok(0)
+    }
+
+    fn GetTypeInfo(&self, _itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
+        Err(E_NOTIMPL.into())
+    }
+
+    fn GetIDsOfNames(
+        &self,
+        _riid: *const windows::core::GUID,
+        rgsznames: *const windows::core::PCWSTR,
+        cnames: u32,
+        _lcid: u32,
+        rgdispid: *mut i32,
+    ) -> windows::core::Result<()> {
+        if rgsznames.is_null() || rgdispid.is_null() || cnames == 0 {
+            return Err(E_POINTER.into());
+        }
+
+        unsafe {
+            // Only the member name is supported, so any argument names are unknown.
+            for index in 0..cnames as usize {
+                *rgdispid.add(index) = DISPID_UNKNOWN;
+            }
+            let name = (*rgsznames)
+                .to_string()
+                .map_err(|_| Error::from(E_INVALIDARG))?;
+            match self.get_id(&name, false) {
+                Some(id) if cnames == 1 => {
+                    *rgdispid = id;
+                    Ok(())
+                }
+                Some(id) => {
+                    *rgdispid = id;
+                    Err(DISP_E_UNKNOWNNAME.into())
+                }
+                None => Err(DISP_E_UNKNOWNNAME.into()),
+            }
+        }
+    }
+
+    fn Invoke(
+        &self,
+        dispidmember: i32,
+        _riid: *const windows::core::GUID,
+        _lcid: u32,
+        wflags: DISPATCH_FLAGS,
+        _pdispparams: *const DISPPARAMS,
+        pvarresult: *mut VARIANT,
+        _pexcepinfo: *mut EXCEPINFO,
+        _puargerr: *mut u32,
+    ) -> windows::core::Result<()> {
+        self.get_property(dispidmember, wflags, pvarresult)
+    }
+}
+
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+impl IDispatchEx_Impl for JsonDispatch {
+    fn GetDispID(&self, bstrname: &BSTR, grfdex: u32) -> windows::core::Result<i32> {
+        let name = String::from_utf16(bstrname.as_wide()).map_err(|_| Error::from(E_INVALIDARG))?;
+        self.get_id(&name, grfdex & fdexNameCaseInsensitive as u32 != 0)
+            .ok_or_else(|| Error::from(DISP_E_UNKNOWNNAME))
+    }
+
+    fn InvokeEx(
+        &self,
+        id: i32,
+        _lcid: u32,
+        wflags: u16,
+        _pdp: *const DISPPARAMS,
+        pvarres: *mut VARIANT,
+        _pei: *mut EXCEPINFO,
+        _pspcaller: Option<&IServiceProvider>,
+    ) -> windows::core::Result<()> {
+        self.get_property(id, DISPATCH_FLAGS(wflags), pvarres)
+    }
+
+    fn DeleteMemberByName(&self, _bstrname: &BSTR, _grfdex: u32) -> windows::core::Result<()> {
+        // The object is read-only, so its members can't be deleted.
+        Err(S_FALSE.into())
+    }
+
+    fn DeleteMemberByDispID(&self, _id: i32) -> windows::core::Result<()> {
+        Err(S_FALSE.into())
+    }
+
+    fn GetMemberProperties(
+        &self,
+        id: i32,
+        grfdexfetch: u32,
+    ) -> windows::core::Result<FDEX_PROP_FLAGS> {
+        if self.member(id).is_none() {
+            return Err(DISP_E_UNKNOWNNAME.into());
+        }
+        let properties = [
+            fdexPropCanGet,
+            fdexPropCannotPut,
+            fdexPropCannotPutRef,
+            fdexPropNoSideEffects,
+            fdexPropCannotCall,
+            fdexPropCannotConstruct,
+            fdexPropCannotSourceEvents,
+        ]
+        .iter()
+        .fold(0, |properties, flag| properties | flag.0);
+        Ok(FDEX_PROP_FLAGS(properties & grfdexfetch))
+    }
+
+    fn GetMemberName(&self, id: i32) -> windows::core::Result<BSTR> {
+        let (name, _) = self
+            .member(id)
+            .ok_or_else(|| Error::from(DISP_E_UNKNOWNNAME))?;
+        Ok(BSTR::from(name.as_str()))
+    }
+
+    fn GetNextDispID(&self, _grfdex: u32, id: i32) -> windows::core::Result<i32> {
+        let next = if id == DISPID_STARTENUM {
+            Some(FIRST_MEMBER_DISPID)
+        } else {
+            self.member(id).and(id.checked_add(1))
+        };
+        // S_FALSE ends the enumeration.
+        next.filter(|&next| self.member(next).is_some())
+            .ok_or_else(|| Error::from(S_FALSE))
+    }
+
+    fn GetNameSpaceParent(&self) -> windows::core::Result<IUnknown> {
+        Err(E_NOTIMPL.into())
+    }
+}
@@ -1,556 +1,286 @@
 #![allow(non_snake_case)]
 
-use std::{
-    cell::UnsafeCell,
-    collections::BTreeMap,
-    ffi::c_void,
-    iter, mem,
-    sync::{
-        atomic::{AtomicI32, Ordering},
-        mpsc, Arc, Mutex, Once, Weak,
-    },
-    thread,
+use serde::Serialize;
+use serde_json::{value::RawValue, Value};
+
+#[macro_use]
+mod typings;
+
+pub mod codegen;
+pub mod dispatcher;
+pub mod encoding;
+pub mod executor;
+pub mod metrics;
+pub mod recording;
+pub mod schema;
+mod service;
+pub mod slow_log;
+mod timing;
+#[cfg(feature = "trace-log")]
+pub mod trace;
+pub mod utf16;
+pub mod validation;
+pub mod variant;
+
+#[cfg(windows)]
+mod com;
+#[cfg(windows)]
+mod json_dispatch;
+
+#[cfg(windows)]
+pub use com::{CreateService, GraphQLService, IGraphQLService};
+pub use dispatcher::{
+    CallbackDispatcher, CallbackError, CallbackQueue, ChannelDispatcher, DeliverySender,
+    DroppedCallback, MAX_CALLBACK_FAILURES,
 };
-
-use windows::{
-    core::*,
-    Win32::{
-        Foundation::*,
-        System::{Com::*, LibraryLoader::*, Ole::*, Variant::*},
-        UI::WindowsAndMessaging::{
-            self, PeekMessageW, CREATESTRUCTW, GWLP_USERDATA, MSG, PM_NOREMOVE, WINDOW_EX_STYLE,
-            WINDOW_STYLE, WM_USER, WNDCLASSEXW,
-        },
-    },
+pub use encoding::Encoding;
+pub use executor::{Executor, FakeExecutor, FakeResponse};
+pub use metrics::{Metrics, MetricsFormat};
+pub use recording::{Recorder, ReplayExecutor};
+pub use schema::SchemaFormat;
+pub use service::{
+    is_subscription, Error, NextReceiver, QueryOptions, QueryResult, Result, Service,
 };
-use windows_implement::implement;
-use windows_interface::interface;
-
-use serde::Serialize;
-use serde_json::Value;
-
-use gqlmapi_rs::*;
-
-macro_rules! impl_dispatch {
-    ($type:ident, $interface:ident) => {
-        #[allow(clippy::not_unsafe_ptr_arg_deref)]
-        impl IDispatch_Impl for $type {
-            fn GetTypeInfoCount(&self) -> windows::core::Result<u32> {
-                Ok(1)
-            }
-
-            fn GetTypeInfo(&self, itinfo: u32, _lcid: u32) -> windows::core::Result<ITypeInfo> {
-                if itinfo != 0 {
-                    TYPE_E_ELEMENTNOTFOUND.ok()?;
-                }
-
-                unsafe {
-                    let type_lib = match &mut *self.type_lib.get() {
-                        Some(type_lib) => type_lib.clone(),
-                        None => {
-                            let type_lib = load_type_lib()?;
-                            *self.type_lib.get() = Some(type_lib.clone());
-                            type_lib
-                        }
-                    };
-
-                    type_lib.GetTypeInfoOfGuid(&<$interface as ComInterface>::IID)
-                }
-            }
-
-            fn GetIDsOfNames(
-                &self,
-                _riid: *const windows::core::GUID,
-                rgsznames: *const windows::core::PCWSTR,
-                cnames: u32,
-                lcid: u32,
-                rgdispid: *mut i32,
-            ) -> windows::core::Result<()> {
-                let type_info = self.GetTypeInfo(0, lcid)?;
-                unsafe { type_info.GetIDsOfNames(rgsznames, cnames, rgdispid) }
-            }
-
-            fn Invoke(
-                &self,
-                dispidmember: i32,
-                _riid: *const windows::core::GUID,
-                lcid: u32,
-                wflags: DISPATCH_FLAGS,
-                pdispparams: *const DISPPARAMS,
-                pvarresult: *mut VARIANT,
-                pexcepinfo: *mut EXCEPINFO,
-                puargerr: *mut u32,
-            ) -> windows::core::Result<()> {
-                let type_info = self.GetTypeInfo(0, lcid)?;
-                unsafe {
-                    let this: $interface = self.cast()?;
-                    type_info.Invoke(
-                        this.as_raw(),
-                        dispidmember,
-                        wflags,
-                        pdispparams as *mut _,
-                        pvarresult,
-                        pexcepinfo,
-                        puargerr,
-                    )
-                }
-            }
-        }
-    };
-}
-
-/// External entry point to create an IDispatch object for the service. If this function succeeds,
-/// the caller is responsible for calling `Release` on the `IDispatch` interface pointer returned
-/// through the `result` out-param. The initial value that `result` points to must be `null`.
-///
-/// # Safety
-///
-/// The `result` parameter must be a valid pointer.
-#[no_mangle]
-pub unsafe extern "C" fn CreateService(result: *mut *mut c_void) -> HRESULT {
-    // Parameter validation
-    let Some(result) = result.as_mut() else {
-        return E_POINTER;
-    };
-    if !result.is_null() {
-        return E_INVALIDARG;
+pub use slow_log::{SlowLog, SlowLogConfig};
+pub use typings::{typescript_definitions, CLIENT_SCRIPT};
+
+/// A self-contained GraphiQL-style explorer page, wired to the `graphql` host object. Hosts can
+/// show it with `ICoreWebView2::NavigateToString` after calling `AddHostObjectToScript`.
+pub const EXPLORER_HTML: &str = include_str!("explorer.html");
+
+typescript_interface! {
+    #[derive(Serialize)]
+    pub struct ResultPayload {
+        pub results: Box<RawValue> => "ExecutionResult",
+        /// Only present if the call asked for an extension, such as `tracing`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub extensions: Option<PayloadExtensions>,
     }
-
-    let service: IGraphQLService = GraphQLService::new().into();
-    let Ok(service) = service.cast::<IDispatch>() else {
-        return E_NOINTERFACE;
-    };
-    *result = service.into_raw();
-    S_OK
-}
-
-#[derive(Serialize)]
-struct ResultPayload {
-    results: Value,
-}
-
-#[derive(Serialize)]
-struct PendingPayload {
-    pending: i32,
 }
 
-#[derive(Serialize)]
-struct NextPayload {
-    next: Value,
-    subscription: i32,
+typescript_interface! {
+    #[derive(Serialize)]
+    pub struct PendingPayload {
+        pub pending: i32,
+        /// `false` for a query or mutation which has not completed yet, whose results arrive as
+        /// its only `next` payload.
+        pub subscription: bool,
+    }
 }
 
-fn serialize_results<T: Serialize>(payload: T) -> BSTR {
-    serde_json::to_string(&payload)
-        .map_err(|_| ())
-        .and_then(|payload| {
-            let payload: Vec<_> = payload
-                .as_str()
-                .encode_utf16()
-                .chain(iter::once(0_u16))
-                .collect();
-            BSTR::from_wide(&payload).map_err(|_| ())
-        })
-        .unwrap_or_default()
+typescript_interface! {
+    #[derive(Serialize)]
+    pub struct NextPayload {
+        pub next: Box<RawValue> => "ExecutionResult",
+        pub subscription: i32,
+        /// Counts up from 1 for each payload of a subscription, which always arrive in order.
+        pub sequence: u64,
+        /// Only present if the call asked for an extension, such as `tracing`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub extensions: Option<PayloadExtensions>,
+    }
 }
 
-#[interface("FA294686-DB83-4268-A84F-157012D56033")]
-pub unsafe trait IGraphQLService: IDispatch {
-    fn fetchQuery(
-        &self,
-        query: BSTR,
-        operation_name: BSTR,
-        variables: BSTR,
-        next_callback: *mut c_void,
-        result: *mut BSTR,
-    ) -> HRESULT;
-    fn unsubscribe(&self, key: i32) -> HRESULT;
+typescript_interface! {
+    /// The extensions which were requested with `fetchQueryWithExtensions`.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct PayloadExtensions {
+        pub tracing: Tracing,
+    }
 }
 
-#[implement(IGraphQLService, IDispatch)]
-pub struct GraphQLService {
-    type_lib: UnsafeCell<Option<ITypeLib>>,
-    gqlmapi: MAPIGraphQL,
-    next_subscription: AtomicI32,
-    subscriptions: Arc<Mutex<BTreeMap<i32, Mutex<Subscription>>>>,
-    dispatch_queue: DeferCallbackQueue,
+typescript_interface! {
+    /// Timing for an operation in the Apollo Tracing format. `startTime` and `endTime` are RFC 3339
+    /// timestamps in UTC, and `duration` is in nanoseconds. For a `next` payload, they end when that
+    /// result arrived. The phases are missing if there was no schema to validate against.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct Tracing {
+        pub version: i32,
+        pub startTime: String,
+        pub endTime: String,
+        pub duration: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub parsing: Option<TracingPhase>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub validation: Option<TracingPhase>,
+        pub execution: TracingExecution,
+    }
 }
 
-impl GraphQLService {
-    pub fn new() -> Self {
-        Self {
-            type_lib: UnsafeCell::new(None),
-            gqlmapi: MAPIGraphQL::new(true),
-            next_subscription: AtomicI32::new(1),
-            subscriptions: Arc::new(Mutex::new(BTreeMap::new())),
-            dispatch_queue: DeferCallbackQueue::new(),
-        }
+typescript_interface! {
+    /// The execution phase of [`Tracing`]. The executor resolves the whole operation at once, so
+    /// `resolvers` is always empty, and `startOffset` and `duration` cover starting the operation,
+    /// and for a query which completed immediately, waiting for its results. They are 0 if the
+    /// request failed validation.
+    #[derive(Clone, Debug, Default, Serialize)]
+    pub struct TracingExecution {
+        pub startOffset: u64,
+        pub duration: u64,
+        pub resolvers: Vec<Value>,
     }
 }
 
-impl Default for GraphQLService {
-    fn default() -> Self {
-        Self::new()
+typescript_interface! {
+    /// When a phase of an operation started, as an offset from `startTime`, and how long it took,
+    /// both in nanoseconds.
+    #[derive(Clone, Copy, Debug, Default, Serialize)]
+    pub struct TracingPhase {
+        pub startOffset: u64,
+        pub duration: u64,
     }
 }
 
-impl_dispatch!(GraphQLService, IGraphQLService);
-
-impl IGraphQLService_Impl for GraphQLService {
-    unsafe fn fetchQuery(
-        &self,
-        query: BSTR,
-        operation_name: BSTR,
-        variables: BSTR,
-        next_callback: *mut c_void,
-        result: *mut BSTR,
-    ) -> HRESULT {
-        // The caller (WebView2) retains ownership of these BSTRs, so suppress the drop destructor
-        // on the windows::core::BSTR arguments constructed by the generated IGraphQLService impl.
-        let (query, operation_name, variables) = (
-            mem::ManuallyDrop::new(query),
-            mem::ManuallyDrop::new(operation_name),
-            mem::ManuallyDrop::new(variables),
-        );
-        let (Ok(query), Ok(operation_name), Ok(variables)) = (
-            String::from_utf16(query.as_wide()),
-            String::from_utf16(operation_name.as_wide()),
-            String::from_utf16(variables.as_wide()),
-        ) else {
-            return E_INVALIDARG;
-        };
-        if next_callback.is_null() {
-            return E_INVALIDARG;
-        }
-        let raw = IDispatch::from_raw(next_callback);
-        let next_callback = raw.clone();
-        mem::forget(raw);
-        let Ok(parsed_query) = self.gqlmapi.parse_query(&query) else {
-            return E_INVALIDARG;
-        };
-
-        let (tx_next, rx_next) = mpsc::channel();
-        let (tx_complete, rx_complete) = mpsc::channel();
-
-        let (key, subscriptions) = {
-            let subscription =
-                self.gqlmapi
-                    .subscribe(parsed_query.clone(), &operation_name, &variables);
-
-            {
-                let Ok(mut locked_subscription) = subscription.lock() else {
-                    return E_UNEXPECTED;
-                };
-                if locked_subscription.listen(tx_next, tx_complete).is_err() {
-                    return E_UNEXPECTED;
-                }
-            }
-
-            let Ok(mut subscriptions) = self.subscriptions.lock() else {
-                return E_UNEXPECTED;
-            };
-            let key: i32 = self.next_subscription.fetch_add(1, Ordering::Relaxed);
-            subscriptions.insert(key, subscription);
-
-            (key, self.subscriptions.clone())
-        };
-
-        match rx_complete.try_recv() {
-            Ok(()) => {
-                let Ok(results) = rx_next.recv() else {
-                    return E_UNEXPECTED;
-                };
-                let Ok(results) = serde_json::from_str(&results) else {
-                    return E_UNEXPECTED;
-                };
-                *result = serialize_results(&ResultPayload { results });
-                drop_subscription(key, &subscriptions)
-            }
-            Err(_) => {
-                let Some(dispatcher) = self.dispatch_queue.get_dispatcher() else {
-                    return E_UNEXPECTED;
-                };
-                self.dispatch_queue.add_subscription(next_callback, key);
-                thread::spawn::<_, HRESULT>(move || {
-                    while let Ok(next) = rx_next.recv() {
-                        let Ok(next) = serde_json::from_str(&next) else {
-                            return E_UNEXPECTED;
-                        };
-                        dispatcher.dispatch(next, key);
-                    }
-                    dispatcher.remove_callback(key);
-                    drop_subscription(key, &subscriptions)
-                });
-
-                *result = serialize_results(PendingPayload { pending: key });
-                S_OK
-            }
-        }
+typescript_interface! {
+    /// What `shutdown` did: the keys of the subscriptions it cancelled, the keys whose worker
+    /// threads had not finished by the deadline, and how many `next` payloads were still queued for
+    /// callbacks and were discarded.
+    #[derive(Clone, Debug, Default, Serialize)]
+    pub struct ShutdownReport {
+        pub cancelled: Vec<i32>,
+        pub abandoned: Vec<i32>,
+        pub discarded: u64,
     }
+}
 
-    unsafe fn unsubscribe(&self, key: i32) -> HRESULT {
-        drop_subscription(key, &self.subscriptions)
+typescript_interface! {
+    /// An entry in the results of `listSubscriptions`. The times are in milliseconds since the Unix
+    /// epoch, and `delivered` is missing until the first `next` result has been delivered.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct SubscriptionInfo {
+        pub key: i32,
+        pub operation: String,
+        pub variables: Box<RawValue> => "Record<string, unknown>",
+        pub created: u64,
+        pub deliveries: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delivered: Option<u64>,
     }
 }
 
-unsafe fn load_type_lib() -> windows::core::Result<ITypeLib> {
-    let mut buffer: mem::MaybeUninit<[u16; MAX_PATH as usize]> = mem::MaybeUninit::uninit();
-    let count = GetModuleFileNameW(get_module_handle(), &mut *buffer.as_mut_ptr()) as usize;
-    let buffer = buffer.assume_init();
-    if count >= buffer.len() {
-        return Err(ERROR_INSUFFICIENT_BUFFER.into());
+typescript_interface! {
+    /// A histogram in `getMetrics`, in seconds. `buckets[i]` counts the observations which took
+    /// no longer than `bounds[i]`, and `count` also includes those which took longer than that.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct HistogramSnapshot {
+        pub bounds: Vec<f64>,
+        pub buckets: Vec<u64>,
+        pub count: u64,
+        pub sum: f64,
     }
-    let Ok(file_name) = String::from_utf16(&buffer[0..count]) else {
-        return Err(E_UNEXPECTED.into());
-    };
-    let resource_name: Vec<_> = format!("{file_name}\\1")
-        .as_str()
-        .encode_utf16()
-        .chain(iter::once(0_u16))
-        .collect();
-    LoadTypeLibEx(PCWSTR(resource_name.as_ptr()), REGKIND_NONE)
 }
 
-fn drop_subscription(
-    key: i32,
-    subscriptions: &Mutex<BTreeMap<i32, Mutex<Subscription>>>,
-) -> HRESULT {
-    let Ok(mut subscriptions) = subscriptions.lock() else {
-        return E_UNEXPECTED;
-    };
-    subscriptions.remove(&key);
-    S_OK
+typescript_interface! {
+    /// The results of `getMetrics`, for every service in the process. `operations` counts those
+    /// started by the executor, `rejected` those which failed validation, and `errors` those the
+    /// executor failed to start. `active` and `queued` are the subscriptions and pending queries
+    /// which are still running, and the `next` payloads waiting for their callbacks.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct MetricsSnapshot {
+        pub operations: u64,
+        pub rejected: u64,
+        pub errors: u64,
+        pub active: u64,
+        pub deliveries: u64,
+        pub failures: u64,
+        pub queued: u64,
+        /// How long operations took to validate and start.
+        pub duration: HistogramSnapshot,
+        /// How long from queueing each `next` payload until its callback returned.
+        pub latency: HistogramSnapshot,
+    }
 }
 
-const MODULE_NAME: PCSTR =
-    PCSTR::from_raw(concat!(env!("CARGO_CRATE_NAME"), ".dll", '\0').as_ptr());
-
-fn get_module_handle() -> HMODULE {
-    unsafe { GetModuleHandleA(MODULE_NAME) }.unwrap_or_default()
+typescript_interface! {
+    /// An entry in `getSlowOperations`. `reason` is `"duration"` if the operation took longer
+    /// than the threshold to complete, or `"backlog"` if more than the threshold of `next`
+    /// payloads were waiting for its callback. Times are in milliseconds.
+    #[derive(Clone, Debug, Serialize)]
+    pub struct SlowOperation {
+        pub key: i32,
+        pub operation: String,
+        /// The argument literals are replaced unless redaction is turned off.
+        pub query: String,
+        /// Every string, number and boolean is replaced with `"[redacted]"` unless redaction is
+        /// turned off.
+        pub variables: Value => "Record<string, unknown>",
+        pub reason: String => "\"duration\" | \"backlog\"",
+        pub started: u64,
+        pub duration: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub backlog: Option<u64>,
+    }
 }
 
-#[derive(Default)]
-struct UniqueHwnd(Option<HWND>);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl Drop for UniqueHwnd {
-    fn drop(&mut self) {
-        if let Some(window) = self.0.take() {
-            unsafe {
-                let _ = WindowsAndMessaging::DestroyWindow(window);
-            }
-        }
+    fn raw(json: &str) -> Box<RawValue> {
+        RawValue::from_string(json.to_string()).unwrap()
     }
-}
-
-const CALLBACK_WINDOW_CLASS_NAME: PCWSTR = w!("NextCallback");
-const DISPATCH_CALLBACKS: u32 = WindowsAndMessaging::WM_USER;
-const REMOVE_CALLBACK: u32 = WindowsAndMessaging::WM_USER + 1;
 
-struct DeferCallbackDispatcher {
-    window: Weak<UniqueHwnd>,
-    tx: mpsc::Sender<(Value, i32)>,
-}
+    #[test]
+    fn embeds_raw_results_in_payloads() {
+        let results = r#"{ "data": { "b": 1, "a": 2 } }"#;
+        let payload = ResultPayload {
+            results: raw(results),
+            extensions: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            format!(r#"{{"results":{results}}}"#)
+        );
 
-impl DeferCallbackDispatcher {
-    fn dispatch(&self, next: Value, subscription: i32) {
-        if let Some(window) = self.window.upgrade() {
-            if let Some(window) = window.0 {
-                if let Ok(()) = self.tx.send((next, subscription)) {
-                    unsafe {
-                        let _ = WindowsAndMessaging::PostMessageW(
-                            window,
-                            DISPATCH_CALLBACKS,
-                            WPARAM(0),
-                            LPARAM(0),
-                        );
-                    }
-                }
-            }
-        }
+        let payload = NextPayload {
+            next: raw(results),
+            subscription: 3,
+            sequence: 1,
+            extensions: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            format!(r#"{{"next":{results},"subscription":3,"sequence":1}}"#)
+        );
     }
 
-    fn remove_callback(&self, subscription: i32) {
-        if let (Ok(subscription), Some(window)) =
-            (isize::try_from(subscription), self.window.upgrade())
-        {
-            if let Some(window) = window.0 {
-                unsafe {
-                    let _ = WindowsAndMessaging::PostMessageW(
-                        window,
-                        REMOVE_CALLBACK,
-                        WPARAM(0),
-                        LPARAM(subscription),
-                    );
-                }
-            }
-        }
+    /// Every identifier which follows `prefix` in `text`, e.g. the methods called on an object.
+    fn identifiers_after<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+        text.split(prefix)
+            .skip(1)
+            .map(|rest| {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(rest.len());
+                &rest[..end]
+            })
+            .collect()
     }
-}
-
-struct NextCallbacks {
-    rx: mpsc::Receiver<(Value, i32)>,
-    next_callbacks: BTreeMap<i32, IDispatch>,
-}
-
-struct DeferCallbackQueue {
-    window: Arc<UniqueHwnd>,
-    tx: Mutex<mpsc::Sender<(Value, i32)>>,
-}
-
-impl DeferCallbackQueue {
-    fn new() -> Self {
-        Self::ensure_message_queue();
-
-        Self::register_window_class();
 
-        let (tx, rx) = mpsc::channel();
-
-        Self {
-            window: Arc::new(UniqueHwnd(Some(unsafe {
-                WindowsAndMessaging::CreateWindowExW(
-                    WINDOW_EX_STYLE(0),
-                    CALLBACK_WINDOW_CLASS_NAME,
-                    None,
-                    WINDOW_STYLE(0),
-                    0,
-                    0,
-                    0,
-                    0,
-                    HWND::default(),
-                    None,
-                    get_module_handle(),
-                    Some(Box::into_raw(Box::new(NextCallbacks {
-                        rx,
-                        next_callbacks: BTreeMap::new(),
-                    })) as *const _),
-                )
-            }))),
-            tx: Mutex::new(tx),
+    #[test]
+    fn explorer_only_calls_host_object_methods() {
+        let methods = identifiers_after(include_str!("GraphQLService.idl"), "HRESULT ");
+        let called = identifiers_after(EXPLORER_HTML, "graphql.");
+        assert!(!called.is_empty());
+        for method in called {
+            assert!(methods.contains(&method), "{method} is not in the IDL");
         }
     }
 
-    unsafe fn add_subscription(&self, next_callback: IDispatch, subscription: i32) {
-        if let Some(window) = &self.window.0 {
-            let callbacks: *mut NextCallbacks =
-                WindowsAndMessaging::GetWindowLongPtrW(*window, GWLP_USERDATA) as *mut _;
-            if !callbacks.is_null() {
-                let callbacks = Box::leak(Box::from_raw(callbacks));
-                let _ = callbacks.next_callbacks.insert(subscription, next_callback);
-            }
+    #[test]
+    fn explorer_looks_up_elements_which_exist() {
+        for id in identifiers_after(EXPLORER_HTML, "$(\"") {
+            assert!(
+                EXPLORER_HTML.contains(&format!(r#"id="{id}""#)),
+                "no element with the id {id}"
+            );
         }
     }
 
-    fn ensure_message_queue() {
-        let mut msg = MSG::default();
-        let hwnd = HWND::default();
-        unsafe { PeekMessageW(&mut msg, hwnd, WM_USER, WM_USER, PM_NOREMOVE) };
-    }
-
-    fn get_dispatcher(&self) -> Option<DeferCallbackDispatcher> {
-        self.tx.lock().ok().map(|tx| DeferCallbackDispatcher {
-            window: Arc::downgrade(&self.window),
-            tx: tx.clone(),
-        })
-    }
-
-    fn register_window_class() {
-        static REGISTER: Once = Once::new();
-        REGISTER.call_once(|| {
-            let wnd_class = WNDCLASSEXW {
-                cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
-                lpfnWndProc: Some(Self::window_proc),
-                hInstance: HINSTANCE(get_module_handle().0),
-                lpszClassName: CALLBACK_WINDOW_CLASS_NAME,
-                ..Default::default()
-            };
-
-            unsafe {
-                WindowsAndMessaging::RegisterClassExW(&wnd_class);
-            }
-        })
-    }
-
-    unsafe extern "system" fn window_proc(
-        window: HWND,
-        message: u32,
-        wparam: WPARAM,
-        lparam: LPARAM,
-    ) -> LRESULT {
-        match message {
-            WindowsAndMessaging::WM_CREATE => {
-                let create_struct: *const CREATESTRUCTW = lparam.0 as *const _;
-                if !create_struct.is_null() && !(*create_struct).lpCreateParams.is_null() {
-                    WindowsAndMessaging::SetWindowLongPtrW(
-                        window,
-                        GWLP_USERDATA,
-                        (*create_struct).lpCreateParams as _,
-                    );
-
-                    LRESULT(0)
-                } else {
-                    LRESULT(-1)
-                }
-            }
-
-            DISPATCH_CALLBACKS => {
-                let callbacks: *mut NextCallbacks =
-                    WindowsAndMessaging::GetWindowLongPtrW(window, GWLP_USERDATA) as *mut _;
-                if !callbacks.is_null() {
-                    let callbacks = Box::leak(Box::from_raw(callbacks));
-                    while let Ok((next, subscription)) = callbacks.rx.try_recv() {
-                        let payload = serialize_results(&NextPayload { next, subscription });
-                        if let Some(next_callback) = callbacks.next_callbacks.get(&subscription) {
-                            let mut rgvarg = [VariantInit(); 1];
-                            #[allow(clippy::explicit_auto_deref)]
-                            {
-                                (*rgvarg[0].Anonymous.Anonymous).vt = VT_BSTR;
-                                *(*rgvarg[0].Anonymous.Anonymous).Anonymous.bstrVal = payload;
-                            }
-                            let params = DISPPARAMS {
-                                rgvarg: rgvarg.as_mut_ptr(),
-                                cArgs: 1,
-                                ..Default::default()
-                            };
-                            const LOCALE_USER_DEFAULT: u32 = 0x400;
-                            let _ = next_callback.Invoke(
-                                DISPID_UNKNOWN,
-                                &GUID::default(),
-                                LOCALE_USER_DEFAULT,
-                                DISPATCH_METHOD,
-                                &params as *const _,
-                                None,
-                                None,
-                                None,
-                            );
-                            ClearVariantArray(&mut rgvarg);
-                        }
-                    }
-                }
-                LRESULT(0)
-            }
-
-            REMOVE_CALLBACK => {
-                if let Ok(key) = i32::try_from(lparam.0) {
-                    let callbacks: *mut NextCallbacks =
-                        WindowsAndMessaging::GetWindowLongPtrW(window, GWLP_USERDATA) as *mut _;
-                    if !callbacks.is_null() {
-                        let callbacks = Box::leak(Box::from_raw(callbacks));
-                        callbacks.next_callbacks.remove(&key);
-                    }
-                }
-                LRESULT(0)
-            }
-
-            WindowsAndMessaging::WM_DESTROY => {
-                let callbacks: *mut NextCallbacks =
-                    WindowsAndMessaging::SetWindowLongPtrW(window, GWLP_USERDATA, 0) as *mut _;
-                if !callbacks.is_null() {
-                    let _ = Box::from_raw(callbacks);
-                }
-                LRESULT(0)
-            }
-
-            _ => WindowsAndMessaging::DefWindowProcW(window, message, wparam, lparam),
+    #[test]
+    fn explorer_is_self_contained() {
+        // It is loaded with `NavigateToString`, so it has no origin to load anything else from.
+        for reference in ["http:", "https:", "<link", "src=", "import "] {
+            assert!(!EXPLORER_HTML.contains(reference), "{reference}");
         }
     }
 }
@@ -0,0 +1,412 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{HistogramSnapshot, MetricsSnapshot};
+
+/// The address which the COM host object serves [`Metrics::to_prometheus`] on, e.g.
+/// `127.0.0.1:9464`, if it is set when the first service is created.
+pub const METRICS_ADDRESS_VAR: &str = "DISPATCH_GRAPHQL_METRICS";
+
+/// Set to `1` to let [`METRICS_ADDRESS_VAR`] be an address other than loopback, e.g.
+/// `0.0.0.0:9464`. The metrics are served without any authentication, so this is off by default.
+pub const METRICS_ALLOW_REMOTE_VAR: &str = "DISPATCH_GRAPHQL_METRICS_ALLOW_REMOTE";
+
+/// How long [`serve`] waits for a scraper to send its request or read the response.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many connections [`serve`] handles at once. Any more are closed without a response until
+/// one of them ends, which takes at most [`CONNECTION_TIMEOUT`] for each read and write.
+const MAX_CONNECTIONS: usize = 4;
+
+/// The upper bounds of the histogram buckets, in seconds.
+const BOUNDS: [f64; 10] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Output formats supported by `getMetrics`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// A [`MetricsSnapshot`] as JSON.
+    Json,
+    /// The Prometheus text exposition format.
+    Prometheus,
+}
+
+impl FromStr for MetricsFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "" | "json" => Ok(Self::Json),
+            "prometheus" | "text" => Ok(Self::Prometheus),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Counters and histograms for every [`crate::Service`] in the process, so they add up to what a
+/// user's whole Outlook session is doing no matter how many host objects or sessions it opens.
+#[derive(Default)]
+pub struct Metrics {
+    operations: AtomicU64,
+    rejected: AtomicU64,
+    errors: AtomicU64,
+    active: AtomicU64,
+    deliveries: AtomicU64,
+    failures: AtomicU64,
+    queued: AtomicU64,
+    duration: Histogram,
+    latency: Histogram,
+}
+
+/// The process-wide metrics.
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+impl Metrics {
+    /// An operation was started by the executor, and took `duration` to validate and start.
+    pub(crate) fn operation_started(&self, duration: Duration) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+        self.duration.observe(duration);
+    }
+
+    /// A request did not parse or failed validation, so it never reached the executor.
+    pub(crate) fn operation_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The executor failed to start an operation.
+    pub(crate) fn operation_failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn subscription_added(&self) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn subscription_removed(&self) {
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn next_delivered(&self) {
+        self.deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A payload was queued for a callback. This is counted before it is sent, so the owning thread
+    /// can never take it off the queue first.
+    pub(crate) fn payload_queued(&self) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Payloads were taken off a callback queue, whether they were delivered or discarded.
+    pub(crate) fn payloads_dequeued(&self, count: usize) {
+        self.queued.fetch_sub(count as u64, Ordering::Relaxed);
+    }
+
+    /// A callback was invoked with a payload which was queued `latency` ago.
+    pub(crate) fn callback_invoked(&self, latency: Duration, succeeded: bool) {
+        if !succeeded {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency.observe(latency);
+    }
+
+    /// Read every metric.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            operations: self.operations.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            deliveries: self.deliveries.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            queued: self.queued.load(Ordering::Relaxed),
+            duration: self.duration.snapshot(),
+            latency: self.latency.snapshot(),
+        }
+    }
+
+    /// Format every metric for `getMetrics`.
+    pub fn format(&self, format: MetricsFormat) -> String {
+        match format {
+            MetricsFormat::Json => serde_json::to_string(&self.snapshot()).unwrap_or_default(),
+            MetricsFormat::Prometheus => self.to_prometheus(),
+        }
+    }
+
+    /// Format every metric in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut text = String::new();
+        for (name, kind, help, value) in [
+            (
+                "operations_total",
+                "counter",
+                "Operations started by the executor.",
+                snapshot.operations,
+            ),
+            (
+                "rejected_total",
+                "counter",
+                "Requests which did not parse or failed validation.",
+                snapshot.rejected,
+            ),
+            (
+                "executor_errors_total",
+                "counter",
+                "Operations which the executor failed to start.",
+                snapshot.errors,
+            ),
+            (
+                "active_subscriptions",
+                "gauge",
+                "Subscriptions and pending queries which are still running.",
+                snapshot.active,
+            ),
+            (
+                "deliveries_total",
+                "counter",
+                "Next results delivered for pending operations.",
+                snapshot.deliveries,
+            ),
+            (
+                "callback_failures_total",
+                "counter",
+                "Callback invocations which failed.",
+                snapshot.failures,
+            ),
+            (
+                "queued_payloads",
+                "gauge",
+                "Payloads waiting in callback queues.",
+                snapshot.queued,
+            ),
+        ] {
+            let _ = writeln!(text, "# HELP dispatch_graphql_{name} {help}");
+            let _ = writeln!(text, "# TYPE dispatch_graphql_{name} {kind}");
+            let _ = writeln!(text, "dispatch_graphql_{name} {value}");
+        }
+        write_histogram(
+            &mut text,
+            "start_duration_seconds",
+            "Time taken to validate and start an operation.",
+            &snapshot.duration,
+        );
+        write_histogram(
+            &mut text,
+            "callback_latency_seconds",
+            "Time from queueing a payload to its callback returning.",
+            &snapshot.latency,
+        );
+        text
+    }
+}
+
+fn write_histogram(text: &mut String, name: &str, help: &str, histogram: &HistogramSnapshot) {
+    let _ = writeln!(text, "# HELP dispatch_graphql_{name} {help}");
+    let _ = writeln!(text, "# TYPE dispatch_graphql_{name} histogram");
+    for (bound, count) in histogram.bounds.iter().zip(&histogram.buckets) {
+        let _ = writeln!(
+            text,
+            "dispatch_graphql_{name}_bucket{{le=\"{bound}\"}} {count}"
+        );
+    }
+    let _ = writeln!(
+        text,
+        "dispatch_graphql_{name}_bucket{{le=\"+Inf\"}} {}",
+        histogram.count
+    );
+    let _ = writeln!(text, "dispatch_graphql_{name}_sum {}", histogram.sum);
+    let _ = writeln!(text, "dispatch_graphql_{name}_count {}", histogram.count);
+}
+
+/// Durations counted in buckets by [`BOUNDS`], with one more bucket for anything longer.
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BOUNDS.len() + 1],
+    /// The sum of every observation, in microseconds.
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BOUNDS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(BOUNDS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut count = 0;
+        let mut buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|bucket| {
+                count += bucket.load(Ordering::Relaxed);
+                count
+            })
+            .collect();
+        buckets.pop();
+        HistogramSnapshot {
+            bounds: BOUNDS.to_vec(),
+            buckets,
+            count,
+            sum: self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+/// Serve [`Metrics::to_prometheus`] for the [`global`] metrics at `/metrics` on a background
+/// thread, so Prometheus can scrape them. This is a minimal HTTP/1.1 responder for a scraper on
+/// the same machine, not a general-purpose web server.
+/// Each connection is handled on its own thread, so a client which never sends its request can't
+/// hold up the next scrape, but only up to [`MAX_CONNECTIONS`] at a time.
+pub fn serve(listener: TcpListener) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming().flatten() {
+            if in_flight.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                drop(stream);
+                continue;
+            }
+            let in_flight = in_flight.clone();
+            thread::spawn(move || {
+                let _ = respond(stream);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    })
+}
+
+/// Bind the listener for [`serve`]. Only loopback addresses are allowed unless `allow_remote` is
+/// set, e.g. from [`METRICS_ALLOW_REMOTE_VAR`], so a typo in the address can't expose the metrics
+/// to the network.
+pub fn bind(address: &str, allow_remote: bool) -> io::Result<TcpListener> {
+    let addresses: Vec<_> = address
+        .to_socket_addrs()?
+        .filter(|address| allow_remote || address.ip().is_loopback())
+        .collect();
+    if addresses.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{address} is not a loopback address, set {METRICS_ALLOW_REMOTE_VAR}=1 to serve metrics on it"),
+        ));
+    }
+    TcpListener::bind(&addresses[..])
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, none of them matter here.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if path == "/metrics" || path.starts_with("/metrics?") {
+        ("200 OK", global().to_prometheus())
+    } else {
+        ("404 Not Found", String::from("Not Found\n"))
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn scrape(address: std::net::SocketAddr) -> String {
+        try_scrape(address).unwrap()
+    }
+
+    fn try_scrape(address: std::net::SocketAddr) -> io::Result<String> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        write!(stream, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    /// Whether a connection was closed without a response, which may reset it before the request
+    /// is even written.
+    fn rejected(response: &io::Result<String>) -> bool {
+        response.as_ref().map_or(true, String::is_empty)
+    }
+
+    #[test]
+    fn only_binds_loopback_unless_allowed() {
+        assert!(bind("127.0.0.1:0", false).is_ok());
+        let error = bind("0.0.0.0:0", false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(bind("0.0.0.0:0", true).is_ok());
+    }
+
+    #[test]
+    fn serves_while_another_connection_is_idle() {
+        let listener = bind("127.0.0.1:0", false).unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener);
+
+        // This client never sends its request.
+        let _idle = TcpStream::connect(address).unwrap();
+        let response = scrape(address);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("# TYPE dispatch_graphql_"), "{response}");
+    }
+
+    #[test]
+    fn closes_connections_beyond_the_limit() {
+        let listener = bind("127.0.0.1:0", false).unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener);
+
+        let mut idle: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        // Each connection is only accepted after the previous one, so once this one is closed,
+        // every idle connection holds a thread.
+        assert!(rejected(&try_scrape(address)));
+
+        // Closing an idle connection frees its thread, which first answers the empty request.
+        drop(idle.pop());
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        loop {
+            let response = try_scrape(address);
+            if !rejected(&response) {
+                assert!(response.unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "no thread was freed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, value::RawValue, Value};
+
+use crate::{
+    executor::{Executor, SubscriptionHandle},
+    service::{self, Result},
+    timing::unix_millis,
+};
+
+/// A file which the COM host object records every operation to, if it is set when the first
+/// service is created. An existing file is replaced.
+pub const RECORD_PATH_VAR: &str = "DISPATCH_GRAPHQL_RECORD";
+
+/// One line of a recording. Each operation starts with a `request`, followed by either its
+/// `results`, if it completed immediately, or a `next` event for each result which arrived later.
+/// Times are in milliseconds since the Unix epoch. The payloads are kept exactly as they were
+/// returned, so a replay serves the same bytes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase", try_from = "EventLine")]
+pub enum Event {
+    Request {
+        id: u64,
+        time: u64,
+        query: String,
+        #[serde(rename = "operationName")]
+        operation_name: String,
+        variables: Box<RawValue>,
+    },
+    Results {
+        id: u64,
+        time: u64,
+        results: Box<RawValue>,
+    },
+    Next {
+        id: u64,
+        time: u64,
+        next: Box<RawValue>,
+    },
+}
+
+/// Every field of an [`Event`] in one struct. `RawValue` can't be deserialized through an
+/// internally tagged enum, so events are read as this and then converted.
+#[derive(Deserialize)]
+struct EventLine {
+    event: String,
+    id: u64,
+    time: u64,
+    query: Option<String>,
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<Box<RawValue>>,
+    results: Option<Box<RawValue>>,
+    next: Option<Box<RawValue>>,
+}
+
+impl TryFrom<EventLine> for Event {
+    type Error = String;
+
+    fn try_from(line: EventLine) -> std::result::Result<Self, Self::Error> {
+        let EventLine { id, time, .. } = line;
+        match (line.event.as_str(), line.query, line.results, line.next) {
+            ("request", Some(query), _, _) => Ok(Self::Request {
+                id,
+                time,
+                query,
+                operation_name: line.operation_name.unwrap_or_default(),
+                variables: line.variables.unwrap_or_else(empty_object),
+            }),
+            ("results", _, Some(results), _) => Ok(Self::Results { id, time, results }),
+            ("next", _, _, Some(next)) => Ok(Self::Next { id, time, next }),
+            (event, ..) => Err(format!("invalid recording event: {event}")),
+        }
+    }
+}
+
+/// Writes every request which a [`crate::Service`] receives, and every result it returns, as one
+/// JSON [`Event`] per line. Each line is flushed as it is written, so a recording is still usable
+/// if the process crashes.
+pub struct Recorder {
+    writer: Mutex<Box<dyn Write + Send>>,
+    next_id: AtomicU64,
+}
+
+impl Recorder {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Record to a new file, replacing it if it already exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+
+    /// Record a request, and return the id which its results are recorded with.
+    pub(crate) fn request(&self, query: &str, operation_name: &str, variables: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let variables = if variables.trim().is_empty() {
+            empty_object()
+        } else {
+            to_raw_value(variables)
+        };
+        self.write(&Event::Request {
+            id,
+            time: unix_millis(SystemTime::now()),
+            query: query.to_string(),
+            operation_name: operation_name.to_string(),
+            variables,
+        });
+        id
+    }
+
+    pub(crate) fn results(&self, id: u64, results: &str) {
+        self.write(&Event::Results {
+            id,
+            time: unix_millis(SystemTime::now()),
+            results: to_raw_value(results),
+        });
+    }
+
+    pub(crate) fn next(&self, id: u64, next: &str) {
+        self.write(&Event::Next {
+            id,
+            time: unix_millis(SystemTime::now()),
+            next: to_raw_value(next),
+        });
+    }
+
+    fn write(&self, event: &Event) {
+        let Ok(mut line) = serde_json::to_string(event) else {
+            return;
+        };
+        line.push('\n');
+        let Ok(mut writer) = self.writer.lock() else {
+            return;
+        };
+        if let Err(error) = writer
+            .write_all(line.as_bytes())
+            .and_then(|()| writer.flush())
+        {
+            tracing::warn!(%error, "failed to write to the recording");
+        }
+    }
+}
+
+/// Check that a payload is JSON, or keep it as a string if it is not, so nothing is lost.
+fn to_raw_value(json: &str) -> Box<RawValue> {
+    RawValue::from_string(json.to_string())
+        .or_else(|_| serde_json::value::to_raw_value(json))
+        .unwrap_or_else(|_| empty_object())
+}
+
+fn empty_object() -> Box<RawValue> {
+    RawValue::from_string(String::from("{}")).expect("valid JSON")
+}
+
+/// An operation in a recording, and every result it returned.
+struct RecordedOperation {
+    query: String,
+    operation_name: String,
+    variables: Value,
+    results: Vec<String>,
+    /// Whether it completed immediately, rather than delivering its results later.
+    completed: bool,
+    replayed: bool,
+}
+
+/// An executor which serves the results from a recording made with [`Recorder`], so a session can
+/// be reproduced without the mailbox it ran against, e.g. on Linux.
+///
+/// Each request is matched with the first operation in the recording which has not been replayed
+/// yet and has the same document and operation name, preferring one with the same variables. Once
+/// every match has been replayed, the last of them, again preferring one with the same variables,
+/// is replayed again, e.g. for a page which polls.
+/// The recorded variables are the coerced ones which the executor received, so they are compared
+/// with the variables this executor receives in turn. Results are sent right away, so a replay does
+/// not depend on how long the original took. Subscriptions then stay open until they are
+/// cancelled, as they did while recording.
+pub struct ReplayExecutor {
+    operations: Mutex<Vec<RecordedOperation>>,
+}
+
+impl ReplayExecutor {
+    pub fn new(events: impl IntoIterator<Item = Event>) -> Self {
+        let mut operations = Vec::new();
+        let mut ids = Vec::new();
+        for event in events {
+            match event {
+                Event::Request {
+                    id,
+                    query,
+                    operation_name,
+                    variables,
+                    ..
+                } => {
+                    ids.push(id);
+                    operations.push(RecordedOperation {
+                        query,
+                        operation_name,
+                        variables: serde_json::from_str(variables.get()).unwrap_or_default(),
+                        results: Vec::new(),
+                        completed: false,
+                        replayed: false,
+                    });
+                }
+                Event::Results { id, results, .. } => {
+                    if let Some(operation) = find(&mut operations, &ids, id) {
+                        operation.results.push(results.get().to_string());
+                        operation.completed = true;
+                    }
+                }
+                Event::Next { id, next, .. } => {
+                    if let Some(operation) = find(&mut operations, &ids, id) {
+                        operation.results.push(next.get().to_string());
+                    }
+                }
+            }
+        }
+        Self {
+            operations: Mutex::new(operations),
+        }
+    }
+
+    /// Load a recording written by [`Recorder`]. Blank lines are skipped.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut events = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                events.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self::new(events))
+    }
+}
+
+/// Take the first of the candidates with the same variables, or else the first of them.
+fn prefer_variables<'a>(
+    mut candidates: impl Iterator<Item = &'a mut RecordedOperation>,
+    variables: &Value,
+) -> Option<&'a mut RecordedOperation> {
+    let first = candidates.next()?;
+    if first.variables == *variables {
+        return Some(first);
+    }
+    Some(
+        candidates
+            .find(|operation| operation.variables == *variables)
+            .unwrap_or(first),
+    )
+}
+
+/// Find the operation for an event, by the id of its request.
+fn find<'a>(
+    operations: &'a mut [RecordedOperation],
+    ids: &[u64],
+    id: u64,
+) -> Option<&'a mut RecordedOperation> {
+    let index = ids.iter().rposition(|&request| request == id)?;
+    operations.get_mut(index)
+}
+
+impl Executor for ReplayExecutor {
+    fn subscribe(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        next: mpsc::Sender<String>,
+        complete: mpsc::Sender<()>,
+    ) -> Result<SubscriptionHandle> {
+        let variables: Value = serde_json::from_str(variables).unwrap_or_else(|_| json!({}));
+        let replayed = self.operations.lock().ok().and_then(|mut operations| {
+            let is_match = |operation: &RecordedOperation| {
+                operation.query == query && operation.operation_name == operation_name
+            };
+            let operation = if operations
+                .iter()
+                .any(|operation| !operation.replayed && is_match(operation))
+            {
+                prefer_variables(
+                    operations
+                        .iter_mut()
+                        .filter(|operation| !operation.replayed && is_match(operation)),
+                    &variables,
+                )
+            } else {
+                prefer_variables(
+                    operations
+                        .iter_mut()
+                        .rev()
+                        .filter(|operation| is_match(operation)),
+                    &variables,
+                )
+            }?;
+            operation.replayed = true;
+            Some((operation.results.clone(), operation.completed))
+        });
+
+        let Some((results, completed)) = replayed else {
+            let _ = next.send(
+                json!({
+                    "data": null,
+                    "errors": [{
+                        "message": format!("No recorded response for operation: {operation_name}")
+                    }]
+                })
+                .to_string(),
+            );
+            let _ = complete.send(());
+            return Ok(Box::new(()));
+        };
+
+        for result in results {
+            let _ = next.send(result);
+        }
+        if completed || !service::is_subscription(query, operation_name) {
+            let _ = complete.send(());
+            Ok(Box::new(()))
+        } else {
+            // Hold onto the senders so the subscription stays open until it is cancelled.
+            Ok(Box::new((next, complete)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{executor::fixtures, schema, QueryResult, Service};
+
+    const ITEMS: &str = "query Items($first: Int = 10) { items(first: $first) { subject } }";
+
+    /// An executor for the schema from [`fixtures::introspection`] which answers `items` with the
+    /// variables it received, so each result shows which request it belongs to.
+    struct EchoExecutor;
+
+    impl Executor for EchoExecutor {
+        fn subscribe(
+            &self,
+            _query: &str,
+            operation_name: &str,
+            variables: &str,
+            next: mpsc::Sender<String>,
+            complete: mpsc::Sender<()>,
+        ) -> Result<SubscriptionHandle> {
+            let results = if operation_name == schema::INTROSPECTION_OPERATION_NAME {
+                fixtures::introspection()
+            } else {
+                let variables: Value = serde_json::from_str(variables).unwrap();
+                json!({ "data": { "items": variables } })
+            };
+            let _ = next.send(results.to_string());
+            let _ = complete.send(());
+            Ok(Box::new(()))
+        }
+    }
+
+    /// A writer which keeps what was recorded, so the test can read it back.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn events(&self) -> Vec<Event> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    fn results(service: &Service, variables: &str) -> Value {
+        match service.start_query(ITEMS, "Items", variables).unwrap() {
+            QueryResult::Results(payload) => serde_json::from_str(payload.results.get()).unwrap(),
+            QueryResult::Pending { .. } => panic!("expected results"),
+        }
+    }
+
+    fn record(variables: &[&str]) -> Vec<Event> {
+        let buffer = Buffer::default();
+        let service =
+            Service::new(EchoExecutor).with_recorder(Arc::new(Recorder::new(buffer.clone())));
+        for variables in variables {
+            results(&service, variables);
+        }
+        buffer.events()
+    }
+
+    #[test]
+    fn records_the_coerced_variables() {
+        let variables: Vec<_> = record(&["", r#"{ "first": 5 }"#])
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Request {
+                    operation_name,
+                    variables,
+                    ..
+                } if operation_name == "Items" => Some(variables.get().to_string()),
+                _ => None,
+            })
+            .collect();
+        // The default value is filled in, as it was for the executor.
+        assert_eq!(variables, [r#"{"first":10}"#, r#"{"first":5}"#]);
+    }
+
+    #[test]
+    fn replays_each_recorded_request() {
+        let events = record(&[r#"{ "first": 5 }"#, "", r#"{ "first": 7 }"#]);
+        let service = Service::new(ReplayExecutor::new(events));
+
+        // Out of order, and with the default value spelled out instead of left to coercion.
+        for first in [10, 7, 5] {
+            assert_eq!(
+                results(&service, &json!({ "first": first }).to_string()),
+                json!({ "data": { "items": { "first": first } } }),
+                "first: {first}"
+            );
+        }
+    }
+
+    #[test]
+    fn replays_the_last_match_once_every_match_was_used() {
+        let events = record(&[r#"{ "first": 1 }"#, r#"{ "first": 2 }"#]);
+        let service = Service::new(ReplayExecutor::new(events));
+
+        for first in [2, 1, 1, 2, 3] {
+            // Once both were replayed, the one with the same variables is preferred, and the
+            // last one is used otherwise.
+            let expected = if first == 3 { 2 } else { first };
+            assert_eq!(
+                results(&service, &json!({ "first": first }).to_string()),
+                json!({ "data": { "items": { "first": expected } } }),
+                "first: {first}"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_requests_which_were_never_recorded() {
+        let service = Service::new(ReplayExecutor::new(record(&[])));
+        let results = match service
+            .start_query("query Other { items { subject } }", "Other", "")
+            .unwrap()
+        {
+            QueryResult::Results(payload) => payload.results.get().to_string(),
+            QueryResult::Pending { .. } => panic!("expected results"),
+        };
+        assert!(results.contains("No recorded response"), "{results}");
+    }
+}
@@ -0,0 +1,887 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    str::FromStr,
+};
+
+use serde_json::Value;
+
+/// The standard introspection query, equivalent to the one used by GraphiQL and most other
+/// GraphQL tooling. The results can be printed as SDL with [`print_sdl`].
+pub const INTROSPECTION_QUERY: &str = r#"query IntrospectionQuery {
+  __schema {
+    queryType { name }
+    mutationType { name }
+    subscriptionType { name }
+    types {
+      ...FullType
+    }
+    directives {
+      name
+      description
+      locations
+      args {
+        ...InputValue
+      }
+    }
+  }
+}
+
+fragment FullType on __Type {
+  kind
+  name
+  description
+  fields(includeDeprecated: true) {
+    name
+    description
+    args {
+      ...InputValue
+    }
+    type {
+      ...TypeRef
+    }
+    isDeprecated
+    deprecationReason
+  }
+  inputFields {
+    ...InputValue
+  }
+  interfaces {
+    ...TypeRef
+  }
+  enumValues(includeDeprecated: true) {
+    name
+    description
+    isDeprecated
+    deprecationReason
+  }
+  possibleTypes {
+    ...TypeRef
+  }
+}
+
+fragment InputValue on __InputValue {
+  name
+  description
+  type { ...TypeRef }
+  defaultValue
+}
+
+fragment TypeRef on __Type {
+  kind
+  name
+  ofType {
+    kind
+    name
+    ofType {
+      kind
+      name
+      ofType {
+        kind
+        name
+        ofType {
+          kind
+          name
+          ofType {
+            kind
+            name
+            ofType {
+              kind
+              name
+              ofType {
+                kind
+                name
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+/// The operation name of [`INTROSPECTION_QUERY`].
+pub const INTROSPECTION_OPERATION_NAME: &str = "IntrospectionQuery";
+
+/// Output formats supported by `getSchema`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaFormat {
+    /// The raw `{ "data": { "__schema": ... } }` introspection result as JSON.
+    Introspection,
+    /// The schema printed in the GraphQL Schema Definition Language.
+    Sdl,
+}
+
+impl FromStr for SchemaFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "" | "json" | "introspection" => Ok(Self::Introspection),
+            "sdl" | "graphql" => Ok(Self::Sdl),
+            _ => Err(()),
+        }
+    }
+}
+
+const BUILTIN_SCALARS: [&str; 5] = ["String", "Int", "Float", "Boolean", "ID"];
+const BUILTIN_DIRECTIVES: [&str; 4] = ["skip", "include", "deprecated", "specifiedBy"];
+
+/// Print the results of [`INTROSPECTION_QUERY`] as SDL. The `introspection` parameter may be
+/// either the full `{ "data": { "__schema": ... } }` result or just the `__schema` object.
+pub fn print_sdl(introspection: &Value) -> Option<String> {
+    let schema = introspection
+        .pointer("/data/__schema")
+        .or_else(|| introspection.get("__schema"))
+        .unwrap_or(introspection);
+    let types = schema.get("types")?.as_array()?;
+    let mut sdl = String::new();
+
+    print_schema_definition(schema, &mut sdl);
+
+    if let Some(directives) = schema.get("directives").and_then(Value::as_array) {
+        for directive in directives {
+            let Some(name) = directive.get("name").and_then(Value::as_str) else {
+                continue;
+            };
+            if BUILTIN_DIRECTIVES.contains(&name) {
+                continue;
+            }
+            print_description(directive, "", &mut sdl);
+            let _ = write!(sdl, "directive @{name}");
+            print_arguments(directive, "", &mut sdl);
+            let locations: Vec<_> = directive
+                .get("locations")
+                .and_then(Value::as_array)
+                .map(|locations| locations.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_default();
+            let _ = writeln!(sdl, " on {}\n", locations.join(" | "));
+        }
+    }
+
+    for full_type in types {
+        let (Some(kind), Some(name)) = (
+            full_type.get("kind").and_then(Value::as_str),
+            full_type.get("name").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+        if name.starts_with("__") {
+            continue;
+        }
+
+        match kind {
+            "SCALAR" => {
+                if BUILTIN_SCALARS.contains(&name) {
+                    continue;
+                }
+                print_description(full_type, "", &mut sdl);
+                let _ = writeln!(sdl, "scalar {name}");
+            }
+            "OBJECT" | "INTERFACE" => {
+                print_description(full_type, "", &mut sdl);
+                let keyword = if kind == "OBJECT" {
+                    "type"
+                } else {
+                    "interface"
+                };
+                let _ = write!(sdl, "{keyword} {name}");
+                let interfaces = type_names(full_type.get("interfaces"));
+                if !interfaces.is_empty() {
+                    let _ = write!(sdl, " implements {}", interfaces.join(" & "));
+                }
+                sdl.push_str(" {\n");
+                for field in full_type
+                    .get("fields")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    print_member(field, &mut sdl, |field, sdl| {
+                        let name = field.get("name")?.as_str()?;
+                        let field_type = type_ref(field.get("type")?)?;
+                        let _ = write!(sdl, "  {name}");
+                        print_arguments(field, "  ", sdl);
+                        let _ = write!(sdl, ": {field_type}");
+                        print_deprecated(field, sdl);
+                        Some(())
+                    });
+                }
+                sdl.push_str("}\n");
+            }
+            "UNION" => {
+                print_description(full_type, "", &mut sdl);
+                let _ = writeln!(
+                    sdl,
+                    "union {name} = {}",
+                    type_names(full_type.get("possibleTypes")).join(" | ")
+                );
+            }
+            "ENUM" => {
+                print_description(full_type, "", &mut sdl);
+                let _ = writeln!(sdl, "enum {name} {{");
+                for value in full_type
+                    .get("enumValues")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    print_member(value, &mut sdl, |value, sdl| {
+                        let _ = write!(sdl, "  {}", value.get("name")?.as_str()?);
+                        print_deprecated(value, sdl);
+                        Some(())
+                    });
+                }
+                sdl.push_str("}\n");
+            }
+            "INPUT_OBJECT" => {
+                print_description(full_type, "", &mut sdl);
+                let _ = writeln!(sdl, "input {name} {{");
+                for input_field in full_type
+                    .get("inputFields")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    print_member(input_field, &mut sdl, |input_field, sdl| {
+                        sdl.push_str("  ");
+                        print_input_value(input_field, sdl)
+                    });
+                }
+                sdl.push_str("}\n");
+            }
+            _ => continue,
+        }
+        sdl.push('\n');
+    }
+
+    Some(sdl.trim_end().to_string() + "\n")
+}
+
+fn print_schema_definition(schema: &Value, sdl: &mut String) {
+    let root_name = |field: &str| {
+        schema
+            .get(field)
+            .and_then(|root| root.get("name"))
+            .and_then(Value::as_str)
+    };
+    let roots = [
+        ("query", root_name("queryType"), "Query"),
+        ("mutation", root_name("mutationType"), "Mutation"),
+        (
+            "subscription",
+            root_name("subscriptionType"),
+            "Subscription",
+        ),
+    ];
+
+    // The schema definition may be omitted if all of the root operation types use the default names.
+    if roots
+        .iter()
+        .all(|(_, name, default)| name.is_none_or(|name| name == *default))
+    {
+        return;
+    }
+
+    sdl.push_str("schema {\n");
+    for (operation, name, _) in roots {
+        if let Some(name) = name {
+            let _ = writeln!(sdl, "  {operation}: {name}");
+        }
+    }
+    sdl.push_str("}\n\n");
+}
+
+/// Print a field, enum value or input field on its own line with its description. A member which
+/// is missing its name or type is skipped, rather than failing the whole schema.
+fn print_member(
+    member: &Value,
+    sdl: &mut String,
+    print: impl FnOnce(&Value, &mut String) -> Option<()>,
+) {
+    let mut line = String::new();
+    if print(member, &mut line).is_none() {
+        return;
+    }
+    print_description(member, "  ", sdl);
+    sdl.push_str(&line);
+    sdl.push('\n');
+}
+
+fn print_description(element: &Value, indent: &str, sdl: &mut String) {
+    let Some(description) = element.get("description").and_then(Value::as_str) else {
+        return;
+    };
+    if description.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(sdl, "{indent}\"\"\"");
+    for line in description.lines() {
+        let _ = writeln!(sdl, "{indent}{}", line.replace("\"\"\"", "\\\"\"\""));
+    }
+    let _ = writeln!(sdl, "{indent}\"\"\"");
+}
+
+fn print_arguments(element: &Value, indent: &str, sdl: &mut String) {
+    // An argument which is missing its name or type is skipped, like any other member.
+    let args: Vec<_> = element
+        .get("args")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|arg| {
+            let mut printed = String::new();
+            print_input_value(arg, &mut printed).map(|()| (arg, printed))
+        })
+        .collect();
+    if args.is_empty() {
+        return;
+    }
+
+    let described = args.iter().any(|(arg, _)| {
+        arg.get("description")
+            .and_then(Value::as_str)
+            .is_some_and(|description| !description.is_empty())
+    });
+
+    if described {
+        // Put each argument on its own line so the descriptions line up with them.
+        let nested = format!("{indent}  ");
+        sdl.push_str("(\n");
+        for (arg, printed) in args {
+            print_description(arg, &nested, sdl);
+            let _ = writeln!(sdl, "{nested}{printed}");
+        }
+        let _ = write!(sdl, "{indent})");
+    } else {
+        let printed: Vec<_> = args.into_iter().map(|(_, printed)| printed).collect();
+        let _ = write!(sdl, "({})", printed.join(", "));
+    }
+}
+
+fn print_input_value(input_value: &Value, sdl: &mut String) -> Option<()> {
+    let name = input_value.get("name")?.as_str()?;
+    let type_ref = type_ref(input_value.get("type")?)?;
+    let _ = write!(sdl, "{name}: {type_ref}");
+    if let Some(default_value) = input_value.get("defaultValue").and_then(Value::as_str) {
+        let _ = write!(sdl, " = {default_value}");
+    }
+    Some(())
+}
+
+fn print_deprecated(element: &Value, sdl: &mut String) {
+    if !element
+        .get("isDeprecated")
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+    {
+        return;
+    }
+
+    match element.get("deprecationReason").and_then(Value::as_str) {
+        Some(reason) if reason != "No longer supported" => {
+            let _ = write!(
+                sdl,
+                " @deprecated(reason: {})",
+                Value::String(reason.to_string())
+            );
+        }
+        _ => sdl.push_str(" @deprecated"),
+    }
+}
+
+fn type_names(types: Option<&Value>) -> Vec<&str> {
+    types
+        .and_then(Value::as_array)
+        .map(|types| {
+            types
+                .iter()
+                .filter_map(|named| named.get("name").and_then(Value::as_str))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Format an introspection `TypeRef` using the SDL wrapper syntax, e.g. `[ID!]!`.
+pub fn type_ref(type_ref: &Value) -> Option<String> {
+    match type_ref.get("kind")?.as_str()? {
+        "NON_NULL" => Some(format!("{}!", self::type_ref(type_ref.get("ofType")?)?)),
+        "LIST" => Some(format!("[{}]", self::type_ref(type_ref.get("ofType")?)?)),
+        _ => type_ref
+            .get("name")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    }
+}
+
+/// A reference to a named type, possibly wrapped in lists and non-null modifiers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeRef {
+    Named(String),
+    List(Box<TypeRef>),
+    NonNull(Box<TypeRef>),
+}
+
+impl TypeRef {
+    fn from_introspection(type_ref: &Value) -> Option<Self> {
+        match type_ref.get("kind")?.as_str()? {
+            "NON_NULL" => Some(Self::NonNull(Box::new(Self::from_introspection(
+                type_ref.get("ofType")?,
+            )?))),
+            "LIST" => Some(Self::List(Box::new(Self::from_introspection(
+                type_ref.get("ofType")?,
+            )?))),
+            _ => Some(Self::Named(type_ref.get("name")?.as_str()?.to_string())),
+        }
+    }
+
+    /// The innermost named type, without any list or non-null wrappers.
+    pub fn named_type(&self) -> &str {
+        match self {
+            Self::Named(name) => name,
+            Self::List(of_type) | Self::NonNull(of_type) => of_type.named_type(),
+        }
+    }
+
+    pub fn is_non_null(&self) -> bool {
+        matches!(self, Self::NonNull(_))
+    }
+}
+
+impl fmt::Display for TypeRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Named(name) => write!(f, "{name}"),
+            Self::List(of_type) => write!(f, "[{of_type}]"),
+            Self::NonNull(of_type) => write!(f, "{of_type}!"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeKind {
+    Scalar,
+    Object,
+    Interface,
+    Union,
+    Enum,
+    InputObject,
+}
+
+#[derive(Clone, Debug)]
+pub struct InputValue {
+    pub name: String,
+    pub type_ref: TypeRef,
+    pub default_value: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub name: String,
+    pub args: Vec<InputValue>,
+    pub type_ref: TypeRef,
+}
+
+#[derive(Clone, Debug)]
+pub struct NamedType {
+    pub name: String,
+    pub kind: TypeKind,
+    pub fields: Vec<Field>,
+    pub input_fields: Vec<InputValue>,
+    pub enum_values: Vec<String>,
+    pub interfaces: Vec<String>,
+    pub possible_types: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Directive {
+    pub name: String,
+    pub args: Vec<InputValue>,
+}
+
+/// The kind of root operation in a document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OperationKind {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Query => "Query",
+            Self::Mutation => "Mutation",
+            Self::Subscription => "Subscription",
+        })
+    }
+}
+
+/// A schema model built from the results of [`INTROSPECTION_QUERY`], used to check and generate
+/// code for documents without calling the executor.
+#[derive(Clone, Debug)]
+pub struct Schema {
+    pub query_type: String,
+    pub mutation_type: Option<String>,
+    pub subscription_type: Option<String>,
+    pub types: BTreeMap<String, NamedType>,
+    pub directives: BTreeMap<String, Directive>,
+}
+
+impl Schema {
+    /// Build the schema model from either the full `{ "data": { "__schema": ... } }` result or
+    /// just the `__schema` object.
+    pub fn from_introspection(introspection: &Value) -> Option<Self> {
+        let schema = introspection
+            .pointer("/data/__schema")
+            .or_else(|| introspection.get("__schema"))
+            .unwrap_or(introspection);
+        let root_name = |field: &str| {
+            schema
+                .get(field)
+                .and_then(|root| root.get("name"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+
+        let mut types = BTreeMap::new();
+        for full_type in schema.get("types")?.as_array()? {
+            let named_type = NamedType::from_introspection(full_type)?;
+            types.insert(named_type.name.clone(), named_type);
+        }
+
+        let mut directives = BTreeMap::new();
+        for directive in schema
+            .get("directives")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let name = directive.get("name")?.as_str()?.to_string();
+            let args = input_values(directive.get("args"))?;
+            directives.insert(name.clone(), Directive { name, args });
+        }
+
+        Some(Self {
+            query_type: root_name("queryType")?,
+            mutation_type: root_name("mutationType"),
+            subscription_type: root_name("subscriptionType"),
+            types,
+            directives,
+        })
+    }
+
+    pub fn get_type(&self, name: &str) -> Option<&NamedType> {
+        self.types.get(name)
+    }
+
+    pub fn root_type(&self, operation: OperationKind) -> Option<&NamedType> {
+        match operation {
+            OperationKind::Query => self.get_type(&self.query_type),
+            OperationKind::Mutation => self.get_type(self.mutation_type.as_deref()?),
+            OperationKind::Subscription => self.get_type(self.subscription_type.as_deref()?),
+        }
+    }
+
+    /// Look up a field on an object or interface type. This does not include the `__typename`,
+    /// `__schema` or `__type` meta-fields.
+    pub fn field(&self, type_name: &str, field_name: &str) -> Option<&Field> {
+        self.get_type(type_name)?
+            .fields
+            .iter()
+            .find(|field| field.name == field_name)
+    }
+
+    /// The concrete object types which a value of the named type may have at runtime.
+    pub fn possible_types(&self, type_name: &str) -> Vec<&str> {
+        match self.get_type(type_name) {
+            Some(named_type) if named_type.kind == TypeKind::Object => vec![&named_type.name],
+            Some(named_type) => named_type
+                .possible_types
+                .iter()
+                .map(String::as_str)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Check whether the type condition on a fragment can apply to a value of the object type.
+    pub fn applies_to(&self, type_condition: &str, object_type: &str) -> bool {
+        type_condition == object_type || self.possible_types(type_condition).contains(&object_type)
+    }
+
+    pub fn is_composite(&self, type_name: &str) -> bool {
+        matches!(
+            self.get_type(type_name).map(|named_type| named_type.kind),
+            Some(TypeKind::Object | TypeKind::Interface | TypeKind::Union)
+        )
+    }
+}
+
+impl NamedType {
+    fn from_introspection(full_type: &Value) -> Option<Self> {
+        let kind = match full_type.get("kind")?.as_str()? {
+            "SCALAR" => TypeKind::Scalar,
+            "OBJECT" => TypeKind::Object,
+            "INTERFACE" => TypeKind::Interface,
+            "UNION" => TypeKind::Union,
+            "ENUM" => TypeKind::Enum,
+            "INPUT_OBJECT" => TypeKind::InputObject,
+            _ => return None,
+        };
+
+        let mut fields = Vec::new();
+        for field in full_type
+            .get("fields")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            fields.push(Field {
+                name: field.get("name")?.as_str()?.to_string(),
+                args: input_values(field.get("args"))?,
+                type_ref: TypeRef::from_introspection(field.get("type")?)?,
+            });
+        }
+
+        let enum_values = full_type
+            .get("enumValues")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|value| value.get("name").and_then(Value::as_str))
+            .map(str::to_string)
+            .collect();
+
+        Some(Self {
+            name: full_type.get("name")?.as_str()?.to_string(),
+            kind,
+            fields,
+            input_fields: input_values(full_type.get("inputFields"))?,
+            enum_values,
+            interfaces: type_names(full_type.get("interfaces"))
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            possible_types: type_names(full_type.get("possibleTypes"))
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+fn input_values(input_values: Option<&Value>) -> Option<Vec<InputValue>> {
+    let mut result = Vec::new();
+    for input_value in input_values.and_then(Value::as_array).into_iter().flatten() {
+        result.push(InputValue {
+            name: input_value.get("name")?.as_str()?.to_string(),
+            type_ref: TypeRef::from_introspection(input_value.get("type")?)?,
+            default_value: input_value
+                .get("defaultValue")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        });
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn named(kind: &str, name: &str) -> Value {
+        json!({ "kind": kind, "name": name, "ofType": null })
+    }
+
+    fn non_null(of_type: Value) -> Value {
+        json!({ "kind": "NON_NULL", "name": null, "ofType": of_type })
+    }
+
+    fn introspection() -> Value {
+        json!({
+            "data": {
+                "__schema": {
+                    "queryType": { "name": "Root" },
+                    "mutationType": null,
+                    "subscriptionType": null,
+                    "types": [
+                        {
+                            "kind": "OBJECT",
+                            "name": "Root",
+                            "description": "The root of every query.",
+                            "interfaces": [],
+                            "fields": [
+                                {
+                                    "name": "greeting",
+                                    "description": null,
+                                    "args": [
+                                        {
+                                            "name": "name",
+                                            "description": null,
+                                            "type": named("SCALAR", "String"),
+                                            "defaultValue": "\"world\""
+                                        },
+                                        { "name": "broken", "description": null }
+                                    ],
+                                    "type": non_null(named("SCALAR", "String")),
+                                    "isDeprecated": false,
+                                    "deprecationReason": null
+                                },
+                                { "name": "broken", "args": [] },
+                                {
+                                    "name": "items",
+                                    "description": null,
+                                    "args": [{
+                                        "name": "filter",
+                                        "description": "Which items to return.",
+                                        "type": named("INPUT_OBJECT", "Filter"),
+                                        "defaultValue": null
+                                    }],
+                                    "type": non_null(json!({
+                                        "kind": "LIST",
+                                        "name": null,
+                                        "ofType": non_null(named("SCALAR", "DateTime"))
+                                    })),
+                                    "isDeprecated": true,
+                                    "deprecationReason": "Use something else."
+                                }
+                            ]
+                        },
+                        {
+                            "kind": "SCALAR",
+                            "name": "DateTime",
+                            "description": "An ISO 8601 timestamp."
+                        },
+                        { "kind": "SCALAR", "name": "String", "description": null },
+                        {
+                            "kind": "ENUM",
+                            "name": "Color",
+                            "description": null,
+                            "enumValues": [
+                                { "name": "RED", "isDeprecated": false },
+                                { "description": "No name." },
+                                {
+                                    "name": "GREEN",
+                                    "isDeprecated": true,
+                                    "deprecationReason": "No longer supported"
+                                }
+                            ]
+                        },
+                        {
+                            "kind": "INPUT_OBJECT",
+                            "name": "Filter",
+                            "description": null,
+                            "inputFields": [
+                                {
+                                    "name": "text",
+                                    "type": non_null(named("SCALAR", "String")),
+                                    "defaultValue": null
+                                },
+                                { "name": "broken", "type": { "kind": "LIST" } },
+                                {
+                                    "name": "limit",
+                                    "type": named("SCALAR", "Int"),
+                                    "defaultValue": "10"
+                                }
+                            ]
+                        },
+                        {
+                            "kind": "UNION",
+                            "name": "Either",
+                            "description": null,
+                            "possibleTypes": [named("OBJECT", "Root"), named("SCALAR", "DateTime")]
+                        },
+                        { "kind": "OBJECT", "name": "__Type", "fields": [] }
+                    ],
+                    "directives": [
+                        {
+                            "name": "skip",
+                            "locations": ["FIELD"],
+                            "args": []
+                        },
+                        {
+                            "name": "cached",
+                            "description": null,
+                            "locations": ["FIELD_DEFINITION", "OBJECT"],
+                            "args": [{
+                                "name": "seconds",
+                                "description": null,
+                                "type": non_null(named("SCALAR", "Int")),
+                                "defaultValue": null
+                            }]
+                        }
+                    ]
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn print_sdl_golden() {
+        let expected = r#"schema {
+  query: Root
+}
+
+directive @cached(seconds: Int!) on FIELD_DEFINITION | OBJECT
+
+"""
+The root of every query.
+"""
+type Root {
+  greeting(name: String = "world"): String!
+  items(
+    """
+    Which items to return.
+    """
+    filter: Filter
+  ): [DateTime!]! @deprecated(reason: "Use something else.")
+}
+
+"""
+An ISO 8601 timestamp.
+"""
+scalar DateTime
+
+enum Color {
+  RED
+  GREEN @deprecated
+}
+
+input Filter {
+  text: String!
+  limit: Int = 10
+}
+
+union Either = Root | DateTime
+"#;
+        assert_eq!(print_sdl(&introspection()).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn print_sdl_accepts_the_schema_object() {
+        let introspection = introspection();
+        assert_eq!(print_sdl(&introspection["data"]), print_sdl(&introspection));
+        assert_eq!(
+            print_sdl(&introspection["data"]["__schema"]),
+            print_sdl(&introspection)
+        );
+    }
+
+    #[test]
+    fn print_sdl_requires_types() {
+        assert_eq!(print_sdl(&json!({ "data": { "__schema": {} } })), None);
+    }
+
+    #[test]
+    fn schema_definition_is_omitted_for_default_root_names() {
+        let mut introspection = introspection();
+        introspection["data"]["__schema"]["queryType"] = json!({ "name": "Query" });
+        let sdl = print_sdl(&introspection).unwrap_or_default();
+        assert!(!sdl.contains("schema {"), "{sdl}");
+    }
+}
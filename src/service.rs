@@ -0,0 +1,966 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fmt, mem,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc, Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use graphql_parser::query::{Definition, OperationDefinition};
+use serde_json::{json, value::RawValue, Value};
+
+use crate::{
+    dispatcher::{self, DeliverySender},
+    executor::{self, Executor, SubscriptionHandle},
+    metrics,
+    recording::Recorder,
+    schema::{self, Schema, SchemaFormat},
+    slow_log,
+    timing::{unix_millis, Timing},
+    validation, PayloadExtensions, ResultPayload, SlowOperation, SubscriptionInfo,
+};
+
+/// Errors returned by the [`Service`] API. The COM wrapper maps these to an `HRESULT`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The query, operation name, variables or another argument were rejected.
+    InvalidArgument,
+    /// The executor or a result payload failed unexpectedly.
+    Unexpected,
+    /// The key does not belong to an active subscription in this session.
+    UnknownSubscription,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::InvalidArgument => "invalid argument",
+            Self::Unexpected => "unexpected failure",
+            Self::UnknownSubscription => "unknown subscription",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The outcome of starting an operation with [`Service::start_query`].
+pub enum QueryResult {
+    /// The operation completed with a single result, e.g. a query or mutation. The JSON from the
+    /// executor is checked, but it is not parsed into a `Value` and serialized again.
+    Results(ResultPayload),
+    /// The operation is still running as a subscription, and each `next` result will be delivered
+    /// through the receiver until the subscription is cancelled.
+    Pending { key: i32, next: NextReceiver },
+}
+
+/// Receives the `next` results of a pending operation, and counts each delivery in the
+/// [`Service::list_subscriptions`] entry for the operation.
+pub struct NextReceiver {
+    key: i32,
+    rx: mpsc::Receiver<String>,
+    subscriptions: Weak<Mutex<BTreeMap<i32, ActiveSubscription>>>,
+    timing: Option<Timing>,
+    received: Cell<Option<Instant>>,
+    recording: Option<(Arc<Recorder>, u64)>,
+}
+
+impl NextReceiver {
+    /// Wait for the next result, or fail once the operation has ended.
+    pub fn recv(&self) -> std::result::Result<String, mpsc::RecvError> {
+        let next = self.rx.recv()?;
+        Ok(self.delivered(next))
+    }
+
+    /// Wait for the next result like [`NextReceiver::recv`], but give up after `timeout`.
+    pub fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> std::result::Result<String, mpsc::RecvTimeoutError> {
+        let next = self.rx.recv_timeout(timeout)?;
+        Ok(self.delivered(next))
+    }
+
+    /// Record and count a result which was just received.
+    fn delivered(&self, next: String) -> String {
+        self.received.set(Some(Instant::now()));
+        if let Some((recorder, id)) = &self.recording {
+            recorder.next(*id, &next);
+        }
+        metrics::global().next_delivered();
+        if let Some(subscriptions) = self.subscriptions.upgrade() {
+            if let Ok(mut subscriptions) = subscriptions.lock() {
+                if let Some(subscription) = subscriptions.get_mut(&self.key) {
+                    subscription.deliveries += 1;
+                    subscription.last_delivery = Some(SystemTime::now());
+                }
+            }
+        }
+        next
+    }
+
+    /// The extensions for the result which was received last, if any were requested.
+    pub fn extensions(&self) -> Option<PayloadExtensions> {
+        let timing = self.timing.as_ref()?;
+        Some(timing.extensions(self.received.get()?))
+    }
+}
+
+/// Options for [`Service::start_query_with_options`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueryOptions {
+    /// Give up on a query or mutation which has not completed in time.
+    pub timeout: Option<Duration>,
+    /// Include the `tracing` extension with the timing of the operation in each payload.
+    pub tracing: bool,
+}
+
+impl QueryOptions {
+    /// Read the options which can be requested through the `extensions` of a request, e.g.
+    /// `{ "tracing": true }`. Unknown extensions are ignored.
+    pub fn with_extensions(mut self, extensions: &str) -> Result<Self> {
+        if extensions.trim().is_empty() {
+            return Ok(self);
+        }
+        let Ok(Value::Object(extensions)) = serde_json::from_str(extensions) else {
+            return Err(Error::InvalidArgument);
+        };
+        self.tracing = extensions.get("tracing") == Some(&Value::Bool(true));
+        Ok(self)
+    }
+}
+
+/// An operation which is still running, and what is known about it for debugging.
+pub(crate) struct ActiveSubscription {
+    _handle: SubscriptionHandle,
+    session: i32,
+    pub(crate) operation_name: String,
+    query: String,
+    variables: Box<RawValue>,
+    created: SystemTime,
+    started: Instant,
+    deliveries: u64,
+    last_delivery: Option<SystemTime>,
+}
+
+impl Drop for ActiveSubscription {
+    fn drop(&mut self) {
+        metrics::global().subscription_removed();
+    }
+}
+
+pub(crate) type Subscriptions = Arc<Mutex<BTreeMap<i32, ActiveSubscription>>>;
+
+/// The transport-independent core of the GraphQL service, which tracks active subscriptions on
+/// top of a pluggable [`Executor`]. The COM `GraphQLService`, the command line runner and the REPL
+/// all share this.
+///
+/// Each `Service` is a session which owns the subscriptions it starts. Sessions opened with
+/// [`Service::open_session`] share the executor and schema, and dropping a session, or calling
+/// [`Service::unsubscribe_all`], only ends its own subscriptions.
+pub struct Service {
+    executor: Arc<dyn Executor>,
+    session: i32,
+    next_session: Arc<AtomicI32>,
+    next_subscription: Arc<AtomicI32>,
+    subscriptions: Subscriptions,
+    schema: Arc<Mutex<Option<Arc<Schema>>>>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl Service {
+    pub fn new(executor: impl Executor + 'static) -> Self {
+        Self {
+            executor: Arc::new(executor),
+            session: 0,
+            next_session: Arc::new(AtomicI32::new(1)),
+            next_subscription: Arc::new(AtomicI32::new(1)),
+            subscriptions: Arc::new(Mutex::new(BTreeMap::new())),
+            schema: Arc::new(Mutex::new(None)),
+            recorder: None,
+        }
+    }
+
+    /// Record every request and its results with `recorder`, including those from sessions
+    /// opened after this, so they can be replayed with a [`crate::ReplayExecutor`].
+    pub fn with_recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Open a new session on the same executor, e.g. for each page loaded in a WebView, so that
+    /// everything the page subscribed to can be cleaned up at once when it navigates away.
+    pub fn open_session(&self) -> Self {
+        Self {
+            executor: self.executor.clone(),
+            session: self.next_session.fetch_add(1, Ordering::Relaxed),
+            next_session: self.next_session.clone(),
+            next_subscription: self.next_subscription.clone(),
+            subscriptions: self.subscriptions.clone(),
+            schema: self.schema.clone(),
+            recorder: self.recorder.clone(),
+        }
+    }
+
+    /// Start an operation and listen for results. If the operation does not complete immediately,
+    /// it is added to the active subscriptions and the caller must end it with
+    /// [`Service::cancel_subscription`] once it is no longer needed.
+    ///
+    /// The document and variables are validated against the schema first, and if they are not
+    /// valid the result has an `errors` entry for every violation instead of `data`. Otherwise
+    /// the executor receives the coerced variables.
+    pub fn start_query(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+    ) -> Result<QueryResult> {
+        self.start_query_with_timeout(query, operation_name, variables, None)
+    }
+
+    /// Start an operation like [`Service::start_query`], but give up on a query or mutation which
+    /// has not completed within `timeout`. Subscriptions are not affected, since they are expected
+    /// to stay open until they are cancelled.
+    ///
+    /// A query or mutation which does not complete immediately is still `Pending`, and the key can
+    /// be passed to [`Service::cancel_subscription`] to cancel it. Either way it is dropped, and its
+    /// only `next` result is an error with an `extensions.code` of `TIMEOUT` or `CANCELLED`.
+    pub fn start_query_with_timeout(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        timeout: Option<Duration>,
+    ) -> Result<QueryResult> {
+        let options = QueryOptions {
+            timeout,
+            ..Default::default()
+        };
+        self.start_query_with_options(query, operation_name, variables, options)
+    }
+
+    /// Start an operation like [`Service::start_query`], with a timeout like
+    /// [`Service::start_query_with_timeout`] and any of the other [`QueryOptions`].
+    pub fn start_query_with_options(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        options: QueryOptions,
+    ) -> Result<QueryResult> {
+        let QueryOptions { timeout, tracing } = options;
+        let mut timing = Timing::start();
+        let started = Instant::now();
+        let operation = if operation_name.is_empty() {
+            executor::first_operation_name(query).unwrap_or_default()
+        } else {
+            operation_name.to_string()
+        };
+        let span = tracing::info_span!(
+            "start_query",
+            operation = %operation,
+            session = self.session,
+            key = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+
+        let validated = tracing::info_span!("validate")
+            .in_scope(|| self.validate_with_timing(query, operation_name, variables, &mut timing));
+        let recording = self.recorder.as_ref().map(|recorder| {
+            // Record the coerced variables which the executor receives, which is what a replay is
+            // matched against. A request which failed validation only has the raw variables.
+            let recorded = validated.as_deref().unwrap_or(variables);
+            let id = recorder.request(query, operation_name, recorded);
+            (recorder.clone(), id)
+        });
+        let variables = match validated {
+            Ok(variables) => variables,
+            Err(violations) => {
+                tracing::info!(violations = violations.len(), "the request is not valid");
+                metrics::global().operation_rejected();
+                let results =
+                    serde_json::value::to_raw_value(&validation::errors_result(&violations))
+                        .map_err(|_| Error::Unexpected)?;
+                if let Some((recorder, id)) = &recording {
+                    recorder.results(*id, results.get());
+                }
+                return Ok(QueryResult::Results(ResultPayload {
+                    results,
+                    extensions: tracing.then(|| timing.extensions(Instant::now())),
+                }));
+            }
+        };
+        let (tx_next, rx_next) = mpsc::channel();
+        let (tx_complete, rx_complete) = mpsc::channel();
+
+        let execution = Instant::now();
+        let key = {
+            let subscribed = tracing::info_span!("subscribe").in_scope(|| {
+                self.executor
+                    .subscribe(query, operation_name, &variables, tx_next, tx_complete)
+            });
+            timing.execution = timing.phase(execution, Instant::now());
+            let subscription = match subscribed {
+                Ok(subscription) => subscription,
+                Err(error) => {
+                    metrics::global().operation_failed();
+                    return Err(error);
+                }
+            };
+            let variables = RawValue::from_string(variables).map_err(|_| Error::Unexpected)?;
+
+            let Ok(mut subscriptions) = self.subscriptions.lock() else {
+                return Err(Error::Unexpected);
+            };
+            let key: i32 = self.next_subscription.fetch_add(1, Ordering::Relaxed);
+            metrics::global().subscription_added();
+            subscriptions.insert(
+                key,
+                ActiveSubscription {
+                    _handle: subscription,
+                    session: self.session,
+                    operation_name: operation,
+                    query: query.to_string(),
+                    variables,
+                    created: SystemTime::now(),
+                    started,
+                    deliveries: 0,
+                    last_delivery: None,
+                },
+            );
+
+            key
+        };
+        span.record("key", key);
+        metrics::global().operation_started(started.elapsed());
+
+        let timing = tracing.then_some(timing);
+        match rx_complete.try_recv() {
+            Ok(()) => {
+                let results = rx_next.recv().map_err(|_| Error::Unexpected);
+                let received = Instant::now();
+                let results = results.and_then(|results| {
+                    tracing::debug_span!("decode", bytes = results.len())
+                        .in_scope(|| RawValue::from_string(results))
+                        .map_err(|_| Error::Unexpected)
+                });
+                record_if_slow(key, &self.subscriptions, None);
+                drop_subscription(key, &self.subscriptions)?;
+                let results = results?;
+                if let Some((recorder, id)) = &recording {
+                    recorder.results(*id, results.get());
+                }
+                Ok(QueryResult::Results(ResultPayload {
+                    results,
+                    extensions: timing.map(|mut timing| {
+                        timing.execution = timing.phase(execution, received);
+                        timing.extensions(received)
+                    }),
+                }))
+            }
+            Err(_) if is_subscription(query, operation_name) => Ok(QueryResult::Pending {
+                key,
+                next: self.next_receiver(key, rx_next, timing, recording),
+            }),
+            Err(_) => {
+                let (tx_results, rx_results) = mpsc::channel();
+                let subscriptions = self.subscriptions.clone();
+                let listen = tracing::info_span!(
+                    "listen",
+                    key,
+                    timeout_ms = timeout.map(|timeout| timeout.as_millis() as u64)
+                );
+                thread::spawn(move || {
+                    let _entered = listen.enter();
+                    wait_for_results(
+                        key,
+                        rx_next,
+                        rx_complete,
+                        tx_results,
+                        timeout,
+                        &subscriptions,
+                    )
+                });
+                Ok(QueryResult::Pending {
+                    key,
+                    next: self.next_receiver(key, rx_results, timing, recording),
+                })
+            }
+        }
+    }
+
+    /// End a subscription started with [`Service::start_query`]. This fails with
+    /// [`Error::UnknownSubscription`] if the key is not active in this session, e.g. because the
+    /// operation already completed, and subscriptions which belong to another session are left
+    /// alone.
+    pub fn cancel_subscription(&self, key: i32) -> Result<()> {
+        let Ok(mut subscriptions) = self.subscriptions.lock() else {
+            return Err(Error::Unexpected);
+        };
+        if subscriptions
+            .get(&key)
+            .is_none_or(|subscription| subscription.session != self.session)
+        {
+            return Err(Error::UnknownSubscription);
+        }
+        let subscription = subscriptions.remove(&key);
+        drop(subscriptions);
+        drop(subscription);
+        Ok(())
+    }
+
+    /// End every subscription started in this session, and return their keys. The handles are
+    /// dropped after the lock is released, so the executor can take as long as it needs to tear
+    /// them down.
+    pub fn unsubscribe_all(&self) -> Result<Vec<i32>> {
+        let Ok(mut subscriptions) = self.subscriptions.lock() else {
+            return Err(Error::Unexpected);
+        };
+        let (ended, active): (BTreeMap<_, _>, BTreeMap<_, _>) = mem::take(&mut *subscriptions)
+            .into_iter()
+            .partition(|(_, subscription)| subscription.session == self.session);
+        *subscriptions = active;
+        drop(subscriptions);
+
+        let keys = ended.keys().copied().collect();
+        drop(ended);
+        Ok(keys)
+    }
+
+    /// Describe every active subscription in this session, including queries which have not
+    /// completed yet.
+    pub fn list_subscriptions(&self) -> Result<Vec<SubscriptionInfo>> {
+        let Ok(subscriptions) = self.subscriptions.lock() else {
+            return Err(Error::Unexpected);
+        };
+        Ok(subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.session == self.session)
+            .map(|(&key, subscription)| SubscriptionInfo {
+                key,
+                operation: subscription.operation_name.clone(),
+                variables: subscription.variables.clone(),
+                created: unix_millis(subscription.created),
+                deliveries: subscription.deliveries,
+                delivered: subscription.last_delivery.map(unix_millis),
+            })
+            .collect())
+    }
+
+    /// Forward the results of a pending operation to its callback through `sender` on a worker
+    /// thread. When the operation ends, the callback is released and the subscription is dropped.
+    pub fn deliver_results(
+        &self,
+        key: i32,
+        next: NextReceiver,
+        sender: DeliverySender,
+    ) -> JoinHandle<Result<()>> {
+        dispatcher::spawn_worker(key, next, sender, self.subscriptions.clone())
+    }
+
+    fn next_receiver(
+        &self,
+        key: i32,
+        rx: mpsc::Receiver<String>,
+        timing: Option<Timing>,
+        recording: Option<(Arc<Recorder>, u64)>,
+    ) -> NextReceiver {
+        NextReceiver {
+            key,
+            rx,
+            subscriptions: Arc::downgrade(&self.subscriptions),
+            timing,
+            received: Cell::new(None),
+            recording,
+        }
+    }
+
+    /// The keys of every active subscription in this session.
+    pub fn active_subscriptions(&self) -> Vec<i32> {
+        self.subscriptions
+            .lock()
+            .map(|subscriptions| {
+                subscriptions
+                    .iter()
+                    .filter(|(_, subscription)| subscription.session == self.session)
+                    .map(|(&key, _)| key)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Validate a request against the schema of the executor, and return either the coerced
+    /// variables as a JSON string or every violation. If the executor does not support
+    /// introspection, there is nothing to validate against and this only checks that the variables
+    /// are a JSON object.
+    pub fn validate(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+    ) -> std::result::Result<String, Vec<validation::Violation>> {
+        self.validate_with_timing(query, operation_name, variables, &mut Timing::start())
+    }
+
+    /// Validate a request like [`Service::validate`], and record how long parsing and validation
+    /// took.
+    fn validate_with_timing(
+        &self,
+        query: &str,
+        operation_name: &str,
+        variables: &str,
+        timing: &mut Timing,
+    ) -> std::result::Result<String, Vec<validation::Violation>> {
+        let variables = if variables.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            match serde_json::from_str(variables) {
+                Ok(variables @ Value::Object(_)) => variables,
+                _ => {
+                    return Err(vec![validation::Violation {
+                        message: "Variables must be a JSON object.".to_string(),
+                        locations: Vec::new(),
+                    }])
+                }
+            }
+        };
+
+        let Some(schema) = self.schema() else {
+            return Ok(variables.to_string());
+        };
+        let (document, parsing) = timing.measure(|| validation::parse(query));
+        timing.parsing = Some(parsing);
+        let document = document?;
+        let (variables, validation) = timing.measure(|| {
+            validation::validate_document(&schema, &document, operation_name, &variables)
+        });
+        timing.validation = Some(validation);
+        variables.map(|variables| variables.to_string())
+    }
+
+    /// The schema model used for validation, which is loaded with an introspection query the first
+    /// time it is needed. It is only kept once it loads, so if introspection fails, e.g. because the
+    /// executor is not ready yet, the next call tries again.
+    pub fn schema(&self) -> Option<Arc<Schema>> {
+        let mut schema = self.schema.lock().ok()?;
+        if schema.is_none() {
+            *schema = self.load_schema().map(Arc::new);
+        }
+        schema.clone()
+    }
+
+    fn load_schema(&self) -> Option<Schema> {
+        let results = self.get_schema(SchemaFormat::Introspection).ok()?;
+        Schema::from_introspection(&serde_json::from_str(&results).ok()?)
+    }
+
+    /// Get the schema of the executor, either as the raw introspection results in JSON or printed
+    /// as SDL.
+    pub fn get_schema(&self, format: SchemaFormat) -> Result<String> {
+        let results = self.execute(
+            schema::INTROSPECTION_QUERY,
+            schema::INTROSPECTION_OPERATION_NAME,
+            "",
+        )?;
+
+        match format {
+            SchemaFormat::Introspection => Ok(results),
+            SchemaFormat::Sdl => {
+                let Ok(results) = serde_json::from_str(&results) else {
+                    return Err(Error::Unexpected);
+                };
+                schema::print_sdl(&results).ok_or(Error::Unexpected)
+            }
+        }
+    }
+
+    /// Synchronously execute a query which is expected to complete with a single result, without
+    /// registering it in the `subscriptions` map.
+    fn execute(&self, query: &str, operation_name: &str, variables: &str) -> Result<String> {
+        let (tx_next, rx_next) = mpsc::channel();
+        let (tx_complete, _rx_complete) = mpsc::channel();
+        let _subscription =
+            self.executor
+                .subscribe(query, operation_name, variables, tx_next, tx_complete)?;
+
+        let results = rx_next.recv().map_err(|_| Error::Unexpected)?;
+        // The introspection query is recorded too, so a replay validates against the same schema.
+        if let Some(recorder) = &self.recorder {
+            let id = recorder.request(query, operation_name, variables);
+            recorder.results(id, &results);
+        }
+        Ok(results)
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        let _ = self.unsubscribe_all();
+    }
+}
+
+#[cfg(windows)]
+impl Default for Service {
+    fn default() -> Self {
+        Self::new(crate::executor::MapiExecutor::new(true))
+    }
+}
+
+/// Wait for a query or mutation which did not complete immediately, and forward its results. If it
+/// times out, it is dropped here, and if it is cancelled the executor drops `complete` without
+/// signalling it. In both cases an error result is sent in place of the results.
+fn wait_for_results(
+    key: i32,
+    next: mpsc::Receiver<String>,
+    complete: mpsc::Receiver<()>,
+    results: mpsc::Sender<String>,
+    timeout: Option<Duration>,
+    subscriptions: &Mutex<BTreeMap<i32, ActiveSubscription>>,
+) {
+    let completed = match timeout {
+        Some(timeout) => complete.recv_timeout(timeout),
+        None => complete
+            .recv()
+            .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+    };
+
+    // The executor sends the results before it signals `complete`.
+    let mut forwarded = false;
+    for next in next.try_iter() {
+        forwarded = results.send(next).is_ok() || forwarded;
+    }
+    // A cancelled operation has already been dropped, so it is not recorded.
+    record_if_slow(key, subscriptions, None);
+
+    let error = match completed {
+        Ok(()) => return,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            tracing::info!("the operation timed out");
+            let _ = drop_subscription(key, subscriptions);
+            operation_error("The operation timed out.", "TIMEOUT")
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) if !forwarded => {
+            tracing::info!("the operation was cancelled");
+            operation_error("The operation was cancelled.", "CANCELLED")
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+    };
+    let _ = results.send(error);
+}
+
+/// An execution result for an operation which ended without any results of its own.
+fn operation_error(message: &str, code: &str) -> String {
+    json!({
+        "data": null,
+        "errors": [{
+            "message": message,
+            "extensions": { "code": code },
+        }],
+    })
+    .to_string()
+}
+
+/// Check if the selected operation in a document is a subscription. The document is expected to
+/// have been validated already, e.g. by [`Service::start_query`], so one which does not parse is
+/// treated as a query.
+pub fn is_subscription(query: &str, operation_name: &str) -> bool {
+    let Ok(document) = graphql_parser::parse_query::<&str>(query) else {
+        return false;
+    };
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::Operation(operation) => Some(operation),
+            Definition::Fragment(_) => None,
+        })
+        .find(|operation| {
+            let name = match operation {
+                OperationDefinition::SelectionSet(_) => None,
+                OperationDefinition::Query(query) => query.name,
+                OperationDefinition::Mutation(mutation) => mutation.name,
+                OperationDefinition::Subscription(subscription) => subscription.name,
+            };
+            operation_name.is_empty() || name == Some(operation_name)
+        })
+        .is_some_and(|operation| matches!(operation, OperationDefinition::Subscription(_)))
+}
+
+pub(crate) fn drop_subscription(
+    key: i32,
+    subscriptions: &Mutex<BTreeMap<i32, ActiveSubscription>>,
+) -> Result<()> {
+    let Ok(mut subscriptions) = subscriptions.lock() else {
+        return Err(Error::Unexpected);
+    };
+    subscriptions.remove(&key);
+    Ok(())
+}
+
+/// Add an operation to the [`slow_log`] if it has taken longer than the threshold so far, or if
+/// `backlog` is set and more than the threshold of its payloads are waiting for its callback.
+pub(crate) fn record_if_slow(
+    key: i32,
+    subscriptions: &Mutex<BTreeMap<i32, ActiveSubscription>>,
+    backlog: Option<u64>,
+) {
+    let slow_log = slow_log::global();
+    let entry = {
+        let Ok(subscriptions) = subscriptions.lock() else {
+            return;
+        };
+        let Some(subscription) = subscriptions.get(&key) else {
+            return;
+        };
+        let duration = subscription.started.elapsed();
+        let reason = match backlog {
+            Some(backlog) if slow_log.exceeds_backlog(backlog) => "backlog",
+            None if slow_log.exceeds_threshold(duration) => "duration",
+            _ => return,
+        };
+        SlowOperation {
+            key,
+            operation: subscription.operation_name.clone(),
+            query: subscription.query.clone(),
+            variables: serde_json::from_str(subscription.variables.get()).unwrap_or_default(),
+            reason: reason.to_string(),
+            started: unix_millis(subscription.created),
+            duration: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            backlog,
+        }
+    };
+    slow_log.record(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        executor::fixtures::{
+            inbox, item_added, service, subscribe, StartingExecutor, INBOX, ITEMS, PENDING,
+        },
+        recording::{Event, ReplayExecutor},
+    };
+
+    #[test]
+    fn completes_queries_immediately() {
+        match service().start_query(INBOX, "", "").unwrap() {
+            QueryResult::Results(payload) => {
+                assert_eq!(payload.results.get(), inbox().to_string())
+            }
+            QueryResult::Pending { .. } => panic!("expected results"),
+        }
+    }
+
+    #[test]
+    fn loads_the_schema_once_introspection_succeeds() {
+        let executor = StartingExecutor::new();
+        let ready = executor.ready.clone();
+        let service = Service::new(executor);
+        assert!(service.schema().is_none());
+        assert_eq!(
+            service.validate("query Inbox { items { unknown } }", "", ""),
+            Ok(String::from("{}"))
+        );
+
+        ready.store(true, Ordering::SeqCst);
+        let schema = service.schema().unwrap();
+        assert!(service
+            .validate("query Inbox { items { unknown } }", "", "")
+            .is_err());
+
+        // Once it loaded, it is kept even if introspection would fail again.
+        ready.store(false, Ordering::SeqCst);
+        assert!(Arc::ptr_eq(&service.schema().unwrap(), &schema));
+        assert!(Arc::ptr_eq(
+            &service.open_session().schema().unwrap(),
+            &schema
+        ));
+    }
+
+    #[test]
+    fn passes_results_through_verbatim() {
+        // A replay serves the recorded bytes, so the spacing and key order show whether the
+        // results were parsed and serialized again on the way through.
+        let results = r#"{ "data": { "items": [], "total": 1.50 }, "extensions": {} }"#;
+        let service = Service::new(ReplayExecutor::new([
+            Event::Request {
+                id: 1,
+                time: 0,
+                query: INBOX.to_string(),
+                operation_name: String::new(),
+                variables: RawValue::from_string("{}".to_string()).unwrap(),
+            },
+            Event::Results {
+                id: 1,
+                time: 0,
+                results: RawValue::from_string(results.to_string()).unwrap(),
+            },
+        ]));
+        match service.start_query(INBOX, "", "").unwrap() {
+            QueryResult::Results(payload) => assert_eq!(payload.results.get(), results),
+            QueryResult::Pending { .. } => panic!("expected results"),
+        }
+    }
+
+    #[test]
+    fn cancels_active_subscriptions_once() {
+        let service = service();
+        let (key, next) = subscribe(&service, ITEMS);
+        assert_eq!(next.recv().unwrap(), item_added(1).to_string());
+        assert_eq!(next.recv().unwrap(), item_added(2).to_string());
+        assert_eq!(service.active_subscriptions(), vec![key]);
+
+        assert_eq!(service.cancel_subscription(key), Ok(()));
+        assert!(service.active_subscriptions().is_empty());
+        assert!(next.recv().is_err());
+        assert_eq!(
+            service.cancel_subscription(key),
+            Err(Error::UnknownSubscription)
+        );
+    }
+
+    /// Start [`PENDING`], which never completes, with a `timeout`.
+    fn start_pending(service: &Service, timeout: Option<Duration>) -> (i32, NextReceiver) {
+        match service
+            .start_query_with_timeout(PENDING, "", "", timeout)
+            .unwrap()
+        {
+            QueryResult::Pending { key, next } => (key, next),
+            QueryResult::Results(payload) => panic!("expected pending: {}", payload.results.get()),
+        }
+    }
+
+    /// The `extensions.code` of the only error in `results`.
+    fn error_code(results: &str) -> Value {
+        let results: Value = serde_json::from_str(results).unwrap();
+        assert_eq!(results["data"], Value::Null);
+        assert_eq!(results["errors"].as_array().map(Vec::len), Some(1));
+        results["errors"][0]["extensions"]["code"].clone()
+    }
+
+    #[test]
+    fn times_out_pending_queries() {
+        let service = service();
+        let (key, next) = start_pending(&service, Some(Duration::from_millis(50)));
+        assert_eq!(service.active_subscriptions(), vec![key]);
+
+        assert_eq!(error_code(&next.recv().unwrap()), "TIMEOUT");
+        assert!(next.recv().is_err());
+        assert!(service.active_subscriptions().is_empty());
+        assert_eq!(
+            service.cancel_subscription(key),
+            Err(Error::UnknownSubscription)
+        );
+    }
+
+    #[test]
+    fn reports_cancelled_queries() {
+        let service = service();
+        let (key, next) = start_pending(&service, None);
+        assert_eq!(
+            next.recv_timeout(Duration::from_millis(50)),
+            Err(mpsc::RecvTimeoutError::Timeout)
+        );
+
+        assert_eq!(service.cancel_subscription(key), Ok(()));
+        assert!(service.active_subscriptions().is_empty());
+        assert_eq!(error_code(&next.recv().unwrap()), "CANCELLED");
+        assert!(next.recv().is_err());
+    }
+
+    #[test]
+    fn counts_deliveries_in_the_list_of_subscriptions() {
+        let service = service();
+        let (key, next) = subscribe(&service, ITEMS);
+        let listed = service.list_subscriptions().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(
+            (listed[0].key, listed[0].operation.as_str()),
+            (key, "Items")
+        );
+        assert_eq!(listed[0].deliveries, 0);
+        assert_eq!(listed[0].delivered, None);
+
+        assert_eq!(next.recv().unwrap(), item_added(1).to_string());
+        assert_eq!(next.recv().unwrap(), item_added(2).to_string());
+        let listed = service.list_subscriptions().unwrap();
+        assert_eq!(listed[0].deliveries, 2);
+        assert!(listed[0]
+            .delivered
+            .is_some_and(|delivered| delivered >= listed[0].created));
+
+        assert_eq!(service.cancel_subscription(key), Ok(()));
+        assert!(service.list_subscriptions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(
+            service().cancel_subscription(42),
+            Err(Error::UnknownSubscription)
+        );
+    }
+
+    #[test]
+    fn leaves_subscriptions_from_other_sessions_alone() {
+        let service = service();
+        let session = service.open_session();
+        let (key, _next) = subscribe(&session, ITEMS);
+
+        assert_eq!(
+            service.cancel_subscription(key),
+            Err(Error::UnknownSubscription)
+        );
+        assert_eq!(service.unsubscribe_all(), Ok(Vec::new()));
+        assert_eq!(session.active_subscriptions(), vec![key]);
+
+        assert_eq!(session.unsubscribe_all(), Ok(vec![key]));
+        assert!(session.active_subscriptions().is_empty());
+    }
+
+    #[test]
+    fn ends_the_subscriptions_of_a_session_when_it_is_dropped() {
+        let service = service();
+        let (kept, _kept_next) = subscribe(&service, ITEMS);
+        let session = service.open_session();
+        let (ended, ended_next) = subscribe(&session, ITEMS);
+        let (pending, pending_next) = subscribe(&session, PENDING);
+        assert_eq!(session.active_subscriptions(), vec![ended, pending]);
+
+        drop(session);
+        assert_eq!(service.active_subscriptions(), vec![kept]);
+        // Both the subscription and the pending query were dropped, after any results they had.
+        assert_eq!(ended_next.recv().unwrap(), item_added(1).to_string());
+        assert_eq!(ended_next.recv().unwrap(), item_added(2).to_string());
+        assert!(ended_next.recv().is_err());
+        assert!(pending_next.recv().is_ok());
+        assert!(pending_next.recv().is_err());
+    }
+
+    #[test]
+    fn gives_each_session_its_own_keys() {
+        let service = service();
+        let first = service.open_session();
+        let second = first.open_session();
+        let keys = [
+            subscribe(&service, ITEMS).0,
+            subscribe(&first, ITEMS).0,
+            subscribe(&second, ITEMS).0,
+        ];
+        assert_eq!(
+            [&service, &first, &second].map(Service::active_subscriptions),
+            keys.map(|key| vec![key])
+        );
+        assert_eq!(
+            first.cancel_subscription(keys[2]),
+            Err(Error::UnknownSubscription)
+        );
+        assert_eq!(second.cancel_subscription(keys[2]), Ok(()));
+    }
+}
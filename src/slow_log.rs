@@ -0,0 +1,301 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use graphql_parser::query::{
+    Definition, Directive, Document, OperationDefinition, Selection, SelectionSet, Value as Literal,
+};
+use serde_json::Value;
+
+use crate::SlowOperation;
+
+/// What the [`SlowLog`] records, and how many entries it keeps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowLogConfig {
+    /// Record a query or mutation which takes longer than this to complete.
+    pub threshold: Duration,
+    /// Record a subscription once more than this many `next` payloads are waiting for its callback.
+    pub backlog: u64,
+    /// How many entries to keep, after which the oldest are dropped.
+    pub capacity: usize,
+    /// Replace every string, number and boolean in the variables with `"[redacted]"`, and every
+    /// argument literal in the document, since they may contain the contents of the mailbox.
+    pub redact: bool,
+}
+
+impl Default for SlowLogConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_secs(1),
+            backlog: 100,
+            capacity: 100,
+            redact: true,
+        }
+    }
+}
+
+/// A ring buffer of the operations which were slow to complete, or whose callbacks fell behind,
+/// for every [`crate::Service`] in the process. This is how to find out which documents are
+/// hurting responsiveness without logging every operation.
+#[derive(Default)]
+pub struct SlowLog {
+    config: Mutex<SlowLogConfig>,
+    entries: Mutex<VecDeque<SlowOperation>>,
+}
+
+/// The process-wide slow-operation log.
+pub fn global() -> &'static SlowLog {
+    static SLOW_LOG: OnceLock<SlowLog> = OnceLock::new();
+    SLOW_LOG.get_or_init(SlowLog::default)
+}
+
+impl SlowLog {
+    pub fn config(&self) -> SlowLogConfig {
+        self.config
+            .lock()
+            .map(|config| config.clone())
+            .unwrap_or_default()
+    }
+
+    /// Change the thresholds, which apply to operations from now on. Shrinking the capacity drops
+    /// the oldest entries.
+    pub fn configure(&self, config: SlowLogConfig) {
+        if let Ok(mut entries) = self.entries.lock() {
+            let excess = entries.len().saturating_sub(config.capacity);
+            entries.drain(..excess);
+        }
+        if let Ok(mut current) = self.config.lock() {
+            *current = config;
+        }
+    }
+
+    /// Every entry which has been recorded and not dropped yet, oldest first.
+    pub fn entries(&self) -> Vec<SlowOperation> {
+        self.entries
+            .lock()
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    pub(crate) fn exceeds_threshold(&self, duration: Duration) -> bool {
+        duration > self.config().threshold
+    }
+
+    pub(crate) fn exceeds_backlog(&self, backlog: u64) -> bool {
+        backlog > self.config().backlog
+    }
+
+    /// Add an entry, redacting its document and variables first if the log is configured to.
+    pub(crate) fn record(&self, mut entry: SlowOperation) {
+        let config = self.config();
+        if config.capacity == 0 {
+            return;
+        }
+        if config.redact {
+            entry.query = redact_query(&entry.query);
+            redact(&mut entry.variables);
+        }
+        tracing::info!(
+            key = entry.key,
+            operation = %entry.operation,
+            reason = %entry.reason,
+            duration_ms = entry.duration,
+            "recorded a slow operation"
+        );
+        if let Ok(mut entries) = self.entries.lock() {
+            while entries.len() >= config.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+}
+
+/// What every redacted value is replaced with.
+const REDACTED: &str = "[redacted]";
+
+/// Replace every string, number and boolean in a JSON value, keeping its shape along with any
+/// nulls, which is enough to tell requests apart without leaking their contents.
+fn redact(value: &mut Value) {
+    match value {
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => *value = Value::from(REDACTED),
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        Value::Object(values) => values.values_mut().for_each(redact),
+        Value::Null => {}
+    }
+}
+
+/// Replace the literals in the arguments of a document, including directives and the default
+/// values of variables, and print it again. Strings become `"[redacted]"`, numbers become 0,
+/// booleans become `false`, and lists and objects become empty, so the document still parses.
+/// Variables, enum values and nulls are kept. A document which does not parse is replaced
+/// entirely.
+fn redact_query(query: &str) -> String {
+    let Ok(mut document) = graphql_parser::parse_query::<String>(query) else {
+        return String::from(REDACTED);
+    };
+    redact_document(&mut document);
+    document.to_string()
+}
+
+fn redact_document(document: &mut Document<String>) {
+    for definition in &mut document.definitions {
+        match definition {
+            Definition::Operation(OperationDefinition::SelectionSet(selection_set)) => {
+                redact_selection_set(selection_set)
+            }
+            Definition::Operation(OperationDefinition::Query(query)) => {
+                for variable in &mut query.variable_definitions {
+                    variable.default_value.iter_mut().for_each(redact_literal);
+                }
+                redact_directives(&mut query.directives);
+                redact_selection_set(&mut query.selection_set);
+            }
+            Definition::Operation(OperationDefinition::Mutation(mutation)) => {
+                for variable in &mut mutation.variable_definitions {
+                    variable.default_value.iter_mut().for_each(redact_literal);
+                }
+                redact_directives(&mut mutation.directives);
+                redact_selection_set(&mut mutation.selection_set);
+            }
+            Definition::Operation(OperationDefinition::Subscription(subscription)) => {
+                for variable in &mut subscription.variable_definitions {
+                    variable.default_value.iter_mut().for_each(redact_literal);
+                }
+                redact_directives(&mut subscription.directives);
+                redact_selection_set(&mut subscription.selection_set);
+            }
+            Definition::Fragment(fragment) => {
+                redact_directives(&mut fragment.directives);
+                redact_selection_set(&mut fragment.selection_set);
+            }
+        }
+    }
+}
+
+fn redact_selection_set(selection_set: &mut SelectionSet<String>) {
+    for selection in &mut selection_set.items {
+        match selection {
+            Selection::Field(field) => {
+                for (_, argument) in &mut field.arguments {
+                    redact_literal(argument);
+                }
+                redact_directives(&mut field.directives);
+                redact_selection_set(&mut field.selection_set);
+            }
+            Selection::FragmentSpread(spread) => redact_directives(&mut spread.directives),
+            Selection::InlineFragment(fragment) => {
+                redact_directives(&mut fragment.directives);
+                redact_selection_set(&mut fragment.selection_set);
+            }
+        }
+    }
+}
+
+fn redact_directives(directives: &mut [Directive<String>]) {
+    for directive in directives {
+        for (_, argument) in &mut directive.arguments {
+            redact_literal(argument);
+        }
+    }
+}
+
+fn redact_literal(value: &mut Literal<String>) {
+    match value {
+        Literal::String(_) => *value = Literal::String(String::from(REDACTED)),
+        Literal::Int(_) => *value = Literal::Int(0.into()),
+        Literal::Float(_) => *value = Literal::Float(0.0),
+        Literal::Boolean(_) => *value = Literal::Boolean(false),
+        Literal::List(values) => values.clear(),
+        Literal::Object(fields) => fields.clear(),
+        Literal::Variable(_) | Literal::Enum(_) | Literal::Null => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn redacts_every_scalar_variable() {
+        let mut variables = json!({
+            "subject": "Quarterly results",
+            "count": 10,
+            "ratio": 0.5,
+            "unread": true,
+            "folder": null,
+            "ids": ["a", 1, false],
+            "filter": { "from": "someone@example.com", "before": 1_700_000_000 },
+        });
+        redact(&mut variables);
+        assert_eq!(
+            variables,
+            json!({
+                "subject": REDACTED,
+                "count": REDACTED,
+                "ratio": REDACTED,
+                "unread": REDACTED,
+                "folder": null,
+                "ids": [REDACTED, REDACTED, REDACTED],
+                "filter": { "from": REDACTED, "before": REDACTED },
+            })
+        );
+    }
+
+    #[test]
+    fn redacts_argument_literals() {
+        let query = redact_query(
+            r#"query Search($folder: ID = "inbox", $first: Int = 10) @cached(ttl: 60) {
+                items(folder: $folder, first: $first, subject: "Quarterly results",
+                      ratio: 0.5, unread: true, order: DESCENDING, after: null,
+                      ids: ["a", "b"], filter: { from: "someone@example.com" }) {
+                    ... on Message @include(if: true) { subject(truncate: 20) }
+                    ...Details @skip(if: false)
+                }
+            }
+            fragment Details on Message { body(format: "html") }"#,
+        );
+
+        for secret in [
+            "inbox",
+            "Quarterly",
+            "someone",
+            "html",
+            "60",
+            "20",
+            "10",
+            "0.5",
+            "true",
+        ] {
+            assert!(!query.contains(secret), "{secret} in {query}");
+        }
+        for kept in [
+            "$folder: ID = \"[redacted]\"",
+            "$first: Int = 0",
+            "folder: $folder",
+            "order: DESCENDING",
+            "after: null",
+            "ids: []",
+            "filter: {}",
+            "@include(if: false)",
+        ] {
+            assert!(query.contains(kept), "{kept} not in {query}");
+        }
+        assert!(graphql_parser::parse_query::<String>(&query).is_ok());
+    }
+
+    #[test]
+    fn replaces_documents_which_do_not_parse() {
+        assert_eq!(redact_query(r#"{ items(subject: "secret" }"#), REDACTED);
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::{variant, PayloadExtensions, Tracing, TracingExecution, TracingPhase};
+
+/// Records when each phase of an operation happened, for the `tracing` extension.
+#[derive(Clone, Debug)]
+pub(crate) struct Timing {
+    start_time: SystemTime,
+    start: Instant,
+    pub(crate) parsing: Option<TracingPhase>,
+    pub(crate) validation: Option<TracingPhase>,
+    pub(crate) execution: TracingPhase,
+}
+
+impl Timing {
+    pub(crate) fn start() -> Self {
+        Self {
+            start_time: SystemTime::now(),
+            start: Instant::now(),
+            parsing: None,
+            validation: None,
+            execution: TracingPhase::default(),
+        }
+    }
+
+    /// Run one phase of the operation, and return its result along with when it ran.
+    pub(crate) fn measure<T>(&self, phase: impl FnOnce() -> T) -> (T, TracingPhase) {
+        let started = Instant::now();
+        let result = phase();
+        (result, self.phase(started, Instant::now()))
+    }
+
+    /// Describe a phase which ran from `started` to `ended`.
+    pub(crate) fn phase(&self, started: Instant, ended: Instant) -> TracingPhase {
+        TracingPhase {
+            startOffset: nanos(started.saturating_duration_since(self.start)),
+            duration: nanos(ended.saturating_duration_since(started)),
+        }
+    }
+
+    /// The extensions for a payload whose results arrived at `ended`.
+    pub(crate) fn extensions(&self, ended: Instant) -> PayloadExtensions {
+        let duration = ended.saturating_duration_since(self.start);
+        PayloadExtensions {
+            tracing: Tracing {
+                version: 1,
+                startTime: rfc3339(self.start_time),
+                endTime: rfc3339(self.start_time + duration),
+                duration: nanos(duration),
+                parsing: self.parsing,
+                validation: self.validation,
+                execution: TracingExecution {
+                    startOffset: self.execution.startOffset,
+                    duration: self.execution.duration,
+                    resolvers: Vec::new(),
+                },
+            },
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, which script can pass to `new Date()`.
+pub(crate) fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| {
+            u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC with milliseconds, e.g.
+/// `2023-09-14T08:30:00.250Z`, which is what Apollo Tracing uses.
+fn rfc3339(time: SystemTime) -> String {
+    let milliseconds = unix_millis(time);
+    let seconds = milliseconds / 1000;
+    let (year, month, day) = variant::civil_from_days((seconds / 86_400) as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        milliseconds % 1000
+    )
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn formats_rfc3339_timestamps() {
+        for (milliseconds, expected) in [
+            (0, "1970-01-01T00:00:00.000Z"),
+            (951_782_400_000, "2000-02-29T00:00:00.000Z"),
+            (1_694_680_200_250, "2023-09-14T08:30:00.250Z"),
+            (253_402_300_799_999, "9999-12-31T23:59:59.999Z"),
+        ] {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_millis(milliseconds);
+            assert_eq!(rfc3339(time), expected);
+        }
+    }
+
+    #[test]
+    fn serializes_apollo_tracing() {
+        let mut timing = Timing::start();
+        timing.execution = TracingPhase {
+            startOffset: 10,
+            duration: 20,
+        };
+        let ended = timing.start + Duration::from_nanos(1_500);
+        let tracing = serde_json::to_value(timing.extensions(ended).tracing).unwrap();
+
+        assert_eq!(tracing["version"], json!(1));
+        assert_eq!(tracing["duration"], json!(1_500));
+        assert_eq!(tracing["startTime"], json!(rfc3339(timing.start_time)));
+        assert!(tracing["endTime"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(
+            tracing["execution"],
+            json!({ "startOffset": 10, "duration": 20, "resolvers": [] })
+        );
+        assert!(tracing.get("parsing").is_none());
+    }
+}
@@ -0,0 +1,187 @@
+use std::{env, fs::File, io, str::FromStr, sync::Mutex};
+
+use tracing::Subscriber;
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{format::FmtSpan, MakeWriter},
+};
+
+/// The file which [`init_from_env`] writes the log to, or `-` for stderr.
+pub const LOG_PATH_VAR: &str = "DISPATCH_GRAPHQL_LOG";
+/// `text` or `json`, for [`init_from_env`].
+pub const LOG_FORMAT_VAR: &str = "DISPATCH_GRAPHQL_LOG_FORMAT";
+/// The most verbose level which [`init_from_env`] logs, e.g. `info` or `debug`.
+pub const LOG_LEVEL_VAR: &str = "DISPATCH_GRAPHQL_LOG_LEVEL";
+
+/// How each line of the log is formatted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One JSON object per line, which doesn't depend on ETW or any other platform facility.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "" | "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Install a global subscriber which logs the spans and events of the service to `writer`. Each
+/// span is logged when it closes, with how long it was busy and idle. Returns `false` if the
+/// process already has a global subscriber, which is left alone.
+pub fn init<W>(writer: W, format: LogFormat, level: LevelFilter) -> bool
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    tracing::subscriber::set_global_default(subscriber(writer, format, level)).is_ok()
+}
+
+/// Build the subscriber which [`init`] installs.
+fn subscriber<W>(
+    writer: W,
+    format: LogFormat,
+    level: LevelFilter,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_writer(writer)
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_thread_ids(true)
+        .with_ansi(false);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
+
+/// Install the subscriber if [`LOG_PATH_VAR`] is set, which is how the COM host object and the
+/// command line tools opt in. The log is appended to the file, and the level defaults to `info`,
+/// which covers every step of an operation but not each payload. Returns whether a subscriber was
+/// installed.
+pub fn init_from_env() -> io::Result<bool> {
+    let Some(path) = env::var_os(LOG_PATH_VAR) else {
+        return Ok(false);
+    };
+    let format = env::var(LOG_FORMAT_VAR)
+        .ok()
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default();
+    let level = env::var(LOG_LEVEL_VAR)
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::INFO);
+
+    if path == "-" {
+        return Ok(init(io::stderr, format, level));
+    }
+    let file = File::options().create(true).append(true).open(path)?;
+    Ok(init(Mutex::new(file), format, level))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::executor::fixtures::{self, INBOX};
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("".parse(), Ok(LogFormat::Text));
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("xml".parse::<LogFormat>(), Err(()));
+    }
+
+    /// Run `f` with the subscriber, and return the lines which it logged.
+    fn log(format: LogFormat, level: LevelFilter, f: impl FnOnce()) -> Vec<String> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let make_writer = move || Writer(writer.clone());
+        tracing::subscriber::with_default(subscriber(make_writer, format, level), f);
+        let log = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        log.lines().map(str::to_string).collect()
+    }
+
+    struct Writer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Writer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn span_with_events() {
+        let span = tracing::info_span!("start_query", operation = "Inbox");
+        let _entered = span.enter();
+        tracing::info!(violations = 2, "the request is not valid");
+        tracing::debug!("decoded");
+    }
+
+    #[test]
+    fn logs_events_and_closed_spans_as_text() {
+        let lines = log(LogFormat::Text, LevelFilter::INFO, span_with_events);
+        assert_eq!(lines.len(), 2, "{lines:#?}");
+        assert!(
+            lines[0].contains("start_query{operation=\"Inbox\"}"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[0].contains("the request is not valid violations=2"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].contains("close time.busy="), "{}", lines[1]);
+    }
+
+    #[test]
+    fn logs_one_json_object_per_line() {
+        let lines: Vec<Value> = log(LogFormat::Json, LevelFilter::DEBUG, span_with_events)
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3, "{lines:#?}");
+        assert_eq!(lines[0]["fields"]["violations"], 2);
+        assert_eq!(lines[0]["span"]["operation"], "Inbox");
+        assert_eq!(lines[1]["level"], "DEBUG");
+        assert_eq!(lines[2]["fields"]["message"], "close");
+    }
+
+    #[test]
+    fn logs_each_step_of_an_operation() {
+        let service = fixtures::service();
+        let lines = log(LogFormat::Json, LevelFilter::INFO, || {
+            service.start_query(INBOX, "", "").unwrap();
+        });
+        let spans: Vec<Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .filter(|line| line["fields"]["message"] == "close")
+            .map(|line| line["span"]["name"].clone())
+            .collect();
+        // Loading the schema for validation runs the introspection query first.
+        assert!(spans.ends_with(&["validate".into(), "subscribe".into(), "start_query".into()]));
+        assert!(lines
+            .iter()
+            .any(|line| line.contains(r#""operation":"Inbox""#)));
+    }
+}
@@ -0,0 +1,233 @@
+use serde_json::{value::RawValue, Value};
+
+/// Map a Rust type to the TypeScript type it serializes to in a payload.
+pub trait TypeScriptType {
+    const OPTIONAL: bool = false;
+
+    fn typescript_type() -> String;
+}
+
+impl TypeScriptType for i32 {
+    fn typescript_type() -> String {
+        String::from("number")
+    }
+}
+
+impl TypeScriptType for u64 {
+    fn typescript_type() -> String {
+        String::from("number")
+    }
+}
+
+impl TypeScriptType for f64 {
+    fn typescript_type() -> String {
+        String::from("number")
+    }
+}
+
+impl TypeScriptType for bool {
+    fn typescript_type() -> String {
+        String::from("boolean")
+    }
+}
+
+impl TypeScriptType for String {
+    fn typescript_type() -> String {
+        String::from("string")
+    }
+}
+
+impl TypeScriptType for Value {
+    fn typescript_type() -> String {
+        String::from("unknown")
+    }
+}
+
+impl TypeScriptType for Box<RawValue> {
+    fn typescript_type() -> String {
+        String::from("unknown")
+    }
+}
+
+impl TypeScriptType for crate::HistogramSnapshot {
+    fn typescript_type() -> String {
+        String::from("HistogramSnapshot")
+    }
+}
+
+impl TypeScriptType for crate::PayloadExtensions {
+    fn typescript_type() -> String {
+        String::from("PayloadExtensions")
+    }
+}
+
+impl TypeScriptType for crate::Tracing {
+    fn typescript_type() -> String {
+        String::from("Tracing")
+    }
+}
+
+impl TypeScriptType for crate::TracingExecution {
+    fn typescript_type() -> String {
+        String::from("TracingExecution")
+    }
+}
+
+impl TypeScriptType for crate::TracingPhase {
+    fn typescript_type() -> String {
+        String::from("TracingPhase")
+    }
+}
+
+impl<T: TypeScriptType> TypeScriptType for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn typescript_type() -> String {
+        T::typescript_type()
+    }
+}
+
+impl<T: TypeScriptType> TypeScriptType for Vec<T> {
+    fn typescript_type() -> String {
+        format!("{}[]", T::typescript_type())
+    }
+}
+
+/// Payload structs which are serialized and handed to script implement this to describe their
+/// JSON shape as a TypeScript interface.
+pub trait TypeScriptInterface {
+    fn typescript_interface() -> String;
+}
+
+/// Declare a payload struct and implement [`TypeScriptInterface`] for it from the same field list,
+/// so the generated typings always match what is serialized. A field may override the type mapped
+/// by [`TypeScriptType`] with `=> "TypeName"`.
+macro_rules! typescript_interface {
+    (@type $type:ty, $ts:literal) => {
+        String::from($ts)
+    };
+
+    (@type $type:ty) => {
+        <$type as $crate::typings::TypeScriptType>::typescript_type()
+    };
+
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident: $type:ty $(=> $ts:literal)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $type,
+            )*
+        }
+
+        impl $crate::typings::TypeScriptInterface for $name {
+            fn typescript_interface() -> String {
+                let mut definition = format!("export interface {} {{\n", stringify!($name));
+                $(
+                    let optional =
+                        if <$type as $crate::typings::TypeScriptType>::OPTIONAL { "?" } else { "" };
+                    definition.push_str(&format!(
+                        "  {}{}: {};\n",
+                        stringify!($field),
+                        optional,
+                        typescript_interface!(@type $type $(, $ts)?),
+                    ));
+                )*
+                definition.push_str("}\n");
+                definition
+            }
+        }
+    };
+}
+
+const HOST_OBJECT_INTERFACE: &str = include_str!(concat!(env!("OUT_DIR"), "/GraphQLService.d.ts"));
+
+/// The `GraphQLClient` interface, generated from the `@signature` of each method in
+/// [`CLIENT_SCRIPT`].
+const CLIENT_INTERFACE: &str = include_str!(concat!(env!("OUT_DIR"), "/GraphQLClient.d.ts"));
+
+const EXECUTION_RESULT: &str = r#"export interface GraphQLError {
+  message: string;
+  locations?: { line: number; column: number }[];
+  path?: (string | number)[];
+  extensions?: Record<string, unknown>;
+}
+
+export interface ExecutionResult<TData = any> {
+  data?: TData | null;
+  errors?: GraphQLError[];
+  extensions?: Record<string, unknown>;
+}
+"#;
+
+const FETCH_OPTIONS: &str = r#"export interface FetchOptions {
+  /** Milliseconds before a query or mutation is cancelled; 0 waits for as long as it takes. */
+  timeout?: number;
+  /** Add `extensions.tracing` to each payload. */
+  tracing?: boolean;
+  /** Deliver payloads as script objects instead of JSON strings. */
+  asObject?: boolean;
+  /** Deliver payloads as bytes in a binary encoding. */
+  encoding?: "json" | "cbor" | "msgpack";
+}
+"#;
+
+const CLIENT_DECLARATIONS: &str = r#"export interface QueryOptions {
+  operationName?: string;
+  variables?: Record<string, unknown>;
+  /** Request extensions, e.g. `{ tracing: true }` to add `extensions.tracing` to each result. */
+  extensions?: { tracing?: boolean } & Record<string, unknown>;
+}
+
+export function createClient(hostObject?: GraphQLHostObject): GraphQLClient;
+
+declare global {
+  interface Window {
+    chrome: {
+      webview: {
+        hostObjects: {
+          graphql: GraphQLHostObject;
+        };
+      };
+    };
+  }
+}
+"#;
+
+/// The promise/async-iterator wrapper around the host object described by
+/// [`typescript_definitions`].
+pub const CLIENT_SCRIPT: &str = include_str!("dispatch-graphql.js");
+
+/// Generate the `.d.ts` typings for the `graphql` host object, the JSON payloads it returns, and
+/// the [`CLIENT_SCRIPT`] wrapper.
+pub fn typescript_definitions() -> String {
+    [
+        String::from("// Generated by dispatch-graphql, do not edit.\n"),
+        String::from(HOST_OBJECT_INTERFACE),
+        String::from(FETCH_OPTIONS),
+        String::from(EXECUTION_RESULT),
+        crate::ResultPayload::typescript_interface(),
+        crate::PendingPayload::typescript_interface(),
+        crate::NextPayload::typescript_interface(),
+        crate::PayloadExtensions::typescript_interface(),
+        crate::Tracing::typescript_interface(),
+        crate::TracingExecution::typescript_interface(),
+        crate::TracingPhase::typescript_interface(),
+        crate::SubscriptionInfo::typescript_interface(),
+        crate::ShutdownReport::typescript_interface(),
+        crate::HistogramSnapshot::typescript_interface(),
+        crate::MetricsSnapshot::typescript_interface(),
+        crate::SlowOperation::typescript_interface(),
+        String::from(CLIENT_INTERFACE),
+        String::from(CLIENT_DECLARATIONS),
+    ]
+    .join("\n")
+}
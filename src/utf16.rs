@@ -0,0 +1,225 @@
+use std::{io, str};
+
+use serde::Serialize;
+
+/// Serialize a payload as JSON directly into a UTF-16 buffer, which is what a `BSTR` holds, without
+/// building an intermediate UTF-8 `String`. The buffer still has to be copied into a `BSTR`, whose
+/// length must be known when it is allocated. The result is not null-terminated.
+pub fn to_utf16<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<Vec<u16>> {
+    let mut writer = Utf16Writer::default();
+    serde_json::to_writer(&mut writer, value)?;
+    writer.finish().map_err(serde_json::Error::io)
+}
+
+/// An [`io::Write`] sink which transcodes the UTF-8 output of `serde_json` to UTF-16 as it is
+/// written. A multi-byte character split across two writes is held until it is complete.
+#[derive(Default)]
+pub struct Utf16Writer {
+    buffer: Vec<u16>,
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl Utf16Writer {
+    /// Take the UTF-16 buffer, or fail if the output ended in the middle of a character.
+    pub fn finish(self) -> io::Result<Vec<u16>> {
+        if self.pending_len > 0 {
+            return Err(invalid_utf8());
+        }
+        Ok(self.buffer)
+    }
+
+    /// Finish a character which was split across writes, and return the rest of the input.
+    fn complete_pending<'a>(&mut self, input: &'a [u8]) -> io::Result<&'a [u8]> {
+        let expected = sequence_len(self.pending[0]).ok_or_else(invalid_utf8)?;
+        let take = (expected - self.pending_len).min(input.len());
+        self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&input[..take]);
+        self.pending_len += take;
+        if self.pending_len == expected {
+            let pending = self.pending;
+            let character = str::from_utf8(&pending[..expected]).map_err(|_| invalid_utf8())?;
+            push_str(&mut self.buffer, character);
+            self.pending_len = 0;
+        }
+        Ok(&input[take..])
+    }
+}
+
+impl io::Write for Utf16Writer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut input = bytes;
+        if self.pending_len > 0 {
+            input = self.complete_pending(input)?;
+        }
+
+        // Most writes are punctuation, keys or plain values, which are entirely ASCII.
+        if input.is_ascii() {
+            self.buffer
+                .extend(input.iter().map(|&byte| u16::from(byte)));
+            return Ok(bytes.len());
+        }
+
+        match str::from_utf8(input) {
+            Ok(value) => push_str(&mut self.buffer, value),
+            Err(error) if error.error_len().is_none() => {
+                let (valid, rest) = input.split_at(error.valid_up_to());
+                push_str(
+                    &mut self.buffer,
+                    str::from_utf8(valid).map_err(|_| invalid_utf8())?,
+                );
+
+                // The input ends with the start of a character which the next write will finish.
+                self.pending[..rest.len()].copy_from_slice(rest);
+                self.pending_len = rest.len();
+            }
+            Err(_) => return Err(invalid_utf8()),
+        }
+
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Append a string to a UTF-16 buffer. Runs of ASCII are widened a byte at a time, which the
+/// compiler can vectorize, and is much faster than `str::encode_utf16` for JSON.
+fn push_str(buffer: &mut Vec<u16>, value: &str) {
+    // There are never more UTF-16 code units than UTF-8 bytes.
+    buffer.reserve(value.len());
+
+    let bytes = value.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        let ascii_len = bytes[index..]
+            .iter()
+            .position(|byte| !byte.is_ascii())
+            .unwrap_or(bytes.len() - index);
+        buffer.extend(
+            bytes[index..index + ascii_len]
+                .iter()
+                .map(|&byte| u16::from(byte)),
+        );
+        index += ascii_len;
+
+        if let Some(character) = value[index..].chars().next() {
+            let mut units = [0; 2];
+            buffer.extend_from_slice(character.encode_utf16(&mut units));
+            index += character.len_utf8();
+        }
+    }
+}
+
+/// The length of a UTF-8 sequence from its first byte.
+fn sequence_len(first: u8) -> Option<usize> {
+    match first {
+        0xC2..=0xDF => Some(2),
+        0xE0..=0xEF => Some(3),
+        0xF0..=0xF4 => Some(4),
+        _ => None,
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8 in JSON output")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Two-, three- and four-byte characters, including astral ones which need a surrogate pair.
+    const SAMPLES: [&str; 5] = ["é", "€", "😀", "a€😀é", "𝄞x😀😀ü"];
+
+    fn write_all(chunks: &[&[u8]]) -> io::Result<Vec<u16>> {
+        let mut writer = Utf16Writer::default();
+        for chunk in chunks {
+            writer.write_all(chunk)?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn transcodes_characters_split_at_every_offset() {
+        for sample in SAMPLES {
+            let expected: Vec<u16> = sample.encode_utf16().collect();
+            let bytes = sample.as_bytes();
+            for split in 0..=bytes.len() {
+                let (first, second) = bytes.split_at(split);
+                assert_eq!(
+                    write_all(&[first, second]).unwrap(),
+                    expected,
+                    "{sample:?} split at {split}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transcodes_characters_split_into_three_writes() {
+        for sample in SAMPLES {
+            let expected: Vec<u16> = sample.encode_utf16().collect();
+            let bytes = sample.as_bytes();
+            for first in 0..=bytes.len() {
+                for second in first..=bytes.len() {
+                    let chunks = [&bytes[..first], &bytes[first..second], &bytes[second..]];
+                    assert_eq!(
+                        write_all(&chunks).unwrap(),
+                        expected,
+                        "{sample:?} split at {first} and {second}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transcodes_one_byte_at_a_time() {
+        for sample in SAMPLES {
+            let chunks: Vec<&[u8]> = sample.as_bytes().chunks(1).collect();
+            assert_eq!(
+                write_all(&chunks).unwrap(),
+                sample.encode_utf16().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        for chunks in [
+            // A continuation byte without a start.
+            &[&[0x80][..]][..],
+            &[b"a", &[0x80]],
+            // An overlong encoding of `/`.
+            &[&[0xC0, 0xAF]],
+            // A UTF-16 surrogate encoded as UTF-8.
+            &[&[0xED, 0xA0, 0x80]],
+            // Beyond U+10FFFF.
+            &[&[0xF5, 0x80, 0x80, 0x80]],
+            // A split character which is not finished by the next write.
+            &[&[0xE2, 0x82], b"a"],
+            &[&[0xF0], &[0x9F, 0x98], b"ab"],
+            // The output ends in the middle of a character.
+            &[&[0xE2, 0x82]],
+            &[b"a", &[0xF0, 0x9F, 0x98]],
+        ] {
+            let error = write_all(chunks).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{chunks:?}");
+        }
+    }
+
+    #[test]
+    fn serializes_json_to_utf16() {
+        let payload = json!({ "subject": "Café ☕ 😀", "items": [1, "𝄞"] });
+        let expected: Vec<u16> = serde_json::to_string(&payload)
+            .unwrap()
+            .encode_utf16()
+            .collect();
+        assert_eq!(to_utf16(&payload).unwrap(), expected);
+    }
+}
@@ -0,0 +1,1385 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use graphql_parser::{
+    query::{
+        Definition, Directive, Document, FragmentDefinition, OperationDefinition, Selection,
+        SelectionSet, TypeCondition, Value, VariableDefinition,
+    },
+    Pos,
+};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    codegen,
+    schema::{InputValue, OperationKind, Schema, TypeKind, TypeRef},
+};
+
+/// A line and column in the document, both starting at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<Pos> for Location {
+    fn from(position: Pos) -> Self {
+        Self {
+            line: position.line,
+            column: position.column,
+        }
+    }
+}
+
+/// A problem found by [`validate`], serialized like an error in a GraphQL response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub locations: Vec<Location>,
+}
+
+impl Violation {
+    fn new(message: String, position: Option<Pos>) -> Self {
+        Self {
+            message,
+            locations: position.into_iter().map(Location::from).collect(),
+        }
+    }
+}
+
+/// Build the response for a request which failed validation, which has `errors` but no `data`.
+pub fn errors_result(violations: &[Violation]) -> serde_json::Value {
+    json!({ "errors": violations })
+}
+
+/// Validate a document against the schema before it is sent to the executor, and return every
+/// violation that was found. This checks that the fields, arguments, directives and fragments
+/// exist, that literal argument values match their types, and that every variable is defined and
+/// used.
+///
+/// The `variables` are then coerced to the variable definitions of the operation selected by
+/// `operation_name`, which may only be empty if the document has a single operation. If everything
+/// is valid, the coerced variables are returned with defaults applied and any undefined variables
+/// removed.
+pub fn validate(
+    schema: &Schema,
+    query: &str,
+    operation_name: &str,
+    variables: &serde_json::Value,
+) -> Result<serde_json::Value, Vec<Violation>> {
+    validate_document(schema, &parse(query)?, operation_name, variables)
+}
+
+/// Parse a document, and report a syntax error as a violation with its location.
+pub fn parse(query: &str) -> Result<Document<'_, String>, Vec<Violation>> {
+    graphql_parser::parse_query(query).map_err(|error| {
+        let message = error.to_string();
+        let position = parse_error_position(&message);
+        vec![Violation::new(message, position)]
+    })
+}
+
+/// Validate a document which has already been parsed, like [`validate`].
+pub fn validate_document<'d>(
+    schema: &Schema,
+    document: &'d Document<'d, String>,
+    operation_name: &str,
+    variables: &serde_json::Value,
+) -> Result<serde_json::Value, Vec<Violation>> {
+    let (mut validator, operations) = check_document(schema, document);
+
+    let selected = operations
+        .iter()
+        .find(|operation| operation_name.is_empty() || operation.name == Some(operation_name));
+    let variables = match selected {
+        // Rather than guess which one was meant, the name is required to pick one of several.
+        Some(_) if operation_name.is_empty() && operations.len() > 1 => {
+            validator.violations.push(Violation::new(
+                "Must provide operation name if query contains multiple operations.".to_string(),
+                None,
+            ));
+            serde_json::Value::Null
+        }
+        Some(operation) => validator.coerce_variables(operation, variables),
+        None if !operation_name.is_empty() => {
+            validator.violations.push(Violation::new(
+                format!(r#"Unknown operation named "{operation_name}"."#),
+                None,
+            ));
+            serde_json::Value::Null
+        }
+        None => {
+            validator.violations.push(Violation::new(
+                "The document does not contain any operations.".to_string(),
+                None,
+            ));
+            serde_json::Value::Null
+        }
+    };
+
+    if validator.violations.is_empty() {
+        Ok(variables)
+    } else {
+        Err(validator.violations)
+    }
+}
+
+/// Validate every operation and fragment in a document which has already been parsed, without
+/// selecting an operation or coercing its variables, e.g. to generate types for all of them.
+pub fn validate_definitions<'d>(
+    schema: &Schema,
+    document: &'d Document<'d, String>,
+) -> Vec<Violation> {
+    check_document(schema, document).0.violations
+}
+
+/// Check the operations and fragments of a document, and return the validator with the
+/// violations it found, along with every operation so one can be selected.
+fn check_document<'s, 'd>(
+    schema: &'s Schema,
+    document: &'d Document<'d, String>,
+) -> (Validator<'s, 'd>, Vec<Operation<'d>>) {
+    let mut validator = Validator {
+        schema,
+        fragments: BTreeMap::new(),
+        violations: Vec::new(),
+    };
+
+    for definition in &document.definitions {
+        if let Definition::Fragment(fragment) = definition {
+            if validator
+                .fragments
+                .insert(&fragment.name, fragment)
+                .is_some()
+            {
+                validator.error(
+                    format!(
+                        r#"There can be only one fragment named "{}"."#,
+                        fragment.name
+                    ),
+                    fragment.position,
+                );
+            }
+        }
+    }
+
+    let mut fragment_usages = BTreeMap::new();
+    for fragment in validator.fragments.clone().into_values() {
+        let usages = validator.fragment(fragment);
+        fragment_usages.insert(fragment.name.as_str(), usages);
+    }
+
+    let mut used_fragments = BTreeSet::new();
+    let mut operation_names = BTreeSet::new();
+    let operations: Vec<_> = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            Definition::Operation(operation) => Some(Operation::from(operation)),
+            Definition::Fragment(_) => None,
+        })
+        .collect();
+
+    for operation in &operations {
+        if let Some(name) = operation.name {
+            if !operation_names.insert(name) {
+                validator.error(
+                    format!(r#"There can be only one operation named "{name}"."#),
+                    operation.position,
+                );
+            }
+        } else if operations.len() > 1 {
+            validator.error(
+                "This anonymous operation must be the only defined operation.".to_string(),
+                operation.position,
+            );
+        }
+
+        let usages = validator.operation(operation, &fragment_usages);
+        used_fragments.extend(usages.fragments);
+    }
+
+    for fragment in validator.fragments.clone().into_values() {
+        if !used_fragments.contains(fragment.name.as_str()) {
+            validator.error(
+                format!(r#"Fragment "{}" is never used."#, fragment.name),
+                fragment.position,
+            );
+        }
+    }
+
+    (validator, operations)
+}
+
+/// The parser only reports the position in its message, e.g. `Parse error at 3:7`.
+fn parse_error_position(message: &str) -> Option<Pos> {
+    let (_, position) = message.split_once(" at ")?;
+    let position = position.split_whitespace().next()?;
+    let (line, column) = position.split_once(':')?;
+    Some(Pos {
+        line: line.parse().ok()?,
+        column: column.parse().ok()?,
+    })
+}
+
+/// The parts of a query, mutation or subscription which are validated the same way.
+struct Operation<'d> {
+    kind: OperationKind,
+    name: Option<&'d str>,
+    position: Pos,
+    variable_definitions: &'d [VariableDefinition<'d, String>],
+    directives: &'d [Directive<'d, String>],
+    selection_set: &'d SelectionSet<'d, String>,
+}
+
+impl<'d> From<&'d OperationDefinition<'d, String>> for Operation<'d> {
+    fn from(operation: &'d OperationDefinition<'d, String>) -> Self {
+        match operation {
+            OperationDefinition::SelectionSet(selection_set) => Self {
+                kind: OperationKind::Query,
+                name: None,
+                position: selection_set.span.0,
+                variable_definitions: &[],
+                directives: &[],
+                selection_set,
+            },
+            OperationDefinition::Query(query) => Self {
+                kind: OperationKind::Query,
+                name: query.name.as_deref(),
+                position: query.position,
+                variable_definitions: &query.variable_definitions,
+                directives: &query.directives,
+                selection_set: &query.selection_set,
+            },
+            OperationDefinition::Mutation(mutation) => Self {
+                kind: OperationKind::Mutation,
+                name: mutation.name.as_deref(),
+                position: mutation.position,
+                variable_definitions: &mutation.variable_definitions,
+                directives: &mutation.directives,
+                selection_set: &mutation.selection_set,
+            },
+            OperationDefinition::Subscription(subscription) => Self {
+                kind: OperationKind::Subscription,
+                name: subscription.name.as_deref(),
+                position: subscription.position,
+                variable_definitions: &subscription.variable_definitions,
+                directives: &subscription.directives,
+                selection_set: &subscription.selection_set,
+            },
+        }
+    }
+}
+
+/// A variable referenced in an argument, and the type expected at that location.
+struct VariableUsage<'d> {
+    name: &'d str,
+    location_type: TypeRef,
+    has_location_default: bool,
+    position: Pos,
+}
+
+/// The variables and fragments referenced directly by an operation or fragment.
+#[derive(Default)]
+struct Usages<'d> {
+    variables: Vec<VariableUsage<'d>>,
+    fragments: BTreeSet<&'d str>,
+}
+
+struct Validator<'s, 'd> {
+    schema: &'s Schema,
+    fragments: BTreeMap<&'d str, &'d FragmentDefinition<'d, String>>,
+    violations: Vec<Violation>,
+}
+
+impl<'s, 'd> Validator<'s, 'd> {
+    fn error(&mut self, message: String, position: Pos) {
+        let violation = Violation::new(message, Some(position));
+        if !self.violations.contains(&violation) {
+            self.violations.push(violation);
+        }
+    }
+
+    fn fragment(&mut self, fragment: &'d FragmentDefinition<'d, String>) -> Usages<'d> {
+        let mut usages = Usages::default();
+        self.directives(&fragment.directives, &mut usages);
+
+        let TypeCondition::On(type_condition) = &fragment.type_condition;
+        if self.type_condition(type_condition, fragment.position) {
+            self.selection_set(type_condition, &fragment.selection_set, &mut usages);
+        }
+
+        if self
+            .reachable_fragments(&usages.fragments)
+            .contains(fragment.name.as_str())
+        {
+            self.error(
+                format!(
+                    r#"Cannot spread fragment "{}" within itself."#,
+                    fragment.name
+                ),
+                fragment.position,
+            );
+        }
+
+        usages
+    }
+
+    fn operation(
+        &mut self,
+        operation: &Operation<'d>,
+        fragment_usages: &BTreeMap<&'d str, Usages<'d>>,
+    ) -> Usages<'d> {
+        let mut usages = Usages::default();
+        let mut defined = BTreeMap::new();
+        for definition in operation.variable_definitions {
+            let var_type = codegen::type_ref(&definition.var_type);
+            if defined
+                .insert(definition.name.as_str(), definition)
+                .is_some()
+            {
+                self.error(
+                    format!(
+                        r#"There can be only one variable named "${}"."#,
+                        definition.name
+                    ),
+                    definition.position,
+                );
+            }
+
+            match self.schema.get_type(var_type.named_type()) {
+                Some(named_type)
+                    if matches!(
+                        named_type.kind,
+                        TypeKind::Scalar | TypeKind::Enum | TypeKind::InputObject
+                    ) =>
+                {
+                    if let Some(default_value) = &definition.default_value {
+                        self.value(default_value, &var_type, definition.position, &mut usages);
+                    }
+                }
+                Some(_) => self.error(
+                    format!(
+                        r#"Variable "${}" cannot be non-input type "{var_type}"."#,
+                        definition.name
+                    ),
+                    definition.position,
+                ),
+                None => self.error(
+                    format!(r#"Unknown type "{}"."#, var_type.named_type()),
+                    definition.position,
+                ),
+            }
+        }
+
+        self.directives(operation.directives, &mut usages);
+        match self.schema.root_type(operation.kind) {
+            Some(root_type) => {
+                self.selection_set(&root_type.name, operation.selection_set, &mut usages)
+            }
+            None => self.error(
+                format!("Schema is not configured for {}s.", operation.kind),
+                operation.position,
+            ),
+        }
+
+        let reachable = self.reachable_fragments(&usages.fragments);
+        let variable_usages = usages.variables.iter().chain(
+            reachable
+                .iter()
+                .filter_map(|name| fragment_usages.get(name))
+                .flat_map(|usages| usages.variables.iter()),
+        );
+
+        let mut used = BTreeSet::new();
+        for usage in variable_usages {
+            used.insert(usage.name);
+            let Some(definition) = defined.get(usage.name) else {
+                let message = match operation.name {
+                    Some(name) => format!(
+                        r#"Variable "${}" is not defined by operation "{name}"."#,
+                        usage.name
+                    ),
+                    None => format!(r#"Variable "${}" is not defined."#, usage.name),
+                };
+                self.error(message, usage.position);
+                continue;
+            };
+
+            let var_type = codegen::type_ref(&definition.var_type);
+            let has_default = definition
+                .default_value
+                .as_ref()
+                .is_some_and(|value| !matches!(value, Value::Null));
+            if !is_variable_allowed(
+                &var_type,
+                has_default || usage.has_location_default,
+                &usage.location_type,
+            ) {
+                self.error(
+                    format!(
+                        r#"Variable "${}" of type "{var_type}" used in position expecting type "{}"."#,
+                        usage.name, usage.location_type
+                    ),
+                    usage.position,
+                );
+            }
+        }
+
+        for definition in operation.variable_definitions {
+            if !used.contains(definition.name.as_str()) {
+                let message = match operation.name {
+                    Some(name) => format!(
+                        r#"Variable "${}" is never used in operation "{name}"."#,
+                        definition.name
+                    ),
+                    None => format!(r#"Variable "${}" is never used."#, definition.name),
+                };
+                self.error(message, definition.position);
+            }
+        }
+
+        usages.fragments.extend(reachable);
+        usages
+    }
+
+    /// Coerce the values supplied for the selected operation's variables to their definitions, so
+    /// a missing or mistyped variable is caught before it reaches the executor.
+    fn coerce_variables(
+        &mut self,
+        operation: &Operation<'d>,
+        variables: &serde_json::Value,
+    ) -> serde_json::Value {
+        let mut coerced = serde_json::Map::new();
+        for definition in operation.variable_definitions {
+            let var_type = codegen::type_ref(&definition.var_type);
+            let value = match (variables.get(&definition.name), &definition.default_value) {
+                (None, Some(default_value)) => {
+                    coerced.insert(definition.name.clone(), literal_to_json(default_value));
+                    continue;
+                }
+                (None, None) if var_type.is_non_null() => {
+                    self.error(
+                        format!(
+                            r#"Variable "${}" of required type "{var_type}" was not provided."#,
+                            definition.name
+                        ),
+                        definition.position,
+                    );
+                    continue;
+                }
+                (None, None) => continue,
+                (Some(value), _) => value,
+            };
+
+            if value.is_null() && var_type.is_non_null() {
+                self.error(
+                    format!(
+                        r#"Variable "${}" of non-null type "{var_type}" must not be null."#,
+                        definition.name
+                    ),
+                    definition.position,
+                );
+                continue;
+            }
+
+            let mut errors = Vec::new();
+            let value = self.coerce(value, &var_type, &definition.name, &mut errors);
+            if errors.is_empty() {
+                coerced.insert(definition.name.clone(), value);
+            }
+            for (path, invalid, message) in errors {
+                let at = if path == definition.name {
+                    String::new()
+                } else {
+                    format!(r#" at "{path}""#)
+                };
+                self.error(
+                    format!(
+                        r#"Variable "${}" got invalid value {invalid}{at}; {message}"#,
+                        definition.name
+                    ),
+                    definition.position,
+                );
+            }
+        }
+        serde_json::Value::Object(coerced)
+    }
+
+    /// Coerce a JSON input value to an input type. Each error is recorded with the path to the
+    /// invalid value, the value itself and a message.
+    fn coerce(
+        &self,
+        value: &serde_json::Value,
+        type_ref: &TypeRef,
+        path: &str,
+        errors: &mut Vec<(String, serde_json::Value, String)>,
+    ) -> serde_json::Value {
+        let mut error = |message: String| {
+            errors.push((path.to_string(), value.clone(), message));
+            serde_json::Value::Null
+        };
+
+        match (value, type_ref) {
+            (serde_json::Value::Null, TypeRef::NonNull(_)) => error(format!(
+                r#"Expected non-nullable type "{type_ref}" not to be null."#
+            )),
+            (serde_json::Value::Null, _) => serde_json::Value::Null,
+            (_, TypeRef::NonNull(of_type)) => self.coerce(value, of_type, path, errors),
+            (serde_json::Value::Array(items), TypeRef::List(of_type)) => items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    self.coerce(item, of_type, &format!("{path}[{index}]"), errors)
+                })
+                .collect(),
+            // A single value is accepted where a list is expected, as a list with one item.
+            (_, TypeRef::List(of_type)) => {
+                serde_json::Value::Array(vec![self.coerce(value, of_type, path, errors)])
+            }
+            (_, TypeRef::Named(name)) => {
+                let Some(named_type) = self.schema.get_type(name) else {
+                    return error(format!(r#"Unknown type "{name}"."#));
+                };
+                match (named_type.kind, value) {
+                    (TypeKind::Scalar, _) => match (name.as_str(), value) {
+                        ("Int", serde_json::Value::Number(number)) => {
+                            let integer = number.as_i64().or_else(|| {
+                                number
+                                    .as_f64()
+                                    .filter(|float| float.fract() == 0.0)
+                                    .map(|float| float as i64)
+                            });
+                            match integer.and_then(|integer| i32::try_from(integer).ok()) {
+                                Some(integer) => integer.into(),
+                                None => error(format!(
+                                    "Int cannot represent non 32-bit signed integer value: {value}"
+                                )),
+                            }
+                        }
+                        ("Float", serde_json::Value::Number(_))
+                        | ("String", serde_json::Value::String(_))
+                        | ("Boolean", serde_json::Value::Bool(_))
+                        | ("ID", serde_json::Value::String(_)) => value.clone(),
+                        ("ID", serde_json::Value::Number(number)) if number.is_i64() => {
+                            number.to_string().into()
+                        }
+                        ("Int" | "Float" | "String" | "Boolean" | "ID", _) => {
+                            error(format!("{name} cannot represent value: {value}"))
+                        }
+                        // Custom scalars are passed through for the executor to coerce.
+                        _ => value.clone(),
+                    },
+                    (TypeKind::Enum, serde_json::Value::String(enum_value))
+                        if named_type.enum_values.contains(enum_value) =>
+                    {
+                        value.clone()
+                    }
+                    (TypeKind::Enum, _) => {
+                        error(format!(r#"Value {value} does not exist in "{name}" enum."#))
+                    }
+                    (TypeKind::InputObject, serde_json::Value::Object(fields)) => {
+                        let mut coerced = serde_json::Map::new();
+                        for field_name in fields.keys() {
+                            if !named_type
+                                .input_fields
+                                .iter()
+                                .any(|field| &field.name == field_name)
+                            {
+                                error(format!(
+                                    r#"Field "{field_name}" is not defined by type "{name}"."#
+                                ));
+                            }
+                        }
+                        for field in &named_type.input_fields {
+                            let field_path = format!("{path}.{}", field.name);
+                            match (fields.get(&field.name), &field.default_value) {
+                                (Some(field_value), _) => {
+                                    let field_value = self.coerce(
+                                        field_value,
+                                        &field.type_ref,
+                                        &field_path,
+                                        errors,
+                                    );
+                                    coerced.insert(field.name.clone(), field_value);
+                                }
+                                (None, Some(default_value)) => {
+                                    if let Some(default_value) = parse_literal(default_value) {
+                                        coerced.insert(field.name.clone(), default_value);
+                                    }
+                                }
+                                (None, None) if field.type_ref.is_non_null() => {
+                                    errors.push((
+                                        path.to_string(),
+                                        value.clone(),
+                                        format!(
+                                            r#"Field "{}" of required type "{}" was not provided."#,
+                                            field.name, field.type_ref
+                                        ),
+                                    ));
+                                }
+                                (None, None) => {}
+                            }
+                        }
+                        serde_json::Value::Object(coerced)
+                    }
+                    (TypeKind::InputObject, _) => {
+                        error(format!(r#"Expected type "{name}" to be an object."#))
+                    }
+                    _ => error(format!(r#"Type "{name}" is not an input type."#)),
+                }
+            }
+        }
+    }
+
+    /// Every fragment which can be reached by following the spreads in `fragments`.
+    fn reachable_fragments(&self, fragments: &BTreeSet<&'d str>) -> BTreeSet<&'d str> {
+        let mut reachable = BTreeSet::new();
+        let mut pending: Vec<_> = fragments.iter().copied().collect();
+        while let Some(name) = pending.pop() {
+            if !reachable.insert(name) {
+                continue;
+            }
+            if let Some(fragment) = self.fragments.get(name) {
+                let mut spreads = BTreeSet::new();
+                collect_spreads(&fragment.selection_set, &mut spreads);
+                pending.extend(spreads);
+            }
+        }
+        reachable
+    }
+
+    /// Check that a type condition names a composite type, and return whether it is safe to
+    /// validate the selections against it.
+    fn type_condition(&mut self, type_condition: &str, position: Pos) -> bool {
+        match self.schema.get_type(type_condition) {
+            Some(_) if self.schema.is_composite(type_condition) => true,
+            Some(_) => {
+                self.error(
+                    format!(
+                        r#"Fragment cannot condition on non composite type "{type_condition}"."#
+                    ),
+                    position,
+                );
+                false
+            }
+            None => {
+                self.error(format!(r#"Unknown type "{type_condition}"."#), position);
+                false
+            }
+        }
+    }
+
+    fn selection_set(
+        &mut self,
+        parent_type: &str,
+        selection_set: &'d SelectionSet<'d, String>,
+        usages: &mut Usages<'d>,
+    ) {
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    self.directives(&field.directives, usages);
+                    let field_type = match field.name.as_str() {
+                        "__typename" => Some(TypeRef::Named("String".to_string())),
+                        // The introspection meta-fields are not part of the schema model.
+                        "__schema" | "__type" if parent_type == self.schema.query_type => None,
+                        _ => match self.schema.field(parent_type, &field.name) {
+                            Some(schema_field) => {
+                                self.arguments(
+                                    &schema_field.args,
+                                    &field.arguments,
+                                    field.position,
+                                    &format!(r#"field "{parent_type}.{}""#, field.name),
+                                    usages,
+                                );
+                                Some(schema_field.type_ref.clone())
+                            }
+                            None => {
+                                self.error(
+                                    format!(
+                                        r#"Cannot query field "{}" on type "{parent_type}"."#,
+                                        field.name
+                                    ),
+                                    field.position,
+                                );
+                                None
+                            }
+                        },
+                    };
+
+                    let Some(field_type) = field_type else {
+                        continue;
+                    };
+                    let named_type = field_type.named_type();
+                    if self.schema.is_composite(named_type) {
+                        if field.selection_set.items.is_empty() {
+                            self.error(
+                                format!(
+                                    r#"Field "{}" of type "{field_type}" must have a selection of subfields."#,
+                                    field.name
+                                ),
+                                field.position,
+                            );
+                        } else {
+                            self.selection_set(named_type, &field.selection_set, usages);
+                        }
+                    } else if !field.selection_set.items.is_empty() {
+                        self.error(
+                            format!(
+                                r#"Field "{}" must not have a selection since type "{field_type}" has no subfields."#,
+                                field.name
+                            ),
+                            field.position,
+                        );
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    self.directives(&spread.directives, usages);
+                    usages.fragments.insert(&spread.fragment_name);
+                    if !self.fragments.contains_key(spread.fragment_name.as_str()) {
+                        self.error(
+                            format!(r#"Unknown fragment "{}"."#, spread.fragment_name),
+                            spread.position,
+                        );
+                    }
+                }
+                Selection::InlineFragment(fragment) => {
+                    self.directives(&fragment.directives, usages);
+                    match &fragment.type_condition {
+                        Some(TypeCondition::On(type_condition)) => {
+                            if self.type_condition(type_condition, fragment.position) {
+                                self.selection_set(type_condition, &fragment.selection_set, usages);
+                            }
+                        }
+                        None => self.selection_set(parent_type, &fragment.selection_set, usages),
+                    }
+                }
+            }
+        }
+    }
+
+    fn directives(&mut self, directives: &'d [Directive<'d, String>], usages: &mut Usages<'d>) {
+        for directive in directives {
+            let args = match self.schema.directives.get(&directive.name) {
+                Some(schema_directive) => Some(schema_directive.args.clone()),
+                // Every schema supports these, even if the introspection results omit them.
+                None if matches!(directive.name.as_str(), "skip" | "include") => {
+                    Some(vec![InputValue {
+                        name: "if".to_string(),
+                        type_ref: TypeRef::NonNull(Box::new(TypeRef::Named("Boolean".to_string()))),
+                        default_value: None,
+                    }])
+                }
+                None => None,
+            };
+            match args {
+                Some(args) => self.arguments(
+                    &args,
+                    &directive.arguments,
+                    directive.position,
+                    &format!(r#"directive "@{}""#, directive.name),
+                    usages,
+                ),
+                None => self.error(
+                    format!(r#"Unknown directive "@{}"."#, directive.name),
+                    directive.position,
+                ),
+            }
+        }
+    }
+
+    fn arguments(
+        &mut self,
+        args: &[InputValue],
+        arguments: &'d [(String, Value<'d, String>)],
+        position: Pos,
+        owner: &str,
+        usages: &mut Usages<'d>,
+    ) {
+        for (name, value) in arguments {
+            match args.iter().find(|arg| &arg.name == name) {
+                Some(arg) => {
+                    if let Value::Variable(variable) = value {
+                        usages.variables.push(VariableUsage {
+                            name: variable,
+                            location_type: arg.type_ref.clone(),
+                            has_location_default: arg.default_value.is_some(),
+                            position,
+                        });
+                    } else {
+                        self.value(value, &arg.type_ref, position, usages);
+                    }
+                }
+                None => self.error(
+                    format!(r#"Unknown argument "{name}" on {owner}."#),
+                    position,
+                ),
+            }
+        }
+
+        for arg in args {
+            if arg.type_ref.is_non_null()
+                && arg.default_value.is_none()
+                && !arguments.iter().any(|(name, _)| name == &arg.name)
+            {
+                self.error(
+                    format!(
+                        r#"Argument "{}" of type "{}" on {owner} is required, but it was not provided."#,
+                        arg.name, arg.type_ref
+                    ),
+                    position,
+                );
+            }
+        }
+    }
+
+    /// Check a literal value against an input type. Variables nested in lists or input objects
+    /// are recorded as usages so their types can be checked against the operation.
+    fn value(
+        &mut self,
+        value: &'d Value<'d, String>,
+        type_ref: &TypeRef,
+        position: Pos,
+        usages: &mut Usages<'d>,
+    ) {
+        let mismatch = |value: &Value<String>| {
+            format!(r#"Expected value of type "{type_ref}", found {value}."#)
+        };
+
+        match (value, type_ref) {
+            (Value::Variable(variable), _) => usages.variables.push(VariableUsage {
+                name: variable,
+                location_type: type_ref.clone(),
+                has_location_default: false,
+                position,
+            }),
+            (Value::Null, TypeRef::NonNull(_)) => self.error(mismatch(value), position),
+            (Value::Null, _) => {}
+            (_, TypeRef::NonNull(of_type)) => self.value(value, of_type, position, usages),
+            (Value::List(items), TypeRef::List(of_type)) => {
+                for item in items {
+                    self.value(item, of_type, position, usages);
+                }
+            }
+            (_, TypeRef::List(of_type)) => self.value(value, of_type, position, usages),
+            (_, TypeRef::Named(name)) => {
+                let Some(named_type) = self.schema.get_type(name) else {
+                    return;
+                };
+                let valid = match (named_type.kind, value) {
+                    (TypeKind::Scalar, _) => match (name.as_str(), value) {
+                        ("Int", Value::Int(number)) => number
+                            .as_i64()
+                            .is_some_and(|number| i32::try_from(number).is_ok()),
+                        ("Float", Value::Int(_) | Value::Float(_))
+                        | ("String", Value::String(_))
+                        | ("Boolean", Value::Boolean(_))
+                        | ("ID", Value::String(_) | Value::Int(_)) => true,
+                        ("Int" | "Float" | "String" | "Boolean" | "ID", _) => false,
+                        // Custom scalars accept any literal which the executor can coerce.
+                        _ => true,
+                    },
+                    (TypeKind::Enum, Value::Enum(enum_value)) => {
+                        named_type.enum_values.contains(enum_value)
+                    }
+                    (TypeKind::InputObject, Value::Object(fields)) => {
+                        let input_fields = &named_type.input_fields;
+                        for (field_name, field_value) in fields {
+                            match input_fields.iter().find(|field| &field.name == field_name) {
+                                Some(field) => {
+                                    self.value(field_value, &field.type_ref, position, usages)
+                                }
+                                None => self.error(
+                                    format!(
+                                        r#"Field "{field_name}" is not defined by type "{name}"."#
+                                    ),
+                                    position,
+                                ),
+                            }
+                        }
+                        for field in input_fields {
+                            if field.type_ref.is_non_null()
+                                && field.default_value.is_none()
+                                && !fields.contains_key(&field.name)
+                            {
+                                self.error(
+                                    format!(
+                                        r#"Field "{name}.{}" of required type "{}" was not provided."#,
+                                        field.name, field.type_ref
+                                    ),
+                                    position,
+                                );
+                            }
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    self.error(mismatch(value), position);
+                }
+            }
+        }
+    }
+}
+
+/// Convert a literal from the document, e.g. a variable default value, to JSON. Enum values become
+/// strings, the same as they would be in the variables.
+fn literal_to_json(value: &Value<String>) -> serde_json::Value {
+    match value {
+        Value::Variable(_) | Value::Null => serde_json::Value::Null,
+        Value::Int(number) => number.as_i64().into(),
+        Value::Float(float) => (*float).into(),
+        Value::String(string) => string.as_str().into(),
+        Value::Boolean(boolean) => (*boolean).into(),
+        Value::Enum(enum_value) => enum_value.as_str().into(),
+        Value::List(items) => items.iter().map(literal_to_json).collect(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| (name.clone(), literal_to_json(value)))
+            .collect(),
+    }
+}
+
+/// Introspection reports default values as GraphQL literals, so parse one as an argument value.
+fn parse_literal(literal: &str) -> Option<serde_json::Value> {
+    let query = format!("{{ field(value: {literal}) }}");
+    let document: Document<String> = graphql_parser::parse_query(&query).ok()?;
+    let Some(Definition::Operation(OperationDefinition::SelectionSet(selection_set))) =
+        document.definitions.first()
+    else {
+        return None;
+    };
+    let Some(Selection::Field(field)) = selection_set.items.first() else {
+        return None;
+    };
+    field
+        .arguments
+        .first()
+        .map(|(_, value)| literal_to_json(value))
+}
+
+fn collect_spreads<'d>(
+    selection_set: &'d SelectionSet<'d, String>,
+    spreads: &mut BTreeSet<&'d str>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            Selection::Field(field) => collect_spreads(&field.selection_set, spreads),
+            Selection::FragmentSpread(spread) => {
+                spreads.insert(&spread.fragment_name);
+            }
+            Selection::InlineFragment(fragment) => {
+                collect_spreads(&fragment.selection_set, spreads)
+            }
+        }
+    }
+}
+
+/// Check whether a variable of type `var_type` can be used where `location_type` is expected. A
+/// nullable variable may only flow into a non-null location if a default value covers it.
+fn is_variable_allowed(var_type: &TypeRef, has_default: bool, location_type: &TypeRef) -> bool {
+    match (var_type, location_type) {
+        (TypeRef::NonNull(var_type), TypeRef::NonNull(location_type)) => {
+            is_variable_allowed(var_type, false, location_type)
+        }
+        (TypeRef::NonNull(var_type), _) => is_variable_allowed(var_type, false, location_type),
+        (_, TypeRef::NonNull(location_type)) => {
+            has_default && is_variable_allowed(var_type, false, location_type)
+        }
+        (TypeRef::List(var_type), TypeRef::List(location_type)) => {
+            is_variable_allowed(var_type, false, location_type)
+        }
+        (TypeRef::Named(var_type), TypeRef::Named(location_type)) => var_type == location_type,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::fixtures;
+
+    /// The message of every violation in the operations and fragments of a document.
+    fn violations(query: &str) -> Vec<String> {
+        let document = parse(query).unwrap();
+        validate_definitions(&fixtures::schema(), &document)
+            .into_iter()
+            .map(|violation| violation.message)
+            .collect()
+    }
+
+    fn named(name: &str) -> TypeRef {
+        TypeRef::Named(name.to_string())
+    }
+
+    fn list(of_type: TypeRef) -> TypeRef {
+        TypeRef::List(Box::new(of_type))
+    }
+
+    fn non_null(of_type: TypeRef) -> TypeRef {
+        TypeRef::NonNull(Box::new(of_type))
+    }
+
+    #[test]
+    fn accepts_valid_documents() {
+        assert!(violations(
+            r#"query Folder($folderId: ObjectId!, $unread: Boolean = true, $skip: Boolean!) {
+                folder(folderId: $folderId) {
+                    ...FolderItems
+                    unread @skip(if: $skip)
+                }
+                search(text: "from:me") {
+                    __typename
+                    ... on Item { subject }
+                    ... on Node { id }
+                }
+            }
+            fragment FolderItems on Folder {
+                items(first: 5, filter: { importance: HIGH, unread: $unread }) { subject received }
+            }"#
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn reports_unknown_fields_and_arguments() {
+        assert_eq!(
+            violations(
+                r#"{
+                    stores { name size }
+                    items(last: 2) { subject }
+                    folder { name }
+                    search(text: "x") { subject }
+                    ... on Query { unknown }
+                }"#
+            ),
+            [
+                r#"Cannot query field "size" on type "Store"."#,
+                r#"Unknown argument "last" on field "Query.items"."#,
+                r#"Argument "folderId" of type "ObjectId!" on field "Query.folder" is required, but it was not provided."#,
+                r#"Cannot query field "subject" on type "SearchResult"."#,
+                r#"Cannot query field "unknown" on type "Query"."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_selections_which_do_not_match_the_field_type() {
+        assert_eq!(
+            violations("{ stores items { subject { length } } }"),
+            [
+                r#"Field "stores" of type "[Store!]!" must have a selection of subfields."#,
+                r#"Field "subject" must not have a selection since type "String!" has no subfields."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_argument_literals_of_the_wrong_type() {
+        assert_eq!(
+            violations(
+                r#"{
+                    first: items(first: "ten") { subject }
+                    large: items(first: 3000000000) { subject }
+                    filtered: items(filter: { importance: URGENT, unread: "yes", read: true }) {
+                        subject
+                    }
+                    search(text: null) { __typename }
+                    folder(folderId: { storeId: "store" }) { name }
+                    stores { folders(ids: { storeId: 1, objectId: 2 }) { name } }
+                }"#
+            ),
+            [
+                r#"Expected value of type "Int", found "ten"."#,
+                r#"Expected value of type "Int", found 3000000000."#,
+                r#"Expected value of type "Importance", found URGENT."#,
+                r#"Field "read" is not defined by type "ItemFilter"."#,
+                r#"Expected value of type "Boolean", found "yes"."#,
+                r#"Expected value of type "String!", found null."#,
+                r#"Field "ObjectId.objectId" of required type "ID!" was not provided."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_unused_and_cyclic_fragments() {
+        assert_eq!(
+            violations(
+                r#"query Items { items { ...Missing ...Subject } }
+                fragment Subject on Item { subject ...Importance }
+                fragment Importance on Item { importance ...Subject }
+                fragment Unused on Item { id }"#
+            ),
+            [
+                r#"Cannot spread fragment "Importance" within itself."#,
+                r#"Cannot spread fragment "Subject" within itself."#,
+                r#"Unknown fragment "Missing"."#,
+                r#"Fragment "Unused" is never used."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_fragments_on_types_which_cannot_have_selections() {
+        assert_eq!(
+            violations(
+                "{ items { ...Subject ... on Unknown { id } } } fragment Subject on String { length }"
+            ),
+            [
+                r#"Fragment cannot condition on non composite type "String"."#,
+                r#"Unknown type "Unknown"."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_and_unused_variables() {
+        assert_eq!(
+            violations(
+                r#"query Items($unused: Int, $first: Int) { items(first: $first) { ...Filtered } }
+                fragment Filtered on Item { subject @include(if: $include) }
+                { items(first: $first) { subject } }"#
+            ),
+            [
+                r#"Variable "$include" is not defined by operation "Items"."#,
+                r#"Variable "$unused" is never used in operation "Items"."#,
+                "This anonymous operation must be the only defined operation.",
+                r#"Variable "$first" is not defined."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_variables_of_the_wrong_type() {
+        assert_eq!(
+            violations(
+                r#"query Folder(
+                    $folderId: ObjectId
+                    $text: String
+                    $first: [Int]
+                    $folder: Folder
+                    $store: Store = null
+                ) {
+                    folder(folderId: $folderId) { items(first: $first) { subject } }
+                    search(text: $text) { __typename }
+                    stores { folders(ids: [$folder, $store]) { name } }
+                }"#
+            ),
+            [
+                r#"Variable "$folder" cannot be non-input type "Folder"."#,
+                r#"Variable "$store" cannot be non-input type "Store"."#,
+                r#"Variable "$folderId" of type "ObjectId" used in position expecting type "ObjectId!"."#,
+                r#"Variable "$first" of type "[Int]" used in position expecting type "Int"."#,
+                r#"Variable "$text" of type "String" used in position expecting type "String!"."#,
+                r#"Variable "$folder" of type "Folder" used in position expecting type "ObjectId!"."#,
+                r#"Variable "$store" of type "Store" used in position expecting type "ObjectId!"."#,
+            ]
+        );
+    }
+
+    #[test]
+    fn allows_nullable_variables_with_a_default_in_non_null_positions() {
+        assert_eq!(
+            violations(
+                r#"query Search($text: String = "inbox", $first: Int = null) {
+                    search(text: $text) { __typename }
+                    items(first: $first) { subject }
+                }
+                query Missing($text: String = null) { search(text: $text) { __typename } }
+                mutation Read($itemId: ObjectId!, $read: Boolean) {
+                    markAsRead(itemId: $itemId, read: $read) { subject }
+                }"#
+            ),
+            // A null default does not cover a non-null position, but the argument's default does.
+            [r#"Variable "$text" of type "String" used in position expecting type "String!"."#]
+        );
+    }
+
+    #[test]
+    fn checks_where_each_variable_type_is_allowed() {
+        let int = || named("Int");
+        let cases = [
+            (int(), false, int(), true),
+            (non_null(int()), false, int(), true),
+            (non_null(int()), false, non_null(int()), true),
+            (int(), false, non_null(int()), false),
+            (int(), true, non_null(int()), true),
+            (named("Float"), false, int(), false),
+            (list(int()), false, list(int()), true),
+            (list(non_null(int())), false, list(int()), true),
+            (list(int()), false, list(non_null(int())), false),
+            (list(int()), true, list(non_null(int())), false),
+            (non_null(list(int())), false, list(int()), true),
+            (list(int()), true, non_null(list(int())), true),
+            (list(int()), false, int(), false),
+            (int(), false, list(int()), false),
+        ];
+        for (var_type, has_default, location_type, allowed) in cases {
+            assert_eq!(
+                is_variable_allowed(&var_type, has_default, &location_type),
+                allowed,
+                "{var_type} (default: {has_default}) in {location_type}"
+            );
+        }
+    }
+
+    const FOLDER: &str = r#"query Folder($folderId: ObjectId!, $first: Int = 10, $filter: ItemFilter) {
+        folder(folderId: $folderId) { items(first: $first, filter: $filter) { subject } }
+    }"#;
+
+    /// Coerce the variables for [`FOLDER`], and return either the coerced variables or the message
+    /// of every violation.
+    fn coerce(variables: serde_json::Value) -> Result<serde_json::Value, Vec<String>> {
+        validate(&fixtures::schema(), FOLDER, "", &variables).map_err(|violations| {
+            violations
+                .into_iter()
+                .map(|violation| violation.message)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn applies_default_values() {
+        let folder_id = json!({ "storeId": "store", "objectId": "folder" });
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id })),
+            Ok(json!({ "folderId": folder_id, "first": 10 }))
+        );
+        // Defaults from the schema fill in missing input object fields, but an explicit null is
+        // kept, and variables which the operation does not define are removed.
+        assert_eq!(
+            coerce(json!({
+                "folderId": folder_id,
+                "first": null,
+                "filter": { "importance": "HIGH" },
+                "other": true,
+            })),
+            Ok(json!({
+                "folderId": folder_id,
+                "first": null,
+                "filter": { "importance": "HIGH", "unread": false },
+            }))
+        );
+    }
+
+    #[test]
+    fn reports_missing_required_variables() {
+        assert_eq!(
+            coerce(json!({})),
+            Err(vec![String::from(
+                r#"Variable "$folderId" of required type "ObjectId!" was not provided."#
+            )])
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": null })),
+            Err(vec![String::from(
+                r#"Variable "$folderId" of non-null type "ObjectId!" must not be null."#
+            )])
+        );
+    }
+
+    #[test]
+    fn coerces_input_objects() {
+        assert_eq!(
+            coerce(json!({ "folderId": { "storeId": "store", "objectId": 42 } })),
+            Ok(json!({ "folderId": { "storeId": "store", "objectId": "42" }, "first": 10 }))
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": { "storeId": "store", "folderId": "folder" } })),
+            Err(vec![
+                String::from(
+                    r#"Variable "$folderId" got invalid value {"folderId":"folder","storeId":"store"}; Field "folderId" is not defined by type "ObjectId"."#
+                ),
+                String::from(
+                    r#"Variable "$folderId" got invalid value {"folderId":"folder","storeId":"store"}; Field "objectId" of required type "ID!" was not provided."#
+                ),
+            ])
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": { "storeId": true, "objectId": null } })),
+            Err(vec![
+                String::from(
+                    r#"Variable "$folderId" got invalid value true at "folderId.storeId"; ID cannot represent value: true"#
+                ),
+                String::from(
+                    r#"Variable "$folderId" got invalid value null at "folderId.objectId"; Expected non-nullable type "ID!" not to be null."#
+                ),
+            ])
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": "store/folder" })),
+            Err(vec![String::from(
+                r#"Variable "$folderId" got invalid value "store/folder"; Expected type "ObjectId" to be an object."#
+            )])
+        );
+    }
+
+    #[test]
+    fn reports_invalid_enum_values() {
+        let folder_id = json!({ "storeId": "store", "objectId": "folder" });
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id, "filter": { "importance": "URGENT" } })),
+            Err(vec![String::from(
+                r#"Variable "$filter" got invalid value "URGENT" at "filter.importance"; Value "URGENT" does not exist in "Importance" enum."#
+            )])
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id, "filter": { "importance": 1 } })),
+            Err(vec![String::from(
+                r#"Variable "$filter" got invalid value 1 at "filter.importance"; Value 1 does not exist in "Importance" enum."#
+            )])
+        );
+    }
+
+    #[test]
+    fn reports_int_values_out_of_range() {
+        let folder_id = json!({ "storeId": "store", "objectId": "folder" });
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id, "first": 2147483647 }))
+                .map(|variables| variables["first"].clone()),
+            Ok(json!(2147483647))
+        );
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id, "first": 5.0 }))
+                .map(|variables| variables["first"].clone()),
+            Ok(json!(5))
+        );
+        for first in [json!(2147483648_i64), json!(-2147483649_i64), json!(1.5)] {
+            assert_eq!(
+                coerce(json!({ "folderId": folder_id, "first": first })),
+                Err(vec![format!(
+                    r#"Variable "$first" got invalid value {first}; Int cannot represent non 32-bit signed integer value: {first}"#
+                )])
+            );
+        }
+        assert_eq!(
+            coerce(json!({ "folderId": folder_id, "first": "10" })),
+            Err(vec![String::from(
+                r#"Variable "$first" got invalid value "10"; Int cannot represent value: "10""#
+            )])
+        );
+    }
+
+    #[test]
+    fn requires_an_operation_name_to_select_one_of_several() {
+        let query = format!("{FOLDER} query Inbox {{ items {{ subject }} }}");
+        let select = |operation_name| {
+            validate(&fixtures::schema(), &query, operation_name, &json!({}))
+                .map_err(|violations| violations[0].message.clone())
+        };
+        assert_eq!(
+            select(""),
+            Err(String::from(
+                "Must provide operation name if query contains multiple operations."
+            ))
+        );
+        assert_eq!(select("Inbox"), Ok(json!({})));
+        assert_eq!(
+            select("Other"),
+            Err(String::from(r#"Unknown operation named "Other"."#))
+        );
+    }
+}
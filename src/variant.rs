@@ -0,0 +1,447 @@
+use serde_json::{Map, Number, Value};
+
+/// A platform-independent model of the `VARIANT` values which script callers can pass to the host
+/// object, so they can be converted to and from JSON without COM.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Variant {
+    Empty,
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    /// An OLE Automation date, which counts days from midnight on December 30, 1899 with the time
+    /// of day as the fraction. It has no time zone, so it represents local time.
+    Date(f64),
+    String(String),
+    Array(Vec<Variant>),
+    /// Properties in enumeration order.
+    Object(Vec<(String, Variant)>),
+}
+
+impl From<&Variant> for Value {
+    /// Dates are converted to ISO 8601 strings without a time zone offset, which script parses as
+    /// local time. Floats which JSON can't represent (NaN or infinity) become `null`.
+    fn from(variant: &Variant) -> Self {
+        match variant {
+            Variant::Empty | Variant::Null => Value::Null,
+            Variant::Bool(value) => Value::Bool(*value),
+            Variant::Int(value) => Value::Number((*value).into()),
+            Variant::Float(value) => Number::from_f64(*value).map_or(Value::Null, Value::Number),
+            Variant::Date(value) => format_date(*value).map_or(Value::Null, Value::String),
+            Variant::String(value) => Value::String(value.clone()),
+            Variant::Array(items) => Value::Array(items.iter().map(Value::from).collect()),
+            Variant::Object(properties) => Value::Object(
+                properties
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Value::from(value)))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+impl From<&Value> for Variant {
+    /// Integers which fit in an `i64` become [`Variant::Int`], and any other number becomes a
+    /// [`Variant::Float`]. JSON has no date type, so dates come back as strings.
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Variant::Null,
+            Value::Bool(value) => Variant::Bool(*value),
+            Value::Number(number) => match number.as_i64() {
+                Some(value) => Variant::Int(value),
+                None => Variant::Float(number.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(value) => Variant::String(value.clone()),
+            Value::Array(items) => Variant::Array(items.iter().map(Variant::from).collect()),
+            Value::Object(properties) => Variant::Object(
+                properties
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Variant::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The OLE Automation date of the Unix epoch, January 1, 1970.
+const UNIX_EPOCH_DATE: i64 = 25_569;
+const MILLISECONDS_PER_DAY: i64 = 86_400_000;
+
+/// Format an OLE Automation date as `YYYY-MM-DDTHH:MM:SS.sss`. Dates before 1899-12-30 are
+/// negative, but the fraction still counts forward from midnight, e.g. `-1.25` is 6 AM on
+/// December 29, 1899.
+pub fn format_date(date: f64) -> Option<String> {
+    if !date.is_finite() {
+        return None;
+    }
+
+    let mut days = date.trunc() as i64;
+    let mut milliseconds = (date.fract().abs() * MILLISECONDS_PER_DAY as f64).round() as i64;
+    if milliseconds >= MILLISECONDS_PER_DAY {
+        days += if date < 0.0 { -1 } else { 1 };
+        milliseconds -= MILLISECONDS_PER_DAY;
+    }
+
+    let (year, month, day) = civil_from_days(days - UNIX_EPOCH_DATE);
+    let seconds = milliseconds / 1000;
+    Some(format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        milliseconds % 1000
+    ))
+}
+
+/// Convert days since the Unix epoch to a proleptic Gregorian `(year, month, day)`, using Howard
+/// Hinnant's `civil_from_days` algorithm.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(windows)]
+pub(crate) use com::from_variant;
+
+#[cfg(windows)]
+mod com {
+    use std::ffi::c_void;
+
+    use windows::{
+        core::*,
+        Win32::{
+            Foundation::*,
+            System::{Com::*, Ole::*, Variant::*},
+        },
+    };
+
+    use super::Variant;
+
+    /// Script objects can refer to themselves, so stop walking them at this depth.
+    const MAX_DEPTH: usize = 64;
+
+    /// Walk a `VARIANT` from a script caller into a [`Variant`]. Script objects are read through
+    /// `IDispatchEx` enumeration, and arrays through their `length` and index properties.
+    ///
+    /// # Safety
+    ///
+    /// The `variant` must be initialized.
+    pub(crate) unsafe fn from_variant(variant: &VARIANT) -> Result<Variant> {
+        convert(variant, 0)
+    }
+
+    unsafe fn convert(variant: &VARIANT, depth: usize) -> Result<Variant> {
+        if depth > MAX_DEPTH {
+            return Err(E_INVALIDARG.into());
+        }
+
+        let value = &variant.Anonymous.Anonymous;
+        let data = &value.Anonymous;
+        if value.vt == VARENUM(VT_ARRAY.0 | VT_VARIANT.0) {
+            return safe_array(data.parray, depth);
+        }
+
+        let unsigned =
+            |value: u64| i64::try_from(value).map_or(Variant::Float(value as f64), Variant::Int);
+        Ok(match value.vt {
+            VT_EMPTY => Variant::Empty,
+            VT_NULL => Variant::Null,
+            VT_BOOL => Variant::Bool(data.boolVal.0 != 0),
+            VT_I2 => Variant::Int(data.iVal.into()),
+            VT_I4 => Variant::Int(data.lVal.into()),
+            VT_INT => Variant::Int(data.intVal.into()),
+            VT_I8 => Variant::Int(data.llVal),
+            VT_UI1 => Variant::Int(data.bVal.into()),
+            VT_UI2 => Variant::Int(data.uiVal.into()),
+            VT_UI4 => Variant::Int(data.ulVal.into()),
+            VT_UINT => Variant::Int(data.uintVal.into()),
+            VT_UI8 => unsigned(data.ullVal),
+            VT_R4 => Variant::Float(data.fltVal.into()),
+            VT_R8 => Variant::Float(data.dblVal),
+            VT_DATE => Variant::Date(data.date),
+            VT_BSTR => Variant::String(
+                String::from_utf16(data.bstrVal.as_wide())
+                    .map_err(|_| Error::from(E_INVALIDARG))?,
+            ),
+            VT_DISPATCH => match data.pdispVal.as_ref() {
+                Some(dispatch) => from_dispatch(dispatch, depth)?,
+                None => Variant::Null,
+            },
+            vt if vt == VARENUM(VT_BYREF.0 | VT_VARIANT.0) => match data.pvarVal.as_ref() {
+                Some(variant) => convert(variant, depth + 1)?,
+                None => Variant::Null,
+            },
+            _ => return Err(DISP_E_TYPEMISMATCH.into()),
+        })
+    }
+
+    unsafe fn safe_array(array: *const SAFEARRAY, depth: usize) -> Result<Variant> {
+        if array.is_null() {
+            return Ok(Variant::Null);
+        }
+        if SafeArrayGetDim(array) != 1 {
+            return Err(DISP_E_TYPEMISMATCH.into());
+        }
+
+        let lower = SafeArrayGetLBound(array, 1)?;
+        let upper = SafeArrayGetUBound(array, 1)?;
+        let mut items = Vec::new();
+        for index in lower..=upper {
+            let mut element = VARIANT::default();
+            SafeArrayGetElement(array, &index, &mut element as *mut _ as *mut c_void)?;
+            let item = convert(&element, depth + 1);
+            let _ = VariantClear(&mut element);
+            items.push(item?);
+        }
+        Ok(Variant::Array(items))
+    }
+
+    unsafe fn from_dispatch(dispatch: &IDispatch, depth: usize) -> Result<Variant> {
+        let names = dispatch
+            .cast::<IDispatchEx>()
+            .ok()
+            .map(|dispatch_ex| member_names(&dispatch_ex))
+            .transpose()?;
+
+        // Script arrays expose their items as index properties, but `length` is not enumerable.
+        let length = match get_id(dispatch, "length") {
+            Some(id) => match convert_property(dispatch, id, depth)? {
+                Variant::Int(length) => usize::try_from(length).ok(),
+                Variant::Float(length) if length.fract() == 0.0 && length >= 0.0 => {
+                    Some(length as usize)
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        let is_array = length.is_some()
+            && names.as_ref().map_or(true, |names| {
+                names.iter().all(|(name, _)| {
+                    name.parse::<usize>()
+                        .is_ok_and(|index| Some(index) < length)
+                })
+            });
+
+        match (length, names) {
+            (Some(length), _) if is_array => {
+                let mut items = Vec::with_capacity(length);
+                for index in 0..length {
+                    items.push(match get_id(dispatch, &index.to_string()) {
+                        Some(id) => convert_property(dispatch, id, depth)?,
+                        None => Variant::Empty,
+                    });
+                }
+                Ok(Variant::Array(items))
+            }
+            (_, Some(names)) => {
+                let mut properties = Vec::with_capacity(names.len());
+                for (name, id) in names {
+                    match convert_property(dispatch, id, depth)? {
+                        // Properties which are `undefined` are skipped, the same as `JSON.stringify`.
+                        Variant::Empty => {}
+                        value => properties.push((name, value)),
+                    }
+                }
+                Ok(Variant::Object(properties))
+            }
+            _ => Err(DISP_E_TYPEMISMATCH.into()),
+        }
+    }
+
+    unsafe fn member_names(dispatch_ex: &IDispatchEx) -> Result<Vec<(String, i32)>> {
+        let mut names = Vec::new();
+        let mut id = DISPID_STARTENUM;
+        loop {
+            let mut next = DISPID_UNKNOWN;
+            let hr = (dispatch_ex.vtable().GetNextDispID)(
+                dispatch_ex.as_raw(),
+                fdexEnumAll as _,
+                id,
+                &mut next,
+            );
+            if hr != S_OK {
+                break;
+            }
+            id = next;
+            let name = dispatch_ex.GetMemberName(id)?;
+            let Ok(name) = String::from_utf16(name.as_wide()) else {
+                return Err(E_INVALIDARG.into());
+            };
+            names.push((name, id));
+        }
+        Ok(names)
+    }
+
+    unsafe fn get_id(dispatch: &IDispatch, name: &str) -> Option<i32> {
+        let name = HSTRING::from(name);
+        let name = PCWSTR(name.as_ptr());
+        let mut id = DISPID_UNKNOWN;
+        dispatch
+            .GetIDsOfNames(&GUID::zeroed(), &name, 1, 0, &mut id)
+            .ok()
+            .map(|()| id)
+    }
+
+    unsafe fn convert_property(dispatch: &IDispatch, id: i32, depth: usize) -> Result<Variant> {
+        let params = DISPPARAMS::default();
+        let mut result = VARIANT::default();
+        dispatch.Invoke(
+            id,
+            &GUID::zeroed(),
+            0,
+            DISPATCH_PROPERTYGET,
+            &params,
+            Some(&mut result),
+            None,
+            None,
+        )?;
+
+        let value = convert(&result, depth + 1);
+        let _ = VariantClear(&mut result);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn formats_dates() {
+        for (date, expected) in [
+            (0.0, Some("1899-12-30T00:00:00.000")),
+            (1.0, Some("1899-12-31T00:00:00.000")),
+            (2.0, Some("1900-01-01T00:00:00.000")),
+            (-1.0, Some("1899-12-29T00:00:00.000")),
+            (-1.25, Some("1899-12-29T06:00:00.000")),
+            (-36_522.5, Some("1800-01-01T12:00:00.000")),
+            (10_000.0, Some("1927-05-18T00:00:00.000")),
+            (25_569.0, Some("1970-01-01T00:00:00.000")),
+            (25_569.25, Some("1970-01-01T06:00:00.000")),
+            (36_585.0, Some("2000-02-29T00:00:00.000")),
+            (45_000.75, Some("2023-03-15T18:00:00.000")),
+            (45_000.5 + 1.5 / 86_400.0, Some("2023-03-15T12:00:01.500")),
+            (1.999_999_999_9, Some("1900-01-01T00:00:00.000")),
+            (2_958_465.999_988_426, Some("9999-12-31T23:59:59.000")),
+            (f64::NAN, None),
+            (f64::INFINITY, None),
+            (f64::NEG_INFINITY, None),
+        ] {
+            assert_eq!(format_date(date).as_deref(), expected, "{date}");
+        }
+    }
+
+    #[test]
+    fn converts_days_to_civil_dates() {
+        for (days, expected) in [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (59, (1970, 3, 1)),
+            (11_016, (2000, 2, 29)),
+            (-25_569, (1899, 12, 30)),
+            (-135_080, (1600, 3, 1)),
+        ] {
+            assert_eq!(civil_from_days(days), expected, "{days}");
+        }
+    }
+
+    #[test]
+    fn converts_variants_to_json() {
+        for (variant, expected) in [
+            (Variant::Empty, json!(null)),
+            (Variant::Null, json!(null)),
+            (Variant::Bool(true), json!(true)),
+            (Variant::Int(-42), json!(-42)),
+            (Variant::Int(i64::MAX), json!(i64::MAX)),
+            (Variant::Float(1.5), json!(1.5)),
+            (Variant::Float(f64::NAN), json!(null)),
+            (Variant::Float(f64::INFINITY), json!(null)),
+            (Variant::Date(25_569.5), json!("1970-01-01T12:00:00.000")),
+            (Variant::Date(f64::NAN), json!(null)),
+            (Variant::String(String::from("text")), json!("text")),
+            (Variant::Array(Vec::new()), json!([])),
+            (
+                Variant::Array(vec![Variant::Int(1), Variant::Null, Variant::Empty]),
+                json!([1, null, null]),
+            ),
+            (
+                Variant::Object(vec![
+                    (String::from("id"), Variant::Int(7)),
+                    (
+                        String::from("item"),
+                        Variant::Object(vec![
+                            (String::from("received"), Variant::Date(0.0)),
+                            (
+                                String::from("tags"),
+                                Variant::Array(vec![Variant::String(String::from("a"))]),
+                            ),
+                            (String::from("flagged"), Variant::Null),
+                        ]),
+                    ),
+                ]),
+                json!({
+                    "id": 7,
+                    "item": {
+                        "received": "1899-12-30T00:00:00.000",
+                        "tags": ["a"],
+                        "flagged": null
+                    }
+                }),
+            ),
+        ] {
+            assert_eq!(Value::from(&variant), expected, "{variant:?}");
+        }
+    }
+
+    #[test]
+    fn converts_json_to_variants() {
+        for (value, expected) in [
+            (json!(null), Variant::Null),
+            (json!(false), Variant::Bool(false)),
+            (json!(-42), Variant::Int(-42)),
+            (json!(0.25), Variant::Float(0.25)),
+            (
+                json!("1970-01-01"),
+                Variant::String(String::from("1970-01-01")),
+            ),
+            (
+                json!([1, [null]]),
+                Variant::Array(vec![Variant::Int(1), Variant::Array(vec![Variant::Null])]),
+            ),
+            (
+                json!({ "a": { "b": [true] } }),
+                Variant::Object(vec![(
+                    String::from("a"),
+                    Variant::Object(vec![(
+                        String::from("b"),
+                        Variant::Array(vec![Variant::Bool(true)]),
+                    )]),
+                )]),
+            ),
+        ] {
+            assert_eq!(Variant::from(&value), expected, "{value}");
+            assert_eq!(Value::from(&expected), value, "{value}");
+        }
+
+        // Integers which don't fit in an `i64` lose precision.
+        assert_eq!(
+            Variant::from(&json!(u64::MAX)),
+            Variant::Float(u64::MAX as f64)
+        );
+    }
+}